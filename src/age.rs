@@ -0,0 +1,607 @@
+use cryptolib::curve25519::{curve25519, curve25519_base};
+use cryptolib::scrypt::{scrypt, ScryptParams};
+use cryptolib::util::fixed_time_eq;
+use rustc_serialize::base64::{CharacterSet, Config, FromBase64, Newline, ToBase64, STANDARD};
+use std::fmt;
+use std::str;
+
+use container::Error;
+use crypto;
+use zeroize::{Zeroize, Zeroizing};
+
+// age v1, see https://age-encryption.org/v1
+pub const MAGIC: &[u8] = b"age-encryption.org/v1\n";
+const ARMOR_BEGIN: &str = "-----BEGIN AGE ENCRYPTED FILE-----";
+const ARMOR_END: &str = "-----END AGE ENCRYPTED FILE-----";
+
+const RECIPIENT_HRP: &str = "age";
+const IDENTITY_HRP: &str = "age-secret-key-";
+
+const FILE_KEY_LEN: usize = 16;
+const NONCE_LEN: usize = 16;
+const CHUNK_LEN: usize = 64 * 1024;
+const COLUMNS: usize = 64;
+
+const X25519_LABEL: &[u8] = b"age-encryption.org/v1/X25519";
+const SCRYPT_LABEL: &[u8] = b"age-encryption.org/v1/scrypt";
+
+// scrypt work factor (log2 N) used when encrypting and the largest one we
+// are willing to spend time on when decrypting
+const WORK_FACTOR: u8 = 18;
+const MAX_WORK_FACTOR: u8 = 22;
+
+// unpadded standard base64, used everywhere in the header
+const BASE64: Config = Config {
+    char_set: CharacterSet::Standard,
+    newline: Newline::LF,
+    pad: false,
+    line_length: None,
+};
+
+pub struct Recipient([u8; 32]);
+
+pub struct Identity([u8; 32]);
+
+struct Stanza {
+    kind: String,
+    args: Vec<String>,
+    body: Vec<u8>,
+}
+
+pub struct Encrypted {
+    stanzas: Vec<Stanza>,
+    // header bytes covered by the MAC
+    header: Vec<u8>,
+    mac: Vec<u8>,
+    payload: Vec<u8>,
+}
+
+pub fn is_age(data: &[u8]) -> bool {
+    data.starts_with(MAGIC) || data.starts_with(ARMOR_BEGIN.as_bytes())
+}
+
+pub fn encrypt(
+    passphrase: Option<&str>,
+    recipients: &[Recipient],
+    plaintext: &[u8],
+) -> Result<Vec<u8>, Error> {
+    encrypt_with_work_factor(passphrase, WORK_FACTOR, recipients, plaintext)
+}
+
+fn encrypt_with_work_factor(
+    passphrase: Option<&str>,
+    work_factor: u8,
+    recipients: &[Recipient],
+    plaintext: &[u8],
+) -> Result<Vec<u8>, Error> {
+    // a scrypt stanza has to be the only one in the file
+    let stanzas = match (passphrase, recipients.len()) {
+        (Some(passphrase), 0) => {
+            let mut salt = [0; 16];
+            crypto::random_bytes(&mut salt);
+            let key = scrypt_key(passphrase, &salt, work_factor);
+            vec![(
+                "scrypt",
+                vec![salt.to_base64(BASE64), work_factor.to_string()],
+                key,
+            )]
+        }
+        (None, n) if n > 0 => recipients.iter().map(Recipient::wrap).collect(),
+        _ => return Err(Error::NoMatchingKey),
+    };
+
+    let mut file_key = Zeroizing::new([0; FILE_KEY_LEN]);
+    crypto::random_bytes(&mut *file_key);
+
+    let mut out = MAGIC.to_vec();
+    for (kind, args, key) in stanzas {
+        out.extend_from_slice(b"-> ");
+        out.extend_from_slice(kind.as_bytes());
+        for arg in args {
+            out.push(b' ');
+            out.extend_from_slice(arg.as_bytes());
+        }
+        out.push(b'\n');
+        let body = crypto::seal(&*key, &[0; 12], &[], &*file_key).to_base64(BASE64);
+        // the last line is always shorter than a full one, even if empty
+        let mut rest = body.as_str();
+        loop {
+            let n = rest.len().min(COLUMNS);
+            out.extend_from_slice(&rest.as_bytes()[..n]);
+            out.push(b'\n');
+            rest = &rest[n..];
+            if n < COLUMNS {
+                break;
+            }
+        }
+    }
+    out.extend_from_slice(b"---");
+    let mac = crypto::hmac_sha256(&*header_key(&*file_key), &[&out]);
+    out.push(b' ');
+    out.extend_from_slice(mac.to_base64(BASE64).as_bytes());
+    out.push(b'\n');
+
+    let mut nonce = [0; NONCE_LEN];
+    crypto::random_bytes(&mut nonce);
+    out.extend_from_slice(&nonce);
+    let key = payload_key(&*file_key, &nonce);
+    let chunks = ((plaintext.len() + CHUNK_LEN - 1) / CHUNK_LEN).max(1);
+    for i in 0..chunks {
+        let chunk = &plaintext[i * CHUNK_LEN..plaintext.len().min((i + 1) * CHUNK_LEN)];
+        let nonce = stream_nonce(i as u64, i == chunks - 1);
+        out.extend_from_slice(&crypto::seal(&*key, &nonce, &[], chunk));
+    }
+    Ok(out)
+}
+
+fn scrypt_key(passphrase: &str, salt: &[u8], work_factor: u8) -> Zeroizing<[u8; 32]> {
+    let mut salt_label = SCRYPT_LABEL.to_vec();
+    salt_label.extend_from_slice(salt);
+    let mut key = Zeroizing::new([0; 32]);
+    scrypt(
+        passphrase.as_bytes(),
+        &salt_label,
+        &ScryptParams::new(work_factor, 8, 1),
+        &mut *key,
+    );
+    key
+}
+
+fn x25519_key(shared: &[u8], ephemeral_public: &[u8], recipient: &[u8]) -> Zeroizing<[u8; 32]> {
+    let mut salt = ephemeral_public.to_vec();
+    salt.extend_from_slice(recipient);
+    let mut key = Zeroizing::new([0; 32]);
+    crypto::hkdf_sha256(&salt, shared, X25519_LABEL, &mut *key);
+    key
+}
+
+fn header_key(file_key: &[u8]) -> Zeroizing<[u8; 32]> {
+    let mut key = Zeroizing::new([0; 32]);
+    crypto::hkdf_sha256(&[], file_key, b"header", &mut *key);
+    key
+}
+
+fn payload_key(file_key: &[u8], nonce: &[u8]) -> Zeroizing<[u8; 32]> {
+    let mut key = Zeroizing::new([0; 32]);
+    crypto::hkdf_sha256(nonce, file_key, b"payload", &mut *key);
+    key
+}
+
+// STREAM nonce: 11 byte big endian chunk counter and a final chunk flag
+fn stream_nonce(counter: u64, last: bool) -> [u8; 12] {
+    let mut nonce = [0; 12];
+    nonce[3..11].copy_from_slice(&counter.to_be_bytes());
+    nonce[11] = last as u8;
+    nonce
+}
+
+// Only canonical encodings are accepted so that a header has exactly one
+// byte representation for the MAC.
+fn decode(s: &str) -> Option<Vec<u8>> {
+    if !s
+        .bytes()
+        .all(|b| b.is_ascii_alphanumeric() || b == b'+' || b == b'/')
+    {
+        return None;
+    }
+    let bytes = s.from_base64().ok()?;
+    if bytes.to_base64(BASE64) != s {
+        return None;
+    }
+    Some(bytes)
+}
+
+fn next_line<'a>(data: &'a [u8], pos: &mut usize) -> Result<&'a str, Error> {
+    let len = data[*pos..]
+        .iter()
+        .position(|&b| b == b'\n')
+        .ok_or(Error::Format)?;
+    let line = str::from_utf8(&data[*pos..*pos + len]).map_err(|_| Error::Format)?;
+    *pos += len + 1;
+    Ok(line)
+}
+
+fn dearmor(data: &[u8]) -> Result<Vec<u8>, Error> {
+    let text = str::from_utf8(data).map_err(|_| Error::Format)?;
+    let mut lines = text.trim().lines().map(str::trim_end);
+    if lines.next() != Some(ARMOR_BEGIN) {
+        return Err(Error::Format);
+    }
+    let mut encoded = String::new();
+    for line in lines {
+        if line == ARMOR_END {
+            return encoded.from_base64().map_err(|_| Error::Format);
+        }
+        encoded.push_str(line);
+    }
+    Err(Error::Format)
+}
+
+impl Encrypted {
+    pub fn parse(data: &[u8]) -> Result<Encrypted, Error> {
+        let data = if data.starts_with(ARMOR_BEGIN.as_bytes()) {
+            dearmor(data)?
+        } else {
+            data.to_vec()
+        };
+        if !data.starts_with(MAGIC) {
+            return Err(Error::Format);
+        }
+
+        let mut stanzas = Vec::new();
+        let mut pos = MAGIC.len();
+        loop {
+            let start = pos;
+            let line = next_line(&data, &mut pos)?;
+            if let Some(stanza) = line.strip_prefix("-> ") {
+                let mut parts = stanza.split(' ').map(str::to_string);
+                let kind = parts.next().ok_or(Error::Format)?;
+                let args: Vec<String> = parts.collect();
+                if kind.is_empty() || args.iter().any(String::is_empty) {
+                    return Err(Error::Format);
+                }
+                let mut body = String::new();
+                loop {
+                    let line = next_line(&data, &mut pos)?;
+                    if line.len() > COLUMNS {
+                        return Err(Error::Format);
+                    }
+                    body.push_str(line);
+                    if line.len() < COLUMNS {
+                        break;
+                    }
+                }
+                stanzas.push(Stanza {
+                    kind,
+                    args,
+                    body: decode(&body).ok_or(Error::Format)?,
+                });
+            } else if let Some(mac) = line.strip_prefix("--- ") {
+                let mac = decode(mac).ok_or(Error::Format)?;
+                if stanzas.is_empty() || mac.len() != 32 {
+                    return Err(Error::Format);
+                }
+                return Ok(Encrypted {
+                    stanzas,
+                    header: data[..start + 3].to_vec(),
+                    mac,
+                    payload: data[pos..].to_vec(),
+                });
+            } else {
+                return Err(Error::Format);
+            }
+        }
+    }
+
+    pub fn has_passphrase(&self) -> bool {
+        self.stanzas.iter().any(|stanza| stanza.kind == "scrypt")
+    }
+
+    pub fn decrypt_with_passphrase(&self, passphrase: &str) -> Result<Vec<u8>, Error> {
+        let stanza = match self.stanzas[..] {
+            [ref stanza] if stanza.kind == "scrypt" => stanza,
+            _ => return Err(Error::NoMatchingKey),
+        };
+        let (salt, work_factor) = match stanza.args[..] {
+            [ref salt, ref work_factor] => (salt, work_factor),
+            _ => return Err(Error::Format),
+        };
+        let salt = decode(salt).ok_or(Error::Format)?;
+        let work_factor = work_factor.parse::<u8>().map_err(|_| Error::Format)?;
+        if salt.len() != 16 || stanza.body.len() != 32 || work_factor.to_string() != stanza.args[1]
+        {
+            return Err(Error::Format);
+        }
+        if work_factor == 0 || work_factor > MAX_WORK_FACTOR {
+            return Err(Error::Format);
+        }
+        let key = scrypt_key(passphrase, &salt, work_factor);
+        let file_key =
+            crypto::open(&*key, &[0; 12], &[], &stanza.body).ok_or(Error::NoMatchingKey)?;
+        self.decrypt_payload(&file_key)
+    }
+
+    pub fn decrypt_with_identities(&self, identities: &[Identity]) -> Result<Vec<u8>, Error> {
+        if self.has_passphrase() {
+            return Err(Error::NoMatchingKey);
+        }
+        for stanza in self.stanzas.iter().filter(|stanza| stanza.kind == "X25519") {
+            let ephemeral_public = match stanza.args[..] {
+                [ref arg] => decode(arg).ok_or(Error::Format)?,
+                _ => return Err(Error::Format),
+            };
+            if ephemeral_public.len() != 32 || stanza.body.len() != 32 {
+                return Err(Error::Format);
+            }
+            for identity in identities {
+                if let Some(file_key) = identity.unwrap(&ephemeral_public, &stanza.body) {
+                    return self.decrypt_payload(&file_key);
+                }
+            }
+        }
+        Err(Error::NoMatchingKey)
+    }
+
+    fn decrypt_payload(&self, file_key: &[u8]) -> Result<Vec<u8>, Error> {
+        let mac = crypto::hmac_sha256(&*header_key(file_key), &[&self.header]);
+        if !fixed_time_eq(&mac, &self.mac) {
+            return Err(Error::Integrity);
+        }
+        if self.payload.len() < NONCE_LEN + crypto::TAG_LEN {
+            return Err(Error::Format);
+        }
+        let (nonce, ciphertext) = self.payload.split_at(NONCE_LEN);
+        let key = payload_key(file_key, nonce);
+        let sealed_len = CHUNK_LEN + crypto::TAG_LEN;
+        let chunks = (ciphertext.len() + sealed_len - 1) / sealed_len;
+        let mut plaintext = Vec::with_capacity(ciphertext.len());
+        for (i, chunk) in ciphertext.chunks(sealed_len).enumerate() {
+            let last = i == chunks - 1;
+            let opened = crypto::open(&*key, &stream_nonce(i as u64, last), &[], chunk)
+                .ok_or(Error::Integrity)?;
+            // only an empty file may end in an empty chunk
+            if last && opened.is_empty() && i > 0 {
+                return Err(Error::Format);
+            }
+            plaintext.extend_from_slice(&opened);
+        }
+        Ok(plaintext)
+    }
+}
+
+impl Recipient {
+    pub fn parse(s: &str) -> Option<Recipient> {
+        let (hrp, data) = bech32_decode(s)?;
+        if hrp != RECIPIENT_HRP || data.len() != 32 || s.chars().any(|c| c.is_uppercase()) {
+            return None;
+        }
+        let mut public = [0; 32];
+        public.copy_from_slice(&data);
+        Some(Recipient(public))
+    }
+
+    // Returns the stanza type, its arguments and the key wrapping the file key.
+    fn wrap(&self) -> (&'static str, Vec<String>, Zeroizing<[u8; 32]>) {
+        let mut ephemeral = Zeroizing::new([0; 32]);
+        crypto::random_bytes(&mut *ephemeral);
+        let ephemeral_public = curve25519_base(&*ephemeral);
+        let shared = Zeroizing::new(curve25519(&*ephemeral, &self.0));
+        let key = x25519_key(&*shared, &ephemeral_public, &self.0);
+        ("X25519", vec![ephemeral_public.to_base64(BASE64)], key)
+    }
+}
+
+impl fmt::Display for Recipient {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", bech32_encode(RECIPIENT_HRP, &self.0))
+    }
+}
+
+impl Identity {
+    pub fn generate() -> Identity {
+        let mut secret = [0; 32];
+        crypto::random_bytes(&mut secret);
+        Identity(secret)
+    }
+
+    pub fn parse(s: &str) -> Option<Identity> {
+        let (hrp, data) = bech32_decode(s)?;
+        if hrp != IDENTITY_HRP || data.len() != 32 || s.chars().any(|c| c.is_lowercase()) {
+            return None;
+        }
+        let mut secret = [0; 32];
+        secret.copy_from_slice(&data);
+        Some(Identity(secret))
+    }
+
+    // Accepts the contents of an age identity file, one identity per line
+    // next to blank lines and `#` comments.
+    pub fn parse_list(s: &str) -> Option<Vec<Identity>> {
+        let identities = s
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(Identity::parse)
+            .collect::<Option<Vec<_>>>()?;
+        if identities.is_empty() {
+            return None;
+        }
+        Some(identities)
+    }
+
+    pub fn recipient(&self) -> Recipient {
+        Recipient(curve25519_base(&self.0))
+    }
+
+    fn unwrap(&self, ephemeral_public: &[u8], body: &[u8]) -> Option<Zeroizing<Vec<u8>>> {
+        let shared = Zeroizing::new(curve25519(&self.0, ephemeral_public));
+        if shared.iter().all(|&b| b == 0) {
+            return None;
+        }
+        let key = x25519_key(&*shared, ephemeral_public, &self.recipient().0);
+        crypto::open(&*key, &[0; 12], &[], body)
+    }
+}
+
+impl fmt::Display for Identity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "# public key: {}", self.recipient())?;
+        write!(f, "{}", bech32_encode(IDENTITY_HRP, &self.0).to_uppercase())
+    }
+}
+
+impl Drop for Identity {
+    fn drop(&mut self) {
+        self.0.zeroize();
+    }
+}
+
+// Armored output, for places that only take text.
+pub fn armor(data: &[u8]) -> String {
+    let mut config = STANDARD;
+    config.newline = Newline::LF;
+    config.line_length = Some(COLUMNS);
+    format!(
+        "{}\n{}\n{}\n",
+        ARMOR_BEGIN,
+        data.to_base64(config),
+        ARMOR_END
+    )
+}
+
+const BECH32_CHARSET: &[u8] = b"qpzry9x8gf2tvdw0s3jn54khce6mua7l";
+
+fn bech32_polymod(values: &[u8]) -> u32 {
+    const GENERATOR: [u32; 5] = [0x3b6a57b2, 0x26508e6d, 0x1ea119fa, 0x3d4233dd, 0x2a1462b3];
+    let mut checksum = 1u32;
+    for &value in values {
+        let top = checksum >> 25;
+        checksum = (checksum & 0x1ffffff) << 5 ^ u32::from(value);
+        for (i, generator) in GENERATOR.iter().enumerate() {
+            if (top >> i) & 1 == 1 {
+                checksum ^= generator;
+            }
+        }
+    }
+    checksum
+}
+
+fn bech32_hrp_expand(hrp: &str) -> Vec<u8> {
+    let mut expanded: Vec<u8> = hrp.bytes().map(|b| b >> 5).collect();
+    expanded.push(0);
+    expanded.extend(hrp.bytes().map(|b| b & 31));
+    expanded
+}
+
+// Regroups bits, padding the last group when encoding.
+fn convert_bits(data: &[u8], from: u32, to: u32, pad: bool) -> Option<Vec<u8>> {
+    let mut acc = 0u32;
+    let mut bits = 0;
+    let mut out = Vec::new();
+    for &value in data {
+        acc = acc << from | u32::from(value);
+        bits += from;
+        while bits >= to {
+            bits -= to;
+            out.push((acc >> bits & ((1 << to) - 1)) as u8);
+        }
+    }
+    if pad {
+        if bits > 0 {
+            out.push((acc << (to - bits) & ((1 << to) - 1)) as u8);
+        }
+    } else if bits >= from || acc & ((1 << bits) - 1) != 0 {
+        return None;
+    }
+    Some(out)
+}
+
+fn bech32_encode(hrp: &str, data: &[u8]) -> String {
+    let data = convert_bits(data, 8, 5, true).unwrap_or_default();
+    let mut values = bech32_hrp_expand(hrp);
+    values.extend_from_slice(&data);
+    values.extend_from_slice(&[0; 6]);
+    let checksum = bech32_polymod(&values) ^ 1;
+    let mut s = format!("{}1", hrp);
+    for value in data {
+        s.push(BECH32_CHARSET[value as usize] as char);
+    }
+    for i in 0..6 {
+        s.push(BECH32_CHARSET[(checksum >> (5 * (5 - i)) & 31) as usize] as char);
+    }
+    s
+}
+
+fn bech32_decode(s: &str) -> Option<(String, Vec<u8>)> {
+    if s.chars().any(|c| c.is_lowercase()) && s.chars().any(|c| c.is_uppercase()) {
+        return None;
+    }
+    let s = s.to_lowercase();
+    let split = s.rfind('1')?;
+    let (hrp, data) = (&s[..split], &s[split + 1..]);
+    if hrp.is_empty() || data.len() < 6 {
+        return None;
+    }
+    let data = data
+        .bytes()
+        .map(|b| BECH32_CHARSET.iter().position(|&c| c == b).map(|p| p as u8))
+        .collect::<Option<Vec<_>>>()?;
+    let mut values = bech32_hrp_expand(hrp);
+    values.extend_from_slice(&data);
+    if bech32_polymod(&values) != 1 {
+        return None;
+    }
+    let data = convert_bits(&data[..data.len() - 6], 5, 8, false)?;
+    Some((hrp.to_string(), data))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // written by the reference implementation
+    const IDENTITY: &str =
+        "AGE-SECRET-KEY-1DZXPCECW9NQKV8UCJD0C5ZQ6WKWHLNYYQZPVT303PL5S4NFR40PSCJ7DZM";
+    const RECIPIENT: &str = "age17kkqjulluk2t46hl073kk20ma9rfg7069gq6lnp9cm5sp2phk9dqdtrk86";
+    const SCRYPT_FILE: &str = "YWdlLWVuY3J5cHRpb24ub3JnL3YxCi0+IHNjcnlwdCBxaDdZR2JzbzJXT0hsN2cwRnMyQWhBIDEwCk13cUw4K0dZUEtUNUVUNWJubWhKYzNTRHFVTklaQ0Y0YmZ2SXlReWFtejAKLS0tIE53T2w3WGJQZjZGTXp3eWpkMVpCclZmc29hZ0hVZmw2a1p2RmFjczh4a2MKIKVQBG003FVEGGvsTWo3uY/tYEmZptYmBjAZbaxsHFsYQ2hRTdyvN6Lv";
+    const X25519_FILE: &str = "YWdlLWVuY3J5cHRpb24ub3JnL3YxCi0+IFgyNTUxOSBHdHZJbExpM3lxaTBaYlp3VWlRK0ZIN2hTS1RaL256N0lhZ2Q3ZklvbHk4Cm5KYTRpN3dLZ3locDVhRkdSTDNXU1VYbjAzQkROWWVER0FoR0ZTTU1lTHMKLT4gST8mRS1ncmVhc2UKZDBLSjEwSHExSllnVlBBUlRMdEQ1bjdwdzE3Z2NLT3NJZUZIeGdWMzdhdy8reTlETGZoNTNYbFBVdUNUS0c4UApiY0swWnVlSjUyNVBtSnJQNUNpVW5tejlCQjN1alNSS2RlTml4V01taGh5bzVzaEoyTG9KQzNETnlhNnJNQQotLS0gZGZjbWc0RXZZYXRWK1R5MktjUzM3OWlwWjR0OXBoYlFub1c3Y2swK3JtawoFjxgse239WwehtIgmhFVxEoA7q1GgJepKbqLwpJ0yfSDM2x4y1rvGSDA=";
+
+    #[test]
+    fn test_reference_files() {
+        let identity = Identity::parse(IDENTITY).unwrap();
+        assert_eq!(identity.recipient().to_string(), RECIPIENT);
+        assert_eq!(Recipient::parse(RECIPIENT).unwrap().to_string(), RECIPIENT);
+
+        let file = Encrypted::parse(&SCRYPT_FILE.from_base64().unwrap()).unwrap();
+        assert!(file.has_passphrase());
+        assert_eq!(
+            file.decrypt_with_passphrase("vault").unwrap(),
+            b"hello age\n"
+        );
+        assert_eq!(
+            file.decrypt_with_passphrase("wrong").err(),
+            Some(Error::NoMatchingKey)
+        );
+
+        let file = Encrypted::parse(&X25519_FILE.from_base64().unwrap()).unwrap();
+        assert!(!file.has_passphrase());
+        assert_eq!(
+            file.decrypt_with_identities(&[Identity::generate(), identity])
+                .unwrap(),
+            b"hello age\n"
+        );
+        assert_eq!(
+            file.decrypt_with_identities(&[Identity::generate()]).err(),
+            Some(Error::NoMatchingKey)
+        );
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let plaintext: Vec<u8> = (0..150_000).map(|i| i as u8).collect();
+        let data = encrypt_with_work_factor(Some("vault"), 10, &[], &plaintext).unwrap();
+        let file = Encrypted::parse(&data).unwrap();
+        assert_eq!(file.decrypt_with_passphrase("vault").unwrap(), plaintext);
+
+        let identities = [Identity::generate(), Identity::generate()];
+        let recipients = [identities[0].recipient(), identities[1].recipient()];
+        let data = encrypt(None, &recipients, b"").unwrap();
+        let armored = armor(&data);
+        assert!(is_age(armored.as_bytes()));
+        let file = Encrypted::parse(armored.as_bytes()).unwrap();
+        assert_eq!(file.decrypt_with_identities(&identities[1..]).unwrap(), b"");
+
+        let mut data = encrypt(None, &recipients, b"hello").unwrap();
+        let last = data.len() - 1;
+        data[last] ^= 1;
+        let file = Encrypted::parse(&data).unwrap();
+        assert_eq!(
+            file.decrypt_with_identities(&identities).err(),
+            Some(Error::Integrity)
+        );
+
+        assert!(encrypt(Some("vault"), &recipients, b"").is_err());
+        assert!(Recipient::parse(&RECIPIENT.replace('6', "7")).is_none());
+        assert!(Identity::parse(&IDENTITY.to_lowercase()).is_none());
+    }
+}
@@ -0,0 +1,122 @@
+use libc;
+use std::fs;
+use std::fs::{DirBuilder, Permissions};
+use std::io;
+use std::io::prelude::*;
+use std::os::unix::fs::{DirBuilderExt, MetadataExt, PermissionsExt};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::thread;
+use std::time::Duration;
+use zeroize::Zeroizing;
+
+// The agent keeps a password in memory for commands that run without a
+// terminal to ask on, such as the git filters. It hands the password to
+// anyone who connects to its socket, which sits in a directory only the user
+// can enter, and then closes the connection. A directory someone else made
+// or can write to is refused on both ends, since its socket could be theirs.
+
+pub fn serve(socket: &Path, password: Zeroizing<String>, ttl: Option<Duration>) -> io::Result<()> {
+    if let Some(dir) = socket.parent() {
+        DirBuilder::new().recursive(true).mode(0o700).create(dir)?;
+        check_dir(dir)?;
+    }
+    // a socket left behind by an agent that is gone
+    if UnixStream::connect(socket).is_err() {
+        let _ = fs::remove_file(socket);
+    }
+    let listener = UnixListener::bind(socket)?;
+    fs::set_permissions(socket, Permissions::from_mode(0o600))?;
+    if let Some(ttl) = ttl {
+        let socket = socket.to_path_buf();
+        thread::spawn(move || expire(socket, ttl));
+    }
+    for stream in listener.incoming() {
+        // a client that goes away early doesn't stop the agent
+        let _ = stream.and_then(|mut stream| stream.write_all(password.as_bytes()));
+    }
+    Ok(())
+}
+
+fn expire(socket: PathBuf, ttl: Duration) {
+    thread::sleep(ttl);
+    let _ = fs::remove_file(socket);
+    process::exit(0);
+}
+
+// The password held by the agent listening on `socket`, if there is one.
+pub fn fetch(socket: &Path) -> Option<Zeroizing<String>> {
+    check_dir(socket.parent()?).ok()?;
+    let mut stream = UnixStream::connect(socket).ok()?;
+    let mut password = Zeroizing::new(String::new());
+    stream.read_to_string(&mut password).ok()?;
+    Some(password)
+}
+
+fn check_dir(dir: &Path) -> io::Result<()> {
+    let metadata = fs::symlink_metadata(dir)?;
+    if !metadata.is_dir()
+        || metadata.uid() != unsafe { libc::geteuid() }
+        || metadata.mode() & 0o777 != 0o700
+    {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!(
+                "{} is not a directory only the user can enter",
+                dir.display()
+            ),
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    #[test]
+    fn test_serve_fetch() {
+        let dir = env::temp_dir().join(format!("vault-agent-{}", process::id()));
+        let socket = dir.join("agent.sock");
+        assert!(fetch(&socket).is_none());
+
+        let served = socket.clone();
+        thread::spawn(move || serve(&served, Zeroizing::new("hunter2".to_string()), None));
+        let mut password = None;
+        for _ in 0..100 {
+            password = fetch(&socket);
+            if password.is_some() {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(password.unwrap().as_str(), "hunter2");
+        assert_eq!(fetch(&socket).unwrap().as_str(), "hunter2");
+        let mode = fs::metadata(&dir).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o700);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_shared_dir() {
+        let dir = env::temp_dir().join(format!("vault-agent-shared-{}", process::id()));
+        DirBuilder::new().mode(0o755).create(&dir).unwrap();
+        fs::set_permissions(&dir, Permissions::from_mode(0o777)).unwrap();
+        let socket = dir.join("agent.sock");
+        let listener = UnixListener::bind(&socket).unwrap();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let _ = stream.and_then(|mut stream| stream.write_all(b"chosen"));
+            }
+        });
+        assert!(fetch(&socket).is_none());
+        let served = serve(&socket, Zeroizing::new("hunter2".to_string()), None);
+        assert_eq!(
+            served.err().map(|err| err.kind()),
+            Some(io::ErrorKind::PermissionDenied)
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
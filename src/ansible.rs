@@ -0,0 +1,224 @@
+use cryptolib::aes;
+use cryptolib::symmetriccipher::SynchronousStreamCipher;
+use cryptolib::util::fixed_time_eq;
+use rustc_serialize::hex::{FromHex, ToHex};
+use std::str;
+
+use container::Error;
+use crypto;
+use state::hash::Hash;
+use zeroize::Zeroizing;
+
+// Ansible Vault files, as written by `ansible-vault encrypt`: a header line
+// and the hex encoded body wrapped at 80 columns. Once decoded the body holds
+// three hex lines, the salt, an HMAC-SHA256 of the ciphertext and the
+// ciphertext, which is AES-256-CTR over PKCS#7 padded plaintext.
+pub const MAGIC: &[u8] = b"$ANSIBLE_VAULT";
+const HEADER: &str = "$ANSIBLE_VAULT;1.1;AES256";
+
+const ITER: u32 = 10000;
+const SALT_LEN: usize = 32;
+const KEY_LEN: usize = 32;
+const IV_LEN: usize = 16;
+const BLOCK_LEN: usize = 16;
+const COLUMNS: usize = 80;
+
+// The layout of an opened file, so that saving it changes nothing but the
+// body.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Envelope {
+    header: String,
+    // None keeps the body on a single line
+    width: Option<usize>,
+    newline: &'static str,
+    trailing_newline: bool,
+}
+
+impl Default for Envelope {
+    fn default() -> Self {
+        Envelope {
+            header: HEADER.to_string(),
+            width: Some(COLUMNS),
+            newline: "\n",
+            trailing_newline: true,
+        }
+    }
+}
+
+impl Envelope {
+    // the label of 1.2 files
+    pub fn vault_id(&self) -> Option<&str> {
+        self.header.split(';').nth(3).map(str::trim)
+    }
+
+    fn parse(data: &[u8]) -> Result<(Envelope, String), Error> {
+        let text = str::from_utf8(data).map_err(|_| Error::Format)?;
+        let mut lines = text.lines();
+        let header = lines.next().ok_or(Error::Format)?;
+        let fields: Vec<&str> = header.trim().split(';').map(str::trim).collect();
+        if fields.len() < 3 || fields[0].as_bytes() != MAGIC {
+            return Err(Error::Format);
+        }
+        // 1.0 files used AES with an MD5 based KDF, long gone from ansible too
+        if !(fields[1] == "1.1" || fields[1] == "1.2") || fields[2] != "AES256" {
+            return Err(Error::Unsupported);
+        }
+
+        let body: Vec<&str> = lines
+            .map(|line| line.trim_end_matches('\r'))
+            .filter(|line| !line.trim().is_empty())
+            .collect();
+        let envelope = Envelope {
+            header: header.to_string(),
+            width: if body.len() > 1 {
+                Some(body[0].len())
+            } else {
+                None
+            },
+            newline: if text.split('\n').next() == Some(header) {
+                "\n"
+            } else {
+                "\r\n"
+            },
+            trailing_newline: text.ends_with('\n'),
+        };
+        Ok((envelope, body.iter().map(|line| line.trim()).collect()))
+    }
+
+    fn wrap(&self, body: &str) -> Vec<u8> {
+        let mut lines = vec![self.header.as_str()];
+        match self.width {
+            Some(width) if width > 0 => {
+                let mut rest = body;
+                while !rest.is_empty() {
+                    let (line, tail) = rest.split_at(width.min(rest.len()));
+                    lines.push(line);
+                    rest = tail;
+                }
+            }
+            _ => lines.push(body),
+        }
+        let mut data = lines.join(self.newline);
+        if self.trailing_newline {
+            data.push_str(self.newline);
+        }
+        data.into_bytes()
+    }
+}
+
+pub fn is_ansible(data: &[u8]) -> bool {
+    data.starts_with(MAGIC)
+}
+
+fn derive(password: &str, salt: &[u8]) -> Zeroizing<Vec<u8>> {
+    let mut derived = Zeroizing::new(vec![0; 2 * KEY_LEN + IV_LEN]);
+    crypto::pbkdf2_with(
+        Hash::SHA2_256,
+        ITER,
+        password.as_bytes(),
+        salt,
+        &mut derived,
+    );
+    derived
+}
+
+fn ctr(derived: &[u8], input: &[u8]) -> Vec<u8> {
+    let mut cipher = aes::ctr(
+        aes::KeySize::KeySize256,
+        &derived[..KEY_LEN],
+        &derived[2 * KEY_LEN..],
+    );
+    let mut output = vec![0; input.len()];
+    cipher.process(input, &mut output);
+    output
+}
+
+pub fn encrypt(envelope: &Envelope, password: &str, plaintext: &[u8]) -> Vec<u8> {
+    let mut salt = [0; SALT_LEN];
+    crypto::random_bytes(&mut salt);
+    let derived = derive(password, &salt);
+
+    let pad = BLOCK_LEN - plaintext.len() % BLOCK_LEN;
+    let mut padded = Zeroizing::new(plaintext.to_vec());
+    padded.resize(plaintext.len() + pad, pad as u8);
+    let ciphertext = ctr(&derived, &padded);
+    let mac = crypto::hmac_sha256(&derived[KEY_LEN..2 * KEY_LEN], &[&ciphertext]);
+
+    let inner = format!(
+        "{}\n{}\n{}",
+        salt.to_hex(),
+        mac.to_hex(),
+        ciphertext.to_hex()
+    );
+    envelope.wrap(&inner.as_bytes().to_hex())
+}
+
+pub fn decrypt(data: &[u8], password: &str) -> Result<(Vec<u8>, Envelope), Error> {
+    let (envelope, body) = Envelope::parse(data)?;
+    let inner = body.from_hex().map_err(|_| Error::Format)?;
+    let inner = str::from_utf8(&inner).map_err(|_| Error::Format)?;
+    let mut parts = inner.splitn(3, '\n').map(|part| part.trim().from_hex());
+    let (salt, mac, ciphertext) = match (parts.next(), parts.next(), parts.next()) {
+        (Some(Ok(salt)), Some(Ok(mac)), Some(Ok(ciphertext))) => (salt, mac, ciphertext),
+        _ => return Err(Error::Format),
+    };
+
+    let derived = derive(password, &salt);
+    let expected = crypto::hmac_sha256(&derived[KEY_LEN..2 * KEY_LEN], &[&ciphertext]);
+    if !fixed_time_eq(&expected, &mac) {
+        return Err(Error::NoMatchingKey);
+    }
+    let mut plaintext = ctr(&derived, &ciphertext);
+    let pad = *plaintext.last().ok_or(Error::Format)? as usize;
+    if pad == 0 || pad > BLOCK_LEN || pad > plaintext.len() {
+        return Err(Error::Format);
+    }
+    plaintext.truncate(plaintext.len() - pad);
+    Ok((plaintext, envelope))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // "db_password: secret\n" under the password vault and the vault id prod
+    const FILE: &str = "$ANSIBLE_VAULT;1.2;AES256;prod
+61376533643661643032326664313335353030653330336139306564366166616533356130613730
+3333653765373038373062303361346334356439623664660a666536353761386635346662353164
+62393838303236343235613338386237336137363532336166303833343661666362643662666532
+3735656436613434370a663036363463373462333031353739653731653861323536656231353063
+66396665656538353033653438353631326465313235313734656162383963323630
+";
+
+    #[test]
+    fn test_ansible_file() {
+        let (plaintext, envelope) = decrypt(FILE.as_bytes(), "vault").unwrap();
+        assert_eq!(plaintext, b"db_password: secret\n");
+        assert_eq!(envelope.vault_id(), Some("prod"));
+        assert!(decrypt(FILE.as_bytes(), "wrong").is_err());
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let (_, envelope) = decrypt(FILE.as_bytes(), "vault").unwrap();
+        let data = encrypt(&envelope, "vault", b"db_password: changed\n");
+        let text = str::from_utf8(&data).unwrap();
+        assert!(text.starts_with("$ANSIBLE_VAULT;1.2;AES256;prod\n"));
+        assert!(text.lines().skip(1).all(|line| line.len() <= COLUMNS));
+        assert_eq!(
+            decrypt(&data, "vault").unwrap(),
+            (b"db_password: changed\n".to_vec(), envelope)
+        );
+
+        // narrower lines and CRLF survive a save
+        let custom = Envelope {
+            header: HEADER.to_string(),
+            width: Some(64),
+            newline: "\r\n",
+            trailing_newline: false,
+        };
+        let data = encrypt(&custom, "vault", b"");
+        assert!(!data.ends_with(b"\n"));
+        assert_eq!(decrypt(&data, "vault").unwrap(), (Vec::new(), custom));
+    }
+}
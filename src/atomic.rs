@@ -0,0 +1,102 @@
+use rustc_serialize::hex::ToHex;
+use std::fs;
+use std::fs::{File, OpenOptions, Permissions};
+use std::io;
+use std::io::prelude::*;
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+
+use crypto;
+
+// Replaces the file at `path` with `data` so that a crash or a failed write
+// leaves either the old contents or the new ones, never a truncated file.
+// The data goes to a temporary file next to the target, which is synced and
+// then renamed over it. It is only readable by the user until it takes the
+// mode of the file it replaces; new files keep that mode.
+pub fn write(path: &Path, data: &[u8]) -> io::Result<()> {
+    // write through a symlink rather than replacing it
+    let path = match fs::canonicalize(path) {
+        Ok(path) => path,
+        Err(ref err) if err.kind() == io::ErrorKind::NotFound => path.to_path_buf(),
+        Err(err) => return Err(err),
+    };
+    let permissions = match fs::metadata(&path) {
+        Ok(metadata) => Some(metadata.permissions()),
+        Err(ref err) if err.kind() == io::ErrorKind::NotFound => None,
+        Err(err) => return Err(err),
+    };
+    let (dir, temp) = temp_path(&path)?;
+
+    let written = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(&temp)
+        .and_then(|mut file| {
+            file.write_all(data)?;
+            if let Some(permissions) = permissions {
+                file.set_permissions(Permissions::from_mode(permissions.mode()))?;
+            }
+            file.sync_all()
+        })
+        .and_then(|()| fs::rename(&temp, &path));
+    if let Err(err) = written {
+        let _ = fs::remove_file(&temp);
+        return Err(err);
+    }
+    // makes the rename itself durable
+    File::open(dir)?.sync_all()
+}
+
+// A hidden file with a random name in the directory of `path`, which the
+// rename needs to stay on the same file system.
+fn temp_path(path: &Path) -> io::Result<(&Path, PathBuf)> {
+    let name = path
+        .file_name()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "not a file"))?;
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let mut suffix = [0; 8];
+    crypto::random_bytes(&mut suffix);
+    let temp = format!(".{}.{}.tmp", name.to_string_lossy(), suffix.to_hex());
+    Ok((dir, dir.join(temp)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    #[test]
+    fn test_write() {
+        let dir = env::temp_dir().join(format!("vault-atomic-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("notes.vault");
+
+        write(&path, b"first").unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"first");
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+
+        fs::set_permissions(&path, Permissions::from_mode(0o640)).unwrap();
+        write(&path, b"second").unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"second");
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o640);
+
+        let link = dir.join("link.vault");
+        std::os::unix::fs::symlink(&path, &link).unwrap();
+        write(&link, b"third").unwrap();
+        assert!(fs::symlink_metadata(&link)
+            .unwrap()
+            .file_type()
+            .is_symlink());
+        assert_eq!(fs::read(&path).unwrap(), b"third");
+
+        // nothing is left next to the file
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 2);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,594 @@
+use rpassword;
+use std::env;
+use std::fs;
+use std::io;
+use std::io::prelude::*;
+use std::path::Path;
+use std::str;
+use std::str::FromStr;
+use std::time::Duration;
+
+use age;
+use agent;
+use ansible;
+use atomic;
+use config;
+use container;
+use fernet;
+use gitfilter;
+use openpgp;
+use openssl;
+use recipient::{Identity, Recipient};
+use sandbox;
+use section;
+use signature;
+use signature::{SigningKey, Status, TrustList};
+use state::format::Format;
+use structured;
+use zeroize::Zeroizing;
+
+const USAGE: &str = "Usage:
+    vault                                   open the editor
+    vault keygen [--signing | --age | --fernet] [-o IDENTITY]
+    vault encrypt [-p] [-r RECIPIENT]... [-R FILE]... [-s SIGNING_KEY] [-o OUTPUT] [INPUT]
+                  [--cipher NAME] [--hash NAME] [--cost NAME] [--kdf NAME]
+                  [--format NAME] [-a] [--md NAME] [--iter N | --pbkdf2] [-k KEY_FILE]
+                  [--deterministic]
+    vault decrypt [-i IDENTITY] [--md NAME] [--iter N | --pbkdf2] [-k KEY_FILE] [--ttl SECONDS]
+                  [-o OUTPUT] [INPUT]
+    vault verify [INPUT]
+    vault inline encrypt [-o OUTPUT] [INPUT] [--cipher NAME] [--hash NAME] [--cost NAME] [--kdf NAME]
+    vault inline decrypt [-o OUTPUT] [INPUT]
+    vault agent [--ttl SECONDS]
+    vault git-filter setup [PATTERN]...
+    vault git-filter clean|smudge PATH
+    vault git-filter textconv FILE
+
+Without -r or -R, encrypt asks for a password. Pass -p to add a password
+slot next to the recipients.
+
+With --format age, recipients are age1... X25519 keys. With --format openpgp
+the file can be decrypted with gpg. -a writes armored age and OpenPGP files.
+Decryption detects both formats and takes age identity files.
+
+--format openssl reads and writes the files of openssl enc -aes-256-cbc, -a
+giving base64. --md, --iter and --pbkdf2 work as they do for openssl enc:
+without --iter or --pbkdf2 the key comes from EVP_BytesToKey.
+--format ansible writes $ANSIBLE_VAULT;1.1;AES256 files.
+
+--format fernet writes a Fernet token with the key read from -k, or asked
+for. Decrypting a token prints when it was made; --ttl refuses older ones.
+
+--format structured encrypts only the values of a YAML, JSON, TOML or .env
+file, told apart by the extension of INPUT or OUTPUT, and leaves the keys
+readable. decrypt detects such files. With --deterministic the values are
+sealed with AES-SIV, so a value that didn't change encrypts the same way
+again and diffs stay small. In exchange anyone comparing two versions sees
+which values were left alone or changed back.
+
+inline encrypt turns text into an armored block to paste into a Markdown or
+config file that otherwise stays plain text. inline decrypt prints such a
+file with every block the key opens replaced by its text; blocks sealed
+with other keys are left as they are.
+
+git-filter setup makes git store files matching PATTERN, *.vault by
+default, as vault files while they are checked out as plain text, and shows
+their diffs decrypted. It adds them to .gitattributes and writes the filter
+commands to the repository config. The filters take the password from
+$VAULT_PASSWORD or from vault agent, which asks for it once and hands it out
+until it is stopped or --ttl runs out. Without either, checked out files
+stay encrypted and adding changed ones fails.
+
+Signers are looked up in the trusted_signers file of the vault config
+directory, one name followed by a vault-sig1: public key per line.";
+
+#[derive(Default)]
+struct Options {
+    passphrase: bool,
+    recipients: Vec<Recipient>,
+    age_recipients: Vec<age::Recipient>,
+    identity: Option<String>,
+    signing: bool,
+    age: bool,
+    fernet: bool,
+    armor: bool,
+    deterministic: bool,
+    signing_key: Option<String>,
+    key_file: Option<String>,
+    ttl: Option<u64>,
+    output: Option<String>,
+    input: Option<String>,
+    params: container::Params,
+    format: Format,
+    openssl: openssl::Params,
+}
+
+// Runs the command named by the arguments. Returns None when there is no
+// command so that the editor starts instead.
+pub fn run(args: &[String]) -> Option<i32> {
+    let command = args.get(1)?;
+    let result = match command.as_str() {
+        "keygen" => parse(&args[2..]).and_then(|options| keygen(&options)),
+        "encrypt" => parse(&args[2..]).and_then(|options| encrypt(&options)),
+        "decrypt" => parse(&args[2..]).and_then(|options| decrypt(&options)),
+        "verify" => parse(&args[2..]).and_then(|options| verify(&options)),
+        "agent" => parse(&args[2..]).and_then(|options| run_agent(&options)),
+        "git-filter" => git_filter(&args[2..]),
+        "inline" => match args.get(2).map(String::as_str) {
+            Some("encrypt") => parse(&args[3..]).and_then(|mut options| {
+                options.format = Format::INLINE;
+                encrypt(&options)
+            }),
+            Some("decrypt") => parse(&args[3..]).and_then(|options| decrypt(&options)),
+            _ => Err(format!("inline takes encrypt or decrypt\n\n{}", USAGE)),
+        },
+        sandbox::COMMAND => parse(&args[2..]).and_then(|options| {
+            sandbox::helper(options.params, options.input.as_deref().unwrap_or("-"))
+        }),
+        "help" | "-h" | "--help" => {
+            println!("{}", USAGE);
+            Ok(())
+        }
+        _ => return None,
+    };
+    match result {
+        Ok(()) => Some(0),
+        Err(err) => {
+            eprintln!("vault: {}", err);
+            Some(1)
+        }
+    }
+}
+
+fn parse_param<T: FromStr>(value: Option<&String>, what: &str) -> Result<T, String> {
+    let value = value.ok_or_else(|| format!("missing {}", what))?;
+    value
+        .parse()
+        .map_err(|_| format!("unknown {} {}", what, value))
+}
+
+fn parse(args: &[String]) -> Result<Options, String> {
+    let mut options = Options {
+        // openssl enc only uses PBKDF2 when asked to
+        openssl: openssl::Params {
+            iter: None,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-p" | "--passphrase" => options.passphrase = true,
+            "-r" | "--recipient" => {
+                let value = args.next().ok_or("missing recipient")?;
+                if let Some(recipient) = age::Recipient::parse(value) {
+                    options.age_recipients.push(recipient);
+                } else {
+                    let recipient = Recipient::parse(value)
+                        .ok_or_else(|| format!("invalid recipient {}", value))?;
+                    options.recipients.push(recipient);
+                }
+            }
+            "-R" | "--recipients-file" => {
+                let path = args.next().ok_or("missing recipients file")?;
+                let contents =
+                    fs::read_to_string(path).map_err(|err| format!("reading {}: {}", path, err))?;
+                let lines = contents
+                    .lines()
+                    .map(str::trim)
+                    .filter(|line| !line.is_empty() && !line.starts_with('#'));
+                for line in lines {
+                    if let Some(recipient) = age::Recipient::parse(line) {
+                        options.age_recipients.push(recipient);
+                    } else if let Some(recipient) = Recipient::parse(line) {
+                        options.recipients.push(recipient);
+                    } else {
+                        return Err(format!("{} does not hold vault or age recipients", path));
+                    }
+                }
+            }
+            "-i" | "--identity" => options.identity = args.next().cloned(),
+            "--signing" => options.signing = true,
+            "--age" => options.age = true,
+            "--fernet" => options.fernet = true,
+            "-k" | "--key-file" => options.key_file = args.next().cloned(),
+            "--ttl" => options.ttl = Some(parse_param(args.next(), "ttl")?),
+            "-a" | "--armor" => options.armor = true,
+            "--deterministic" => options.deterministic = true,
+            "-s" | "--sign" => options.signing_key = args.next().cloned(),
+            "-o" | "--output" => options.output = args.next().cloned(),
+            "--cipher" => options.params.cipher = parse_param(args.next(), "cipher")?,
+            "--hash" => options.params.hash = parse_param(args.next(), "hash")?,
+            "--cost" => options.params.cost = parse_param(args.next(), "cost")?,
+            "--kdf" => options.params.kdf = parse_param(args.next(), "kdf")?,
+            "--format" => options.format = parse_param(args.next(), "format")?,
+            "--md" => {
+                let value = args.next().ok_or("missing md")?;
+                options.openssl.md =
+                    openssl::parse_md(value).ok_or_else(|| format!("unknown md {}", value))?;
+            }
+            "--iter" => options.openssl.iter = Some(parse_param(args.next(), "iter")?),
+            "--pbkdf2" => {
+                options.openssl.iter = options.openssl.iter.or(Some(openssl::DEFAULT_ITER))
+            }
+            _ if arg.starts_with('-') && arg != "-" => {
+                return Err(format!("unknown option {}\n\n{}", arg, USAGE))
+            }
+            _ if options.input.is_none() => options.input = Some(arg.clone()),
+            _ => return Err(format!("unexpected argument {}", arg)),
+        }
+    }
+    Ok(options)
+}
+
+fn read_input(options: &Options) -> Result<Vec<u8>, String> {
+    match options.input.as_deref() {
+        None | Some("-") => read_stdin(),
+        Some(path) => fs::read(path).map_err(|err| format!("reading {}: {}", path, err)),
+    }
+}
+
+fn read_stdin() -> Result<Vec<u8>, String> {
+    let mut data = Vec::new();
+    io::stdin()
+        .read_to_end(&mut data)
+        .map_err(|err| format!("reading stdin: {}", err))?;
+    Ok(data)
+}
+
+fn write_output(options: &Options, data: &[u8]) -> Result<(), String> {
+    match options.output.as_ref() {
+        None => io::stdout()
+            .write_all(data)
+            .map_err(|err| format!("writing stdout: {}", err)),
+        Some(path) => {
+            atomic::write(Path::new(path), data).map_err(|err| format!("writing {}: {}", path, err))
+        }
+    }
+}
+
+fn read_password(prompt: &str) -> Result<Zeroizing<String>, String> {
+    rpassword::read_password_from_tty(Some(prompt))
+        .map(Zeroizing::new)
+        .map_err(|err| format!("reading password: {}", err))
+}
+
+fn keygen(options: &Options) -> Result<(), String> {
+    if options.signing {
+        let signing_key = SigningKey::generate();
+        let mut contents = signing_key.to_string();
+        contents.push('\n');
+        if options.output.is_some() {
+            eprintln!(
+                "Public key: {}",
+                signature::public_key_to_string(signing_key.public_key())
+            );
+        }
+        return write_output(options, contents.as_bytes());
+    }
+    if options.fernet {
+        let mut contents = fernet::Key::generate().to_string();
+        contents.push('\n');
+        return write_output(options, contents.as_bytes());
+    }
+    if options.age {
+        let identity = age::Identity::generate();
+        let mut contents = identity.to_string();
+        contents.push('\n');
+        if options.output.is_some() {
+            eprintln!("Public key: {}", identity.recipient());
+        }
+        return write_output(options, contents.as_bytes());
+    }
+    let identity = Identity::generate();
+    let mut contents = identity.to_string();
+    contents.push('\n');
+    if options.output.is_some() {
+        eprintln!("Public key: {}", identity.recipient());
+    }
+    write_output(options, contents.as_bytes())
+}
+
+fn encrypt(options: &Options) -> Result<(), String> {
+    let signing_key = match options.signing_key {
+        Some(ref path) => Some(
+            fs::read_to_string(path)
+                .ok()
+                .as_ref()
+                .and_then(|s| SigningKey::parse(s))
+                .ok_or_else(|| format!("{} is not a vault signing key", path))?,
+        ),
+        None => None,
+    };
+    // age recipients imply an age file
+    let format = if options.age_recipients.is_empty() {
+        options.format
+    } else {
+        Format::AGE
+    };
+    if format != Format::VAULT {
+        if !options.recipients.is_empty() {
+            return Err("vault recipients only work in vault files".to_string());
+        }
+        if signing_key.is_some() {
+            return Err("only vault files can be signed".to_string());
+        }
+    }
+    if format == Format::AGE && options.passphrase && !options.age_recipients.is_empty() {
+        return Err("age files take either a passphrase or recipients".to_string());
+    }
+    // structured files are told apart by their extension
+    let kind = [options.input.as_deref(), options.output.as_deref()]
+        .iter()
+        .flatten()
+        .find_map(|path| structured::Kind::from_path(Path::new(path)));
+    if format == Format::STRUCTURED && kind.is_none() {
+        return Err("structured files end in .yaml, .yml, .json, .toml or .env".to_string());
+    }
+    if options.deterministic {
+        if format != Format::STRUCTURED {
+            return Err("--deterministic only works with --format structured".to_string());
+        }
+        eprintln!(
+            "vault: deterministic values show anyone comparing versions which ones are unchanged"
+        );
+    }
+    let plaintext = Zeroizing::new(read_input(options)?);
+    if format == Format::INLINE && str::from_utf8(&plaintext).is_err() {
+        return Err("inline blocks only hold text".to_string());
+    }
+    if let (Format::STRUCTURED, Some(kind)) = (format, kind) {
+        let text = str::from_utf8(&plaintext)
+            .map_err(|_| "structured files only hold text".to_string())?;
+        structured::check(kind, text).map_err(|_| format!("input is not a {:?} document", kind))?;
+    }
+    let recipients = options.recipients.len() + options.age_recipients.len();
+    // Fernet takes a key rather than a password
+    let key = if format != Format::FERNET && (options.passphrase || recipients == 0) {
+        let key = read_password("Encryption key: ")?;
+        if read_password("Confirm encryption key: ")? != key {
+            return Err("keys do not match".to_string());
+        }
+        Some(key)
+    } else {
+        None
+    };
+    let password = key.as_ref().map(|key| key.as_str());
+    let data = match format {
+        Format::VAULT => container::seal(
+            options.params,
+            password,
+            &options.recipients,
+            signing_key.as_ref(),
+            &plaintext,
+        ),
+        Format::AGE => age::encrypt(password, &options.age_recipients, &plaintext),
+        Format::OPENPGP => Ok(openpgp::encrypt(password.unwrap_or_default(), &plaintext)),
+        Format::OPENSSL => {
+            warn_legacy_kdf(options);
+            Ok(openssl::encrypt(
+                options.openssl,
+                password.unwrap_or_default(),
+                &plaintext,
+            ))
+        }
+        Format::ANSIBLE => Ok(ansible::encrypt(
+            &Default::default(),
+            password.unwrap_or_default(),
+            &plaintext,
+        )),
+        Format::FERNET => Ok(fernet::encrypt(&fernet_key(options)?, &plaintext).into_bytes()),
+        Format::INLINE => str::from_utf8(&plaintext)
+            .map_err(|_| container::Error::Format)
+            .and_then(|text| section::seal(options.params, password.unwrap_or_default(), text))
+            .map(|armored| format!("{}\n", armored).into_bytes()),
+        Format::STRUCTURED => kind
+            .ok_or(container::Error::Format)
+            .and_then(|kind| {
+                structured::Key::new(
+                    kind,
+                    options.params,
+                    options.deterministic,
+                    password.unwrap_or_default(),
+                )
+            })
+            .and_then(|key| structured::seal(&key, str::from_utf8(&plaintext).unwrap_or_default()))
+            .map(String::into_bytes),
+    }
+    .map_err(|err| err.to_string())?;
+    match format {
+        Format::AGE if options.armor => write_output(options, age::armor(&data).as_bytes()),
+        Format::OPENPGP if options.armor => write_output(options, openpgp::armor(&data).as_bytes()),
+        Format::OPENSSL if options.armor => write_output(options, openssl::armor(&data).as_bytes()),
+        _ => write_output(options, &data),
+    }
+}
+
+fn decrypt(options: &Options) -> Result<(), String> {
+    let data = read_input(options)?;
+    let plaintext = Zeroizing::new(if age::is_age(&data) {
+        let file = age::Encrypted::parse(&data).map_err(|err| err.to_string())?;
+        match options.identity {
+            Some(ref path) => {
+                let identities = fs::read_to_string(path)
+                    .ok()
+                    .as_ref()
+                    .and_then(|s| age::Identity::parse_list(s))
+                    .ok_or_else(|| format!("{} does not hold age identities", path))?;
+                file.decrypt_with_identities(&identities)
+            }
+            None => file.decrypt_with_passphrase(&read_password("Decryption key: ")?),
+        }
+        .map_err(|err| err.to_string())?
+    } else if openpgp::is_openpgp(&data) {
+        openpgp::decrypt(&data, &read_password("Decryption key: ")?)
+            .map_err(|err| err.to_string())?
+    } else if openssl::is_openssl(&data) {
+        warn_legacy_kdf(options);
+        openssl::decrypt(options.openssl, &read_password("Decryption key: ")?, &data)
+            .map_err(|err| err.to_string())?
+    } else if ansible::is_ansible(&data) {
+        let (plaintext, _) = ansible::decrypt(&data, &read_password("Decryption key: ")?)
+            .map_err(|err| err.to_string())?;
+        plaintext
+    } else if fernet::is_fernet(&data) {
+        let (plaintext, issued) = fernet::decrypt(&fernet_key(options)?, &data, options.ttl)
+            .map_err(|err| err.to_string())?;
+        eprintln!("issued {}", fernet::format_timestamp(issued));
+        plaintext
+    } else if structured::is_structured(&data) {
+        let text = str::from_utf8(&data).map_err(|err| err.to_string())?;
+        let (plaintext, _) = structured::open(text, &read_password("Decryption key: ")?)
+            .map_err(|err| err.to_string())?;
+        plaintext.as_bytes().to_vec()
+    } else if section::is_inline(&data) {
+        let text = str::from_utf8(&data).map_err(|err| err.to_string())?;
+        let blocks = section::find(text).len();
+        let (plaintext, opened) = section::open_all(text, &read_password("Decryption key: ")?);
+        if opened == 0 {
+            return Err("no block opens with this key".to_string());
+        }
+        if opened < blocks {
+            eprintln!(
+                "vault: {} of {} blocks left encrypted",
+                blocks - opened,
+                blocks
+            );
+        }
+        plaintext.as_bytes().to_vec()
+    } else if container::is_container(&data) {
+        let container = container::Container::parse(&data).map_err(|err| err.to_string())?;
+        eprintln!("{}", container.signature_status(&trusted_signers()));
+        match options.identity {
+            Some(ref path) => {
+                let identity = fs::read_to_string(path)
+                    .ok()
+                    .as_ref()
+                    .and_then(|s| Identity::parse(s))
+                    .ok_or_else(|| format!("{} is not a vault identity", path))?;
+                container.open_with_identity(&identity)
+            }
+            None => container.open_with_password(&read_password("Decryption key: ")?),
+        }
+        .map_err(|err| err.to_string())?
+    } else {
+        let input = match options.input.as_deref() {
+            None | Some("-") => sandbox::Input::Data(&data),
+            Some(path) => sandbox::Input::File(Path::new(path)),
+        };
+        sandbox::decrypt(options.params, &read_password("Decryption key: ")?, input)
+            .map_err(|err| err.to_string())?
+    });
+    write_output(options, &plaintext)
+}
+
+fn verify(options: &Options) -> Result<(), String> {
+    let data = read_input(options)?;
+    let container = container::Container::parse(&data).map_err(|err| err.to_string())?;
+    let status = container.signature_status(&trusted_signers());
+    println!("{}", status);
+    match status {
+        Status::Trusted(_) => Ok(()),
+        _ => Err("signature not trusted".to_string()),
+    }
+}
+
+fn run_agent(options: &Options) -> Result<(), String> {
+    let password = read_password("Key: ")?;
+    if read_password("Confirm key: ")? != password {
+        return Err("keys do not match".to_string());
+    }
+    let socket = config::agent_socket();
+    eprintln!("vault: agent listening on {}", socket.display());
+    agent::serve(&socket, password, options.ttl.map(Duration::from_secs))
+        .map_err(|err| format!("agent: {}", err))
+}
+
+// git runs the filters with the path of the file, and hands clean and smudge
+// its contents on stdin.
+fn git_filter(args: &[String]) -> Result<(), String> {
+    let command = args.first().map(String::as_str).unwrap_or_default();
+    let args = args.get(1..).unwrap_or_default();
+    if command == "setup" {
+        let mut patterns = args.to_vec();
+        if patterns.is_empty() {
+            patterns.push(gitfilter::PATTERN.to_string());
+        }
+        return gitfilter::setup(&patterns).map_err(|err| format!("setting up git: {}", err));
+    }
+    let options = parse(args)?;
+    let path = options.input.as_deref().ok_or("missing path")?;
+    match command {
+        "clean" => {
+            let plaintext = Zeroizing::new(read_stdin()?);
+            let indexed = gitfilter::indexed(path);
+            let data = gitfilter::clean(
+                options.params,
+                &cached_password()?,
+                &plaintext,
+                indexed.as_deref(),
+            )
+            .map_err(|err| format!("{}: {}", path, err))?;
+            write_output(&options, &data)
+        }
+        "smudge" => write_output(&options, &open_or_keep(path, &read_stdin()?)),
+        "textconv" => write_output(&options, &open_or_keep(path, &read_input(&options)?)),
+        _ => Err(format!(
+            "git-filter takes setup, clean, smudge or textconv\n\n{}",
+            USAGE
+        )),
+    }
+}
+
+// Files the cached password doesn't open stay ciphertext, so that a checkout
+// without it still works.
+fn open_or_keep(path: &str, data: &[u8]) -> Zeroizing<Vec<u8>> {
+    if !container::is_container(data) {
+        return Zeroizing::new(data.to_vec());
+    }
+    let opened = cached_password()
+        .and_then(|password| gitfilter::smudge(&password, data).map_err(|err| err.to_string()));
+    match opened {
+        Ok(plaintext) => Zeroizing::new(plaintext),
+        Err(err) => {
+            eprintln!("vault: {} left encrypted: {}", path, err);
+            Zeroizing::new(data.to_vec())
+        }
+    }
+}
+
+// git runs the filters without a terminal to ask on.
+fn cached_password() -> Result<Zeroizing<String>, String> {
+    if let Ok(password) = env::var(gitfilter::PASSWORD_VAR) {
+        return Ok(Zeroizing::new(password));
+    }
+    agent::fetch(&config::agent_socket()).ok_or_else(|| {
+        format!(
+            "no password, set {} or start vault agent",
+            gitfilter::PASSWORD_VAR
+        )
+    })
+}
+
+fn fernet_key(options: &Options) -> Result<fernet::Key, String> {
+    match options.key_file {
+        Some(ref path) => fs::read_to_string(path)
+            .ok()
+            .as_ref()
+            .and_then(|s| fernet::Key::parse(s))
+            .ok_or_else(|| format!("{} is not a Fernet key", path)),
+        None => fernet::Key::parse(&read_password("Fernet key: ")?)
+            .ok_or_else(|| "not a Fernet key".to_string()),
+    }
+}
+
+fn warn_legacy_kdf(options: &Options) {
+    if options.openssl.iter.is_none() {
+        eprintln!("vault: deprecated key derivation used, pass --iter or --pbkdf2");
+    }
+}
+
+fn trusted_signers() -> TrustList {
+    TrustList::load(&config::trusted_signers())
+}
@@ -1,3 +1,31 @@
+use glib;
+use std::path::PathBuf;
+
 pub const ID: &'static str = "com.github.gqgs.vault";
 pub const TITLE: &'static str = "vault";
 pub const ICON: &[u8] = include_bytes!("../icon-pixdata");
+
+fn dir() -> PathBuf {
+    glib::get_user_config_dir().unwrap_or_default().join(TITLE)
+}
+
+// Known signers, one `name public-key` pair per line.
+pub fn trusted_signers() -> PathBuf {
+    dir().join("trusted_signers")
+}
+
+// Where the agent holding a password for the git filters listens.
+pub fn agent_socket() -> PathBuf {
+    glib::get_user_runtime_dir()
+        .unwrap_or_else(std::env::temp_dir)
+        .join(TITLE)
+        .join("agent.sock")
+}
+
+// Encrypted autosaves of documents with unsaved changes.
+pub fn recovery_dir() -> PathBuf {
+    glib::get_user_data_dir()
+        .unwrap_or_default()
+        .join(TITLE)
+        .join("recovery")
+}
@@ -0,0 +1,797 @@
+// Container layout:
+//
+//   magic (6) | version (1) | cipher | hash | cost | kdf
+//   records: tag (1) | length (4, big endian) | payload
+//
+// The document is encrypted under a random file key. Every key slot record
+// wraps that file key, either from a password or for a hybrid recipient, and
+// the MAC record authenticates everything written before it. An optional
+// history record between the body and the MAC holds earlier revisions. An
+// optional signature record follows the MAC and signs everything before it.
+//
+// Save writes a file back under the file key it was opened with, so that
+// the slots of every recipient still open it. A salt record before the body
+// then keeps the keys of the body apart from those of earlier saves.
+use cryptolib::util::fixed_time_eq;
+use std::fmt;
+
+use crypto;
+use history;
+use history::Revision;
+use memory;
+use recipient::{Identity, Recipient, ENCAPSULATION_LEN};
+use signature;
+use signature::{SigningKey, Status, TrustList, PUBLIC_KEY_LEN, SIGNATURE_LEN};
+use state::cipher::Cipher;
+use state::cost::Cost;
+use state::hash::Hash;
+use state::kdf::KDF;
+use zeroize::Zeroizing;
+
+const MAGIC: &[u8] = b"VAULT\0";
+const VERSION: u8 = 1;
+const HEADER_LEN: usize = 11;
+
+const TAG_PASSWORD: u8 = 1;
+const TAG_HYBRID: u8 = 2;
+const TAG_SALT: u8 = 15;
+const TAG_BODY: u8 = 16;
+const TAG_MAC: u8 = 17;
+const TAG_HISTORY: u8 = 18;
+const TAG_SIGNATURE: u8 = 32;
+
+const FILE_KEY_LEN: usize = 32;
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+const SEALED_KEY_LEN: usize = FILE_KEY_LEN + crypto::TAG_LEN;
+
+#[derive(Copy, Clone, Default, PartialEq, Debug)]
+pub struct Params {
+    pub cipher: Cipher,
+    pub hash: Hash,
+    pub cost: Cost,
+    pub kdf: KDF,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Error {
+    // not a container or a malformed one
+    Format,
+    // no key slot matches the given password or identity
+    NoMatchingKey,
+    // MAC mismatch
+    Integrity,
+    // the body failed to decrypt
+    Cipher,
+    // an algorithm or feature this implementation doesn't have
+    Unsupported,
+    // a token older than its time to live
+    Expired,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Format => write!(f, "malformed file"),
+            Error::NoMatchingKey => write!(f, "no key matches"),
+            Error::Integrity => write!(f, "file was modified"),
+            Error::Cipher => write!(f, "decryption failed"),
+            Error::Unsupported => write!(f, "unsupported algorithm"),
+            Error::Expired => write!(f, "token has expired"),
+        }
+    }
+}
+
+// Key of a password slot. Kept for the open document, it seals the file key
+// of every later save under the same salt with a fresh nonce, so the KDF runs
+// once per document rather than once per save.
+pub struct PasswordKey {
+    params: Params,
+    salt: [u8; SALT_LEN],
+    key: Zeroizing<Vec<u8>>,
+}
+
+impl PasswordKey {
+    pub fn new(params: Params, password: &str) -> PasswordKey {
+        let mut salt = [0; SALT_LEN];
+        crypto::random_bytes(&mut salt);
+        PasswordKey::derive(params, password, &salt)
+    }
+
+    pub fn derive(params: Params, password: &str, salt: &[u8]) -> PasswordKey {
+        let mut key = memory::key(32);
+        crypto::derive(
+            params.hash,
+            params.kdf,
+            params.cost,
+            password.as_bytes(),
+            salt,
+            &mut key,
+        );
+        let mut salt_bytes = [0; SALT_LEN];
+        salt_bytes.copy_from_slice(salt);
+        PasswordKey {
+            params,
+            salt: salt_bytes,
+            key,
+        }
+    }
+
+    pub fn params(&self) -> Params {
+        self.params
+    }
+
+    pub fn salt(&self) -> [u8; SALT_LEN] {
+        self.salt
+    }
+}
+
+#[derive(Clone)]
+enum Slot {
+    Password {
+        salt: Vec<u8>,
+        nonce: Vec<u8>,
+        sealed: Vec<u8>,
+    },
+    Hybrid {
+        encapsulation: Vec<u8>,
+        sealed: Vec<u8>,
+    },
+}
+
+// File key of an opened container and the slots that wrap it, to write the
+// file back for everyone it was sealed for.
+pub struct FileKey {
+    params: Params,
+    key: Zeroizing<Vec<u8>>,
+    slots: Vec<Slot>,
+}
+
+// The slots of a FileKey without the key, which a password key opens again.
+pub struct KeySlots {
+    params: Params,
+    slots: Vec<Slot>,
+}
+
+impl FileKey {
+    pub fn params(&self) -> Params {
+        self.params
+    }
+
+    pub fn has_recipient_slot(&self) -> bool {
+        self.slots
+            .iter()
+            .any(|slot| matches!(slot, Slot::Hybrid { .. }))
+    }
+
+    pub fn forget(self) -> KeySlots {
+        KeySlots {
+            params: self.params,
+            slots: self.slots,
+        }
+    }
+}
+
+impl KeySlots {
+    pub fn open(self, key: &PasswordKey) -> Option<FileKey> {
+        let aad = header(self.params);
+        let file_key = password_slot(&self.slots, key, &aad)?;
+        memory::lock(&file_key);
+        Some(FileKey {
+            params: self.params,
+            key: file_key,
+            slots: self.slots,
+        })
+    }
+}
+
+pub struct Container {
+    params: Params,
+    slots: Vec<Slot>,
+    salt: Option<Vec<u8>>,
+    body: Vec<u8>,
+    history: Option<Vec<u8>>,
+    mac: Vec<u8>,
+    // every byte covered by the MAC
+    authenticated: Vec<u8>,
+    // signer's public key and whether the signature holds
+    signature: Option<(Vec<u8>, bool)>,
+}
+
+pub fn is_container(data: &[u8]) -> bool {
+    data.starts_with(MAGIC)
+}
+
+fn header(params: Params) -> Vec<u8> {
+    let mut header = MAGIC.to_vec();
+    header.push(VERSION);
+    header.push(params.cipher.id());
+    header.push(params.hash.id());
+    header.push(params.cost.id());
+    header.push(params.kdf.id());
+    header
+}
+
+fn push_record(out: &mut Vec<u8>, tag: u8, payload: &[&[u8]]) {
+    let len: usize = payload.iter().map(|p| p.len()).sum();
+    out.push(tag);
+    out.extend_from_slice(&(len as u32).to_be_bytes());
+    for p in payload {
+        out.extend_from_slice(p);
+    }
+}
+
+// iv (16) | cipher key (32) | mac key (32)
+fn body_keys(file_key: &[u8], salt: &[u8]) -> Zeroizing<Vec<u8>> {
+    let mut keys = Zeroizing::new(vec![0; 80]);
+    crypto::hkdf_sha256(salt, file_key, b"vault body", &mut keys);
+    keys
+}
+
+// iv (16) | cipher key (32)
+fn history_keys(file_key: &[u8], salt: &[u8]) -> Zeroizing<[u8; 48]> {
+    let mut keys = Zeroizing::new([0; 48]);
+    crypto::hkdf_sha256(salt, file_key, b"vault history", &mut *keys);
+    keys
+}
+
+// The file key in the password slot with the salt of `key`.
+fn password_slot(slots: &[Slot], key: &PasswordKey, aad: &[u8]) -> Option<Zeroizing<Vec<u8>>> {
+    slots.iter().find_map(|slot| match slot {
+        Slot::Password {
+            salt,
+            nonce,
+            sealed,
+        } if salt[..] == key.salt[..] => crypto::open(&key.key, nonce, aad, sealed),
+        _ => None,
+    })
+}
+
+pub fn seal(
+    params: Params,
+    password: Option<&str>,
+    recipients: &[Recipient],
+    signer: Option<&SigningKey>,
+    plaintext: &[u8],
+) -> Result<Vec<u8>, Error> {
+    let key = password.map(|password| PasswordKey::new(params, password));
+    seal_with_key(params, key.as_ref(), recipients, signer, plaintext, &[])
+}
+
+pub fn seal_with_key(
+    params: Params,
+    password: Option<&PasswordKey>,
+    recipients: &[Recipient],
+    signer: Option<&SigningKey>,
+    plaintext: &[u8],
+    history: &[Revision],
+) -> Result<Vec<u8>, Error> {
+    if password.is_none() && recipients.is_empty() {
+        return Err(Error::NoMatchingKey);
+    }
+    // the slot is opened with the parameters in the header
+    if password.map_or(false, |key| key.params != params) {
+        return Err(Error::NoMatchingKey);
+    }
+    let mut file_key = Zeroizing::new([0; FILE_KEY_LEN]);
+    crypto::random_bytes(&mut *file_key);
+
+    let mut out = header(params);
+    let aad = out.clone();
+    if let Some(key) = password {
+        let mut nonce = [0; NONCE_LEN];
+        crypto::random_bytes(&mut nonce);
+        let sealed = crypto::seal(&key.key, &nonce, &aad, &*file_key);
+        push_record(&mut out, TAG_PASSWORD, &[&key.salt, &nonce, &sealed]);
+    }
+    push_recipients(&mut out, recipients, &*file_key);
+    seal_body(out, &*file_key, &[], params, signer, plaintext, history)
+}
+
+// Writes an opened file back under its own file key, for the slots it had
+// and the `recipients` added since.
+pub fn reseal(
+    file_key: &FileKey,
+    recipients: &[Recipient],
+    signer: Option<&SigningKey>,
+    plaintext: &[u8],
+    history: &[Revision],
+) -> Result<Vec<u8>, Error> {
+    let mut out = header(file_key.params);
+    for slot in &file_key.slots {
+        match slot {
+            Slot::Password {
+                salt,
+                nonce,
+                sealed,
+            } => push_record(&mut out, TAG_PASSWORD, &[salt, nonce, sealed]),
+            Slot::Hybrid {
+                encapsulation,
+                sealed,
+            } => push_record(&mut out, TAG_HYBRID, &[encapsulation, sealed]),
+        }
+    }
+    push_recipients(&mut out, recipients, &file_key.key);
+    let mut salt = [0; SALT_LEN];
+    crypto::random_bytes(&mut salt);
+    push_record(&mut out, TAG_SALT, &[&salt]);
+    seal_body(
+        out,
+        &file_key.key,
+        &salt,
+        file_key.params,
+        signer,
+        plaintext,
+        history,
+    )
+}
+
+fn push_recipients(out: &mut Vec<u8>, recipients: &[Recipient], file_key: &[u8]) {
+    let aad = out[..HEADER_LEN].to_vec();
+    for recipient in recipients {
+        let (encapsulation, key) = recipient.wrap();
+        let sealed = crypto::seal(&*key, &[0; NONCE_LEN], &aad, file_key);
+        push_record(out, TAG_HYBRID, &[&encapsulation, &sealed]);
+    }
+}
+
+// Appends the body, history, MAC and signature records to `out`.
+fn seal_body(
+    mut out: Vec<u8>,
+    file_key: &[u8],
+    salt: &[u8],
+    params: Params,
+    signer: Option<&SigningKey>,
+    plaintext: &[u8],
+    history: &[Revision],
+) -> Result<Vec<u8>, Error> {
+    let keys = body_keys(file_key, salt);
+    let body = crypto::encrypt_with(params.cipher, &keys[16..48], &keys[..16], plaintext)
+        .map_err(|_| Error::Cipher)?;
+    push_record(&mut out, TAG_BODY, &[&body]);
+
+    if !history.is_empty() {
+        let keys = history_keys(file_key, salt);
+        let encoded = history::encode(history);
+        let history = crypto::encrypt_with(params.cipher, &keys[16..], &keys[..16], &encoded)
+            .map_err(|_| Error::Cipher)?;
+        push_record(&mut out, TAG_HISTORY, &[&history]);
+    }
+
+    let mac = crypto::hmac_sha256(&keys[48..], &[&out]);
+    push_record(&mut out, TAG_MAC, &[&mac]);
+
+    if let Some(signer) = signer {
+        let signature = signer.sign(&out);
+        push_record(&mut out, TAG_SIGNATURE, &[signer.public_key(), &signature]);
+    }
+    Ok(out)
+}
+
+impl Container {
+    pub fn parse(data: &[u8]) -> Result<Container, Error> {
+        if data.len() < HEADER_LEN || !is_container(data) || data[6] != VERSION {
+            return Err(Error::Format);
+        }
+        let params = Params {
+            cipher: Cipher::from_id(data[7]).ok_or(Error::Format)?,
+            hash: Hash::from_id(data[8]).ok_or(Error::Format)?,
+            cost: Cost::from_id(data[9]).ok_or(Error::Format)?,
+            kdf: KDF::from_id(data[10]).ok_or(Error::Format)?,
+        };
+
+        let mut slots = Vec::new();
+        let mut salt = None;
+        let mut body = None;
+        let mut history = None;
+        let mut mac = None;
+        let mut signature = None;
+        let mut pos = HEADER_LEN;
+        while pos < data.len() {
+            if data.len() - pos < 5 || signature.is_some() {
+                return Err(Error::Format);
+            }
+            let tag = data[pos];
+            let mut len = [0; 4];
+            len.copy_from_slice(&data[pos + 1..pos + 5]);
+            let len = u32::from_be_bytes(len) as usize;
+            let start = pos + 5;
+            if data.len() - start < len {
+                return Err(Error::Format);
+            }
+            let payload = &data[start..start + len];
+            if mac.is_some() && tag != TAG_SIGNATURE {
+                return Err(Error::Format);
+            }
+            match tag {
+                TAG_PASSWORD if len == SALT_LEN + NONCE_LEN + SEALED_KEY_LEN => {
+                    slots.push(Slot::Password {
+                        salt: payload[..SALT_LEN].to_vec(),
+                        nonce: payload[SALT_LEN..SALT_LEN + NONCE_LEN].to_vec(),
+                        sealed: payload[SALT_LEN + NONCE_LEN..].to_vec(),
+                    })
+                }
+                TAG_HYBRID if len == ENCAPSULATION_LEN + SEALED_KEY_LEN => {
+                    slots.push(Slot::Hybrid {
+                        encapsulation: payload[..ENCAPSULATION_LEN].to_vec(),
+                        sealed: payload[ENCAPSULATION_LEN..].to_vec(),
+                    })
+                }
+                TAG_SALT if salt.is_none() && body.is_none() && len == SALT_LEN => {
+                    salt = Some(payload.to_vec())
+                }
+                TAG_BODY if body.is_none() => body = Some(payload.to_vec()),
+                TAG_HISTORY if history.is_none() => history = Some(payload.to_vec()),
+                TAG_MAC if mac.is_none() && len == 32 => {
+                    mac = Some((payload.to_vec(), pos));
+                }
+                TAG_SIGNATURE if mac.is_some() && len == PUBLIC_KEY_LEN + SIGNATURE_LEN => {
+                    let (public, sig) = payload.split_at(PUBLIC_KEY_LEN);
+                    let valid = signature::verify(&data[..pos], public, sig);
+                    signature = Some((public.to_vec(), valid));
+                }
+                TAG_PASSWORD | TAG_HYBRID | TAG_SALT | TAG_BODY | TAG_HISTORY | TAG_MAC
+                | TAG_SIGNATURE => return Err(Error::Format),
+                // records from newer versions are skipped
+                _ => {}
+            }
+            pos = start + len;
+        }
+
+        match (body, mac) {
+            (Some(body), Some((mac, mac_pos))) => Ok(Container {
+                params,
+                slots,
+                salt,
+                body,
+                history,
+                mac,
+                authenticated: data[..mac_pos].to_vec(),
+                signature,
+            }),
+            _ => Err(Error::Format),
+        }
+    }
+
+    pub fn signature_status(&self, trusted: &TrustList) -> Status {
+        match self.signature {
+            None => Status::Unsigned,
+            Some((_, false)) => Status::Invalid,
+            Some((ref public, true)) => trusted.status(public),
+        }
+    }
+
+    pub fn params(&self) -> Params {
+        self.params
+    }
+
+    pub fn has_password_slot(&self) -> bool {
+        self.slots
+            .iter()
+            .any(|slot| matches!(slot, Slot::Password { .. }))
+    }
+
+    pub fn has_recipient_slot(&self) -> bool {
+        self.slots
+            .iter()
+            .any(|slot| matches!(slot, Slot::Hybrid { .. }))
+    }
+
+    pub fn open_with_password(&self, password: &str) -> Result<Vec<u8>, Error> {
+        self.open_with_password_key(password)
+            .map(|(plaintext, _)| plaintext)
+    }
+
+    // Also hands back the key of the slot that opened, to seal later saves.
+    pub fn open_with_password_key(&self, password: &str) -> Result<(Vec<u8>, PasswordKey), Error> {
+        let aad = &self.authenticated[..HEADER_LEN];
+        for slot in &self.slots {
+            if let Slot::Password {
+                salt,
+                nonce,
+                sealed,
+            } = slot
+            {
+                let key = PasswordKey::derive(self.params, password, salt);
+                if let Some(file_key) = crypto::open(&key.key, nonce, aad, sealed) {
+                    return Ok((self.open_body(&file_key)?, key));
+                }
+            }
+        }
+        Err(Error::NoMatchingKey)
+    }
+
+    // Opens a file sealed with a key kept from earlier, without deriving it
+    // again. Only a slot with the key's salt can match.
+    pub fn open_with_key(&self, key: &PasswordKey) -> Result<Vec<u8>, Error> {
+        self.open_body(&self.file_key_with_key(key)?)
+    }
+
+    pub fn open_with_identity(&self, identity: &Identity) -> Result<Vec<u8>, Error> {
+        self.open_body(&self.file_key_with_identity(identity)?)
+    }
+
+    // The file key and slots, to write the file back with reseal.
+    pub fn keep_with_key(&self, key: &PasswordKey) -> Result<FileKey, Error> {
+        self.keep(self.file_key_with_key(key)?)
+    }
+
+    pub fn keep_with_identity(&self, identity: &Identity) -> Result<FileKey, Error> {
+        self.keep(self.file_key_with_identity(identity)?)
+    }
+
+    fn keep(&self, file_key: Zeroizing<Vec<u8>>) -> Result<FileKey, Error> {
+        self.check_mac(&file_key)?;
+        memory::lock(&file_key);
+        Ok(FileKey {
+            params: self.params,
+            key: file_key,
+            slots: self.slots.clone(),
+        })
+    }
+
+    pub fn history_with_key(&self, key: &PasswordKey) -> Result<Vec<Revision>, Error> {
+        self.open_history(&self.file_key_with_key(key)?)
+    }
+
+    pub fn history_with_identity(&self, identity: &Identity) -> Result<Vec<Revision>, Error> {
+        self.open_history(&self.file_key_with_identity(identity)?)
+    }
+
+    fn file_key_with_key(&self, key: &PasswordKey) -> Result<Zeroizing<Vec<u8>>, Error> {
+        if key.params != self.params {
+            return Err(Error::NoMatchingKey);
+        }
+        password_slot(&self.slots, key, &self.authenticated[..HEADER_LEN])
+            .ok_or(Error::NoMatchingKey)
+    }
+
+    fn file_key_with_identity(&self, identity: &Identity) -> Result<Zeroizing<Vec<u8>>, Error> {
+        let aad = &self.authenticated[..HEADER_LEN];
+        for slot in &self.slots {
+            if let Slot::Hybrid {
+                encapsulation,
+                sealed,
+            } = slot
+            {
+                if let Some(key) = identity.unwrap(encapsulation) {
+                    if let Some(file_key) = crypto::open(&*key, &[0; NONCE_LEN], aad, sealed) {
+                        return Ok(file_key);
+                    }
+                }
+            }
+        }
+        Err(Error::NoMatchingKey)
+    }
+
+    fn check_mac(&self, file_key: &[u8]) -> Result<Zeroizing<Vec<u8>>, Error> {
+        let keys = body_keys(file_key, self.salt());
+        let mac = crypto::hmac_sha256(&keys[48..], &[&self.authenticated]);
+        if !fixed_time_eq(&mac, &self.mac) {
+            return Err(Error::Integrity);
+        }
+        Ok(keys)
+    }
+
+    fn salt(&self) -> &[u8] {
+        self.salt.as_deref().unwrap_or_default()
+    }
+
+    fn open_body(&self, file_key: &[u8]) -> Result<Vec<u8>, Error> {
+        let keys = self.check_mac(file_key)?;
+        crypto::decrypt_with(self.params.cipher, &keys[16..48], &keys[..16], &self.body)
+            .map_err(|_| Error::Cipher)
+    }
+
+    fn open_history(&self, file_key: &[u8]) -> Result<Vec<Revision>, Error> {
+        self.check_mac(file_key)?;
+        let history = match self.history {
+            Some(ref history) => history,
+            None => return Ok(Vec::new()),
+        };
+        let keys = history_keys(file_key, self.salt());
+        let encoded = crypto::decrypt_with(self.params.cipher, &keys[16..], &keys[..16], history)
+            .map(Zeroizing::new)
+            .map_err(|_| Error::Cipher)?;
+        history::decode(&encoded).ok_or(Error::Format)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn params() -> Params {
+        Params {
+            cipher: Cipher::CHACHA20,
+            hash: Hash::SHA2_256,
+            cost: Cost::LOW,
+            kdf: KDF::PBKDF2,
+        }
+    }
+
+    #[test]
+    fn test_password_roundtrip() {
+        let data = seal(params(), Some("hello"), &[], None, b"secret").unwrap();
+        assert!(is_container(&data));
+        let container = Container::parse(&data).unwrap();
+        assert_eq!(container.params, params());
+        assert!(container.has_password_slot());
+        assert!(!container.has_recipient_slot());
+        assert_eq!(container.open_with_password("hello").unwrap(), b"secret");
+        assert_eq!(
+            container.open_with_password("world").unwrap_err(),
+            Error::NoMatchingKey
+        );
+    }
+
+    #[test]
+    fn test_password_key_reuse() {
+        let data = seal(params(), Some("hello"), &[], None, b"secret").unwrap();
+        let (_, key) = Container::parse(&data)
+            .unwrap()
+            .open_with_password_key("hello")
+            .unwrap();
+        let resealed = seal_with_key(params(), Some(&key), &[], None, b"edited", &[]).unwrap();
+        let container = Container::parse(&resealed).unwrap();
+        assert_eq!(container.open_with_password("hello").unwrap(), b"edited");
+        // same salt, fresh nonce and file key
+        assert_eq!(resealed[16..32], data[16..32]);
+        assert!(resealed[32..44] != data[32..44]);
+
+        let mut other = params();
+        other.cost = Cost::MEDIUM;
+        assert_eq!(
+            seal_with_key(other, Some(&key), &[], None, b"edited", &[]).unwrap_err(),
+            Error::NoMatchingKey
+        );
+    }
+
+    #[test]
+    fn test_history() {
+        let identity = Identity::generate();
+        let recipients = vec![Recipient::parse(&identity.recipient().to_string()).unwrap()];
+        let key = PasswordKey::new(params(), "hello");
+        let history = vec![Revision::now("first"), Revision::now("")];
+        let mut data =
+            seal_with_key(params(), Some(&key), &recipients, None, b"third", &history).unwrap();
+        let container = Container::parse(&data).unwrap();
+        assert_eq!(container.open_with_key(&key).unwrap(), b"third");
+        let revisions = container.history_with_key(&key).unwrap();
+        assert_eq!(revisions.len(), 2);
+        assert_eq!(revisions[0].text.as_str(), "first");
+        assert_eq!(revisions[0].time, history[0].time);
+        let revisions = container.history_with_identity(&identity).unwrap();
+        assert_eq!(revisions[1].text.as_str(), "");
+
+        let empty = seal_with_key(params(), Some(&key), &[], None, b"first", &[]).unwrap();
+        let container = Container::parse(&empty).unwrap();
+        assert!(container.history_with_key(&key).unwrap().is_empty());
+
+        // the MAC covers the history
+        let mac = data.len() - 37;
+        data[mac - 1] ^= 1;
+        let container = Container::parse(&data).unwrap();
+        assert_eq!(
+            container.history_with_key(&key).err(),
+            Some(Error::Integrity)
+        );
+    }
+
+    #[test]
+    fn test_reseal() {
+        let identity = Identity::generate();
+        let other = Identity::generate();
+        let recipients = vec![Recipient::parse(&identity.recipient().to_string()).unwrap()];
+        let added = vec![Recipient::parse(&other.recipient().to_string()).unwrap()];
+        let key = PasswordKey::new(params(), "hello");
+        let data = seal_with_key(params(), Some(&key), &recipients, None, b"first", &[]).unwrap();
+
+        // saved by a recipient, the file still opens with the password
+        let file_key = Container::parse(&data)
+            .unwrap()
+            .keep_with_identity(&identity)
+            .unwrap();
+        assert!(file_key.has_recipient_slot());
+        let history = vec![Revision::now("first")];
+        let resealed = reseal(&file_key, &added, None, b"second", &history).unwrap();
+        let container = Container::parse(&resealed).unwrap();
+        assert_eq!(container.open_with_key(&key).unwrap(), b"second");
+        assert_eq!(container.open_with_identity(&identity).unwrap(), b"second");
+        assert_eq!(container.open_with_identity(&other).unwrap(), b"second");
+        assert_eq!(container.history_with_key(&key).unwrap().len(), 1);
+
+        // the same file key seals each save under other body keys
+        let body = |data: Vec<u8>| data[data.len() - 37 - 6..data.len() - 37].to_vec();
+        let once = reseal(&file_key, &[], None, b"second", &[]).unwrap();
+        let twice = reseal(&file_key, &[], None, b"second", &[]).unwrap();
+        assert!(body(once) != body(twice));
+
+        // locked and unlocked with the password
+        let file_key = container.keep_with_key(&key).unwrap().forget().open(&key);
+        let third = reseal(&file_key.unwrap(), &[], None, b"third", &[]).unwrap();
+        let container = Container::parse(&third).unwrap();
+        assert_eq!(container.open_with_identity(&other).unwrap(), b"third");
+        let wrong = PasswordKey::new(params(), "hello");
+        assert!(container
+            .keep_with_key(&key)
+            .unwrap()
+            .forget()
+            .open(&wrong)
+            .is_none());
+    }
+
+    #[test]
+    fn test_recipient_roundtrip() {
+        let identity = Identity::generate();
+        let recipients = vec![Recipient::parse(&identity.recipient().to_string()).unwrap()];
+        let data = seal(params(), Some("hello"), &recipients, None, b"secret").unwrap();
+        let container = Container::parse(&data).unwrap();
+        assert!(container.has_recipient_slot());
+        assert_eq!(container.open_with_identity(&identity).unwrap(), b"secret");
+        assert_eq!(container.open_with_password("hello").unwrap(), b"secret");
+        assert_eq!(
+            container
+                .open_with_identity(&Identity::generate())
+                .unwrap_err(),
+            Error::NoMatchingKey
+        );
+    }
+
+    #[test]
+    fn test_signature() {
+        let key = SigningKey::generate();
+        let trusted = TrustList::parse(&format!(
+            "alice {}",
+            signature::public_key_to_string(key.public_key())
+        ));
+        let unsigned = seal(params(), Some("hello"), &[], None, b"secret").unwrap();
+        let container = Container::parse(&unsigned).unwrap();
+        assert_eq!(container.signature_status(&trusted), Status::Unsigned);
+
+        let mut data = seal(params(), Some("hello"), &[], Some(&key), b"secret").unwrap();
+        let container = Container::parse(&data).unwrap();
+        assert_eq!(container.open_with_password("hello").unwrap(), b"secret");
+        assert_eq!(
+            container.signature_status(&trusted),
+            Status::Trusted("alice".to_string())
+        );
+        assert_eq!(
+            container.signature_status(&TrustList::parse("")),
+            Status::Untrusted(signature::public_key_to_string(key.public_key()))
+        );
+
+        data[HEADER_LEN + 5] ^= 1;
+        let container = Container::parse(&data).unwrap();
+        assert_eq!(container.signature_status(&trusted), Status::Invalid);
+
+        let mut appended = unsigned.clone();
+        appended.extend_from_slice(&[TAG_BODY, 0, 0, 0, 0]);
+        assert_eq!(Container::parse(&appended).err(), Some(Error::Format));
+    }
+
+    #[test]
+    fn test_tampering() {
+        let mut data = seal(params(), Some("hello"), &[], None, b"secret").unwrap();
+        let body = data.len() - 32 - 5 - 1;
+        data[body] ^= 1;
+        let container = Container::parse(&data).unwrap();
+        assert_eq!(
+            container.open_with_password("hello").unwrap_err(),
+            Error::Integrity
+        );
+
+        data[9] = Cost::MEDIUM.id();
+        let container = Container::parse(&data).unwrap();
+        assert_eq!(
+            container.open_with_password("hello").unwrap_err(),
+            Error::NoMatchingKey
+        );
+
+        assert_eq!(Container::parse(b"VAULT\0").err(), Some(Error::Format));
+        assert_eq!(
+            Container::parse(&data[..data.len() - 1]).err(),
+            Some(Error::Format)
+        );
+    }
+}
@@ -2,17 +2,24 @@ use argon2;
 use cryptolib::blake2b::Blake2b;
 use cryptolib::blake2s::Blake2s;
 use cryptolib::buffer::{BufferResult, ReadBuffer, WriteBuffer};
+use cryptolib::digest::Digest;
+use cryptolib::hkdf::{hkdf_expand, hkdf_extract};
 use cryptolib::hmac::Hmac;
+use cryptolib::mac::Mac;
 use cryptolib::pbkdf2::pbkdf2;
+use cryptolib::poly1305::Poly1305;
 use cryptolib::ripemd160::Ripemd160;
 use cryptolib::sha2::{Sha256, Sha384, Sha512};
 use cryptolib::sha3::{Sha3, Sha3Mode};
-use cryptolib::{aes, blockmodes, buffer, chacha20, salsa20, symmetriccipher};
+use cryptolib::symmetriccipher::{BlockEncryptor, Decryptor, Encryptor, SynchronousStreamCipher};
+use cryptolib::util::fixed_time_eq;
+use cryptolib::{aes, aessafe, blockmodes, buffer, chacha20, salsa20, symmetriccipher};
 use rand::{thread_rng, RngCore};
 use state::cipher::Cipher;
 use state::cost::Cost;
 use state::hash::Hash;
 use state::kdf::{KDFCost, KDF};
+use zeroize::Zeroizing;
 
 #[cfg(test)]
 mod tests {
@@ -20,11 +27,11 @@ mod tests {
         ($cipher:ident, $hash:ident, $cost: ident, $kdf: ident, $pass: expr, $plaintext: expr) => {{
             println!("testing: {} {} {} {}", $cipher, $hash, $cost, $kdf);
             let (salt, ciphertext) =
-                encrypt($cipher, $hash, $cost, $kdf, $pass, $plaintext).unwrap();
+                encrypt($cipher, $hash, $cost, $kdf, &$pass, $plaintext).unwrap();
             let mut c: std::vec::Vec<u8> = std::vec::Vec::new();
             c.append(&mut salt[..].to_vec());
             c.extend(ciphertext);
-            let res = decrypt($cipher, $hash, $cost, $kdf, $pass, c).unwrap();
+            let res = decrypt($cipher, $hash, $cost, $kdf, &$pass, c).unwrap();
             assert_eq!(&res.as_slice(), $plaintext);
         }};
     }
@@ -58,6 +65,57 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_seal_open() {
+        let key = [7; 32];
+        let nonce = [1; 12];
+        let sealed = seal(&key, &nonce, b"header", b"secret");
+        assert_eq!(sealed.len(), 6 + TAG_LEN);
+        assert_eq!(*open(&key, &nonce, b"header", &sealed).unwrap(), b"secret");
+        assert!(open(&key, &nonce, b"other", &sealed).is_none());
+        assert!(open(&[8; 32], &nonce, b"header", &sealed).is_none());
+    }
+
+    #[test]
+    fn test_siv() {
+        // RFC 5297, A.1
+        let key = (0..16)
+            .map(|i| 0xff - i)
+            .chain(0xf0..=0xff)
+            .collect::<Vec<u8>>();
+        let ad = (0x10..=0x27).collect::<Vec<u8>>();
+        let plaintext = [
+            0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77, 0x88, 0x99, 0xaa, 0xbb, 0xcc, 0xdd, 0xee,
+        ];
+        let sealed = siv_seal(&key, &[&ad], &plaintext);
+        assert_eq!(
+            sealed,
+            [
+                0x85, 0x63, 0x2d, 0x07, 0xc6, 0xe8, 0xf3, 0x7f, 0x95, 0x0a, 0xcd, 0x32, 0x0a, 0x2e,
+                0xcc, 0x93, 0x40, 0xc0, 0x2b, 0x96, 0x90, 0xc4, 0xdc, 0x04, 0xda, 0xef, 0x7f, 0x6a,
+                0xfe, 0x5c,
+            ]
+        );
+        assert_eq!(*siv_open(&key, &[&ad], &sealed).unwrap(), plaintext);
+        assert!(siv_open(&key, &[b"other"], &sealed).is_none());
+
+        let key = [9; 64];
+        let sealed = siv_seal(&key, &[b"path"], b"a value longer than one block");
+        assert_eq!(
+            sealed,
+            siv_seal(&key, &[b"path"], b"a value longer than one block")
+        );
+        assert_eq!(
+            *siv_open(&key, &[b"path"], &sealed).unwrap(),
+            b"a value longer than one block"
+        );
+        assert!(siv_open(&key, &[b"path"], &sealed[1..]).is_none());
+        assert_eq!(
+            *siv_open(&key, &[], &siv_seal(&key, &[], b"")).unwrap(),
+            b""
+        );
+    }
+
     #[test]
     fn test_argon2_encryption() {
         let cost = Cost::LOW;
@@ -85,12 +143,14 @@ fn xor(v1: &[u8], v2: &[u8], res: &mut [u8]) {
     }
 }
 
+// The output is allocated up front: growing it would leave copies of the
+// plaintext behind in freed memory. CBC padding adds at most one block.
 macro_rules! cipher {
     ($data:ident,$func:ident,$op:ident) => {{
-        let mut final_result = Vec::<u8>::new();
+        let mut final_result = Vec::<u8>::with_capacity($data.len() + 16);
         let mut read_buffer = buffer::RefReadBuffer::new($data);
-        let mut buffer = [0; 4096];
-        let mut write_buffer = buffer::RefWriteBuffer::new(&mut buffer);
+        let mut buffer = Zeroizing::new(vec![0; 4096]);
+        let mut write_buffer = buffer::RefWriteBuffer::new(&mut *buffer);
 
         loop {
             let result = $func.$op(&mut read_buffer, &mut write_buffer, true)?;
@@ -110,38 +170,72 @@ macro_rules! cipher {
     }};
 }
 
+// Vault keys are always 256 bits, other formats bring their own.
+fn aes_key_size(key: &[u8]) -> aes::KeySize {
+    match key.len() {
+        16 => aes::KeySize::KeySize128,
+        24 => aes::KeySize::KeySize192,
+        _ => aes::KeySize::KeySize256,
+    }
+}
+
+fn encryptor(cipher: Cipher, key: &[u8], iv: &[u8]) -> Box<dyn Encryptor> {
+    match cipher {
+        Cipher::AESCBC => aes::cbc_encryptor(aes_key_size(key), key, iv, blockmodes::PkcsPadding),
+        Cipher::CHACHA20 => Box::new(chacha20::ChaCha20::new(key, &iv[0..12])),
+        Cipher::SALSA20 => Box::new(salsa20::Salsa20::new(key, &iv[0..8])),
+    }
+}
+
+fn decryptor(cipher: Cipher, key: &[u8], iv: &[u8]) -> Box<dyn Decryptor> {
+    match cipher {
+        Cipher::AESCBC => aes::cbc_decryptor(aes_key_size(key), key, iv, blockmodes::PkcsPadding),
+        Cipher::CHACHA20 => Box::new(chacha20::ChaCha20::new(key, &iv[0..12])),
+        Cipher::SALSA20 => Box::new(salsa20::Salsa20::new(key, &iv[0..8])),
+    }
+}
+
+pub fn encrypt_with(
+    cipher: Cipher,
+    key: &[u8],
+    iv: &[u8],
+    plaintext: &[u8],
+) -> Result<Vec<u8>, symmetriccipher::SymmetricCipherError> {
+    let mut encryptor = encryptor(cipher, key, iv);
+    cipher!(plaintext, encryptor, encrypt)
+}
+
+pub fn decrypt_with(
+    cipher: Cipher,
+    key: &[u8],
+    iv: &[u8],
+    ciphertext: &[u8],
+) -> Result<Vec<u8>, symmetriccipher::SymmetricCipherError> {
+    let mut decryptor = decryptor(cipher, key, iv);
+    cipher!(ciphertext, decryptor, decrypt)
+}
+
+// Headerless format written before containers existed. The editor only
+// reads it now; tests use it to produce fixtures for `decrypt`.
+#[cfg(test)]
 pub fn encrypt(
     cipher: Cipher,
     hash: Hash,
     cost: Cost,
     kdf: KDF,
-    key: String,
+    key: &str,
     plaintext: &[u8],
 ) -> Result<([u8; 16], Vec<u8>), symmetriccipher::SymmetricCipherError> {
-    let mut rng = thread_rng();
     let mut salt: [u8; 16] = [0; 16];
-    let mut derived_key: [u8; 48] = [0; 48]; // 384bits
+    let mut derived_key = Zeroizing::new([0; 48]); // 384bits
 
-    rng.fill_bytes(&mut salt);
-    derive_key(hash, kdf, key, &salt, cost, &mut derived_key);
+    random_bytes(&mut salt);
+    derive_key(hash, kdf, key, &salt, cost, &mut *derived_key);
 
     let mut iv: [u8; 16] = [0; 16];
     xor(&salt, &derived_key[0..16], &mut iv);
 
-    let key_slice = &derived_key[16..48];
-
-    let mut encryptor = match cipher {
-        Cipher::AESCBC => aes::cbc_encryptor(
-            aes::KeySize::KeySize256,
-            key_slice,
-            &iv,
-            blockmodes::PkcsPadding,
-        ),
-        Cipher::CHACHA20 => Box::new(chacha20::ChaCha20::new(key_slice, &iv[0..12])),
-        Cipher::SALSA20 => Box::new(salsa20::Salsa20::new(key_slice, &iv[0..8])),
-    };
-
-    let ciphertext = cipher!(plaintext, encryptor, encrypt)?;
+    let ciphertext = encrypt_with(cipher, &derived_key[16..48], &iv, plaintext)?;
     Ok((salt, ciphertext))
 }
 
@@ -150,81 +244,348 @@ pub fn decrypt(
     hash: Hash,
     cost: Cost,
     kdf: KDF,
-    key: String,
+    key: &str,
     data: Vec<u8>,
 ) -> Result<Vec<u8>, symmetriccipher::SymmetricCipherError> {
-    let mut derived_key: [u8; 48] = [0; 48]; // 384bits
-    assert!(data.len() > 16);
+    let mut derived_key = Zeroizing::new([0; 48]); // 384bits
+    if data.len() <= 16 {
+        return Err(symmetriccipher::SymmetricCipherError::InvalidLength);
+    }
     let (salt, ciphertext) = data.split_at(16);
 
-    derive_key(hash, kdf, key, salt, cost, &mut derived_key);
+    derive_key(hash, kdf, key, salt, cost, &mut *derived_key);
 
     let mut iv: [u8; 16] = [0; 16];
     xor(&salt, &derived_key[0..16], &mut iv);
 
-    let encrypted_text = &ciphertext[..];
-    let key_slice = &derived_key[16..48];
-
-    let mut decryptor = match cipher {
-        Cipher::AESCBC => aes::cbc_decryptor(
-            aes::KeySize::KeySize256,
-            key_slice,
-            &iv,
-            blockmodes::PkcsPadding,
-        ),
-        Cipher::CHACHA20 => Box::new(chacha20::ChaCha20::new(key_slice, &iv[0..12])),
-        Cipher::SALSA20 => Box::new(salsa20::Salsa20::new(key_slice, &iv[0..8])),
+    decrypt_with(cipher, &derived_key[16..48], &iv, ciphertext)
+}
+
+pub const TAG_LEN: usize = 16;
+
+pub fn random_bytes(buf: &mut [u8]) {
+    thread_rng().fill_bytes(buf);
+}
+
+// ChaCha20-Poly1305 as specified in RFC 8439 (96-bit nonce). The returned
+// ciphertext carries the 16 byte tag at its end.
+pub fn seal(key: &[u8], nonce: &[u8], aad: &[u8], plaintext: &[u8]) -> Vec<u8> {
+    let mut chacha = chacha20::ChaCha20::new(key, nonce);
+    let mut poly_key = Zeroizing::new([0; 64]);
+    chacha.process(&[0; 64], &mut *poly_key);
+
+    let mut sealed = vec![0; plaintext.len() + TAG_LEN];
+    chacha.process(plaintext, &mut sealed[..plaintext.len()]);
+    let tag = poly1305_tag(&poly_key[..32], aad, &sealed[..plaintext.len()]);
+    sealed[plaintext.len()..].copy_from_slice(&tag);
+    sealed
+}
+
+pub fn open(key: &[u8], nonce: &[u8], aad: &[u8], sealed: &[u8]) -> Option<Zeroizing<Vec<u8>>> {
+    if sealed.len() < TAG_LEN {
+        return None;
+    }
+    let (ciphertext, tag) = sealed.split_at(sealed.len() - TAG_LEN);
+    let mut chacha = chacha20::ChaCha20::new(key, nonce);
+    let mut poly_key = Zeroizing::new([0; 64]);
+    chacha.process(&[0; 64], &mut *poly_key);
+
+    if !fixed_time_eq(&poly1305_tag(&poly_key[..32], aad, ciphertext), tag) {
+        return None;
+    }
+    let mut plaintext = Zeroizing::new(vec![0; ciphertext.len()]);
+    chacha.process(ciphertext, &mut plaintext);
+    Some(plaintext)
+}
+
+fn poly1305_tag(key: &[u8], aad: &[u8], ciphertext: &[u8]) -> [u8; TAG_LEN] {
+    let padding = [0; 16];
+    let mut poly = Poly1305::new(key);
+    poly.input(aad);
+    poly.input(&padding[..(16 - aad.len() % 16) % 16]);
+    poly.input(ciphertext);
+    poly.input(&padding[..(16 - ciphertext.len() % 16) % 16]);
+    poly.input(&(aad.len() as u64).to_le_bytes());
+    poly.input(&(ciphertext.len() as u64).to_le_bytes());
+    let mut tag = [0; TAG_LEN];
+    poly.raw_result(&mut tag);
+    tag
+}
+
+pub const SIV_LEN: usize = 16;
+
+// AES-SIV as specified in RFC 5297, with a 32 or 64 byte key for AES-128 or
+// AES-256. It is deterministic: the same key, associated data and plaintext
+// always seal to the same synthetic IV and ciphertext, which follows it.
+pub fn siv_seal(key: &[u8], ad: &[&[u8]], plaintext: &[u8]) -> Vec<u8> {
+    let (mac_key, ctr_key) = key.split_at(key.len() / 2);
+    let iv = s2v(mac_key, ad, plaintext);
+    let mut sealed = vec![0; SIV_LEN + plaintext.len()];
+    sealed[..SIV_LEN].copy_from_slice(&iv);
+    siv_ctr(ctr_key, &iv).process(plaintext, &mut sealed[SIV_LEN..]);
+    sealed
+}
+
+pub fn siv_open(key: &[u8], ad: &[&[u8]], sealed: &[u8]) -> Option<Zeroizing<Vec<u8>>> {
+    if sealed.len() < SIV_LEN {
+        return None;
+    }
+    let (mac_key, ctr_key) = key.split_at(key.len() / 2);
+    let (iv, ciphertext) = sealed.split_at(SIV_LEN);
+    let mut plaintext = Zeroizing::new(vec![0; ciphertext.len()]);
+    siv_ctr(ctr_key, iv).process(ciphertext, &mut plaintext);
+    if !fixed_time_eq(&s2v(mac_key, ad, &plaintext), iv) {
+        return None;
+    }
+    Some(plaintext)
+}
+
+fn aes_encryptor(key: &[u8]) -> Box<dyn BlockEncryptor> {
+    match key.len() {
+        16 => Box::new(aessafe::AesSafe128Encryptor::new(key)),
+        _ => Box::new(aessafe::AesSafe256Encryptor::new(key)),
+    }
+}
+
+// The counter starts from the synthetic IV with two bits cleared, so that
+// implementations with a 64 or 32 bit counter agree.
+fn siv_ctr(key: &[u8], iv: &[u8]) -> Box<dyn SynchronousStreamCipher> {
+    let mut counter = [0; SIV_LEN];
+    counter.copy_from_slice(iv);
+    counter[8] &= 0x7f;
+    counter[12] &= 0x7f;
+    let key_size = match key.len() {
+        16 => aes::KeySize::KeySize128,
+        _ => aes::KeySize::KeySize256,
     };
+    aes::ctr(key_size, key, &counter)
+}
 
-    cipher!(encrypted_text, decryptor, decrypt)
+// Multiplication by x in GF(2^128).
+fn dbl(block: &mut [u8; 16]) {
+    let carry = block[0] >> 7;
+    for i in 0..15 {
+        block[i] = (block[i] << 1) | (block[i + 1] >> 7);
+    }
+    block[15] = (block[15] << 1) ^ (carry * 0x87);
+}
+
+fn xor_into(block: &mut [u8; 16], data: &[u8]) {
+    for (b, d) in block.iter_mut().zip(data) {
+        *b ^= d;
+    }
+}
+
+// CMAC from RFC 4493.
+fn cmac(aes: &dyn BlockEncryptor, data: &[u8]) -> [u8; 16] {
+    let mut subkey = [0; 16];
+    aes.encrypt_block(&[0; 16], &mut subkey);
+    dbl(&mut subkey);
+    let complete = !data.is_empty() && data.len() % 16 == 0;
+    if !complete {
+        dbl(&mut subkey);
+    }
+    let last_start = if complete {
+        data.len() - 16
+    } else {
+        data.len() / 16 * 16
+    };
+    let mut last = Zeroizing::new([0; 16]);
+    let tail = &data[last_start..];
+    last[..tail.len()].copy_from_slice(tail);
+    if !complete {
+        last[tail.len()] = 0x80;
+    }
+    xor_into(&mut last, &subkey);
+
+    let mut state = [0; 16];
+    let mut input = Zeroizing::new([0; 16]);
+    for block in data[..last_start].chunks(16).chain(Some(&last[..])) {
+        input.copy_from_slice(&state);
+        xor_into(&mut input, block);
+        aes.encrypt_block(&*input, &mut state);
+    }
+    state
+}
+
+// S2V from RFC 5297, over the associated data and then the plaintext.
+fn s2v(key: &[u8], ad: &[&[u8]], plaintext: &[u8]) -> [u8; 16] {
+    let aes = aes_encryptor(key);
+    let mut d = cmac(&*aes, &[0; 16]);
+    for data in ad {
+        dbl(&mut d);
+        xor_into(&mut d, &cmac(&*aes, data));
+    }
+    // room for the padding, so the copy of the plaintext never moves
+    let mut last = Zeroizing::new(Vec::with_capacity(plaintext.len().max(16)));
+    last.extend_from_slice(plaintext);
+    if plaintext.len() >= 16 {
+        let end = last.len() - 16;
+        for (l, d) in last[end..].iter_mut().zip(&d) {
+            *l ^= d;
+        }
+    } else {
+        dbl(&mut d);
+        last.push(0x80);
+        last.resize(16, 0);
+        for (l, d) in last.iter_mut().zip(&d) {
+            *l ^= d;
+        }
+    }
+    cmac(&*aes, &last)
+}
+
+pub fn hkdf_sha256(salt: &[u8], ikm: &[u8], info: &[u8], okm: &mut [u8]) {
+    let mut prk = Zeroizing::new([0; 32]);
+    hkdf_extract(Sha256::new(), salt, ikm, &mut *prk);
+    hkdf_expand(Sha256::new(), &*prk, info, okm);
+}
+
+pub fn hmac_sha256(key: &[u8], data: &[&[u8]]) -> [u8; 32] {
+    let mut hmac = Hmac::new(Sha256::new(), key);
+    for d in data {
+        hmac.input(d);
+    }
+    let mut code = [0; 32];
+    hmac.raw_result(&mut code);
+    code
 }
 
 macro_rules! hmac_digest {
     ($digest:expr,$key:ident,$salt:ident,$iter:ident,$derived_key:ident) => {{
-        let mut hmac = Hmac::new($digest, $key.as_bytes());
+        let mut hmac = Hmac::new($digest, $key);
         pbkdf2(&mut hmac, &$salt[..], $iter, $derived_key);
     }};
 }
 
-fn derive_key(hash: Hash, kdf: KDF, key: String, salt: &[u8], cost: Cost, derived_key: &mut [u8]) {
+// Derives `derived_key.len()` bytes from the password. This is what key
+// slots use; `derive_key` is only kept for headerless files.
+pub fn derive(hash: Hash, kdf: KDF, cost: Cost, key: &[u8], salt: &[u8], derived_key: &mut [u8]) {
     match kdf.cost(cost) {
-        KDFCost::PBKDF2(cost) => {
-            match hash {
-                Hash::RIPEMD160 => hmac_digest!(Ripemd160::new(), key, salt, cost, derived_key),
-                Hash::BLAKE2S => hmac_digest!(Blake2s::new(32), key, salt, cost, derived_key), // 256bits
-                Hash::BLAKE2B => hmac_digest!(Blake2b::new(64), key, salt, cost, derived_key), // 512bits
-                Hash::SHA2_256 => hmac_digest!(Sha256::new(), key, salt, cost, derived_key),
-                Hash::SHA2_384 => hmac_digest!(Sha384::new(), key, salt, cost, derived_key),
-                Hash::SHA2_512 => hmac_digest!(Sha512::new(), key, salt, cost, derived_key),
-                Hash::SHA3_256 => {
-                    hmac_digest!(Sha3::new(Sha3Mode::Sha3_256), key, salt, cost, derived_key)
-                }
-                Hash::SHA3_384 => {
-                    hmac_digest!(Sha3::new(Sha3Mode::Sha3_384), key, salt, cost, derived_key)
-                }
-                Hash::SHA3_512 => {
-                    hmac_digest!(Sha3::new(Sha3Mode::Sha3_512), key, salt, cost, derived_key)
-                }
-            }
+        KDFCost::PBKDF2(cost) => pbkdf2_hmac(hash, cost, key, salt, derived_key, false),
+        KDFCost::ARGON2(mem_cost, time_cost) => {
+            let config = argon2_config(mem_cost, time_cost, derived_key.len() as u32);
+            let hash = Zeroizing::new(argon2::hash_raw(key, salt, &config).unwrap());
+            derived_key.copy_from_slice(&hash);
         }
+    }
+}
+
+// PBKDF2 as other implementations compute it. Vault files keep the BLAKE2
+// block sizes of rust-crypto, see `Blake2Block`.
+pub fn pbkdf2_with(hash: Hash, iter: u32, key: &[u8], salt: &[u8], derived_key: &mut [u8]) {
+    pbkdf2_hmac(hash, iter, key, salt, derived_key, true)
+}
+
+// rust-crypto gives the BLAKE2 block sizes in bits, which HMAC then takes as
+// bytes. This reports the real block size instead.
+struct Blake2Block<D: Digest>(D, usize);
+
+impl<D: Digest> Digest for Blake2Block<D> {
+    fn input(&mut self, input: &[u8]) {
+        self.0.input(input)
+    }
+
+    fn result(&mut self, out: &mut [u8]) {
+        self.0.result(out)
+    }
+
+    fn reset(&mut self) {
+        self.0.reset()
+    }
+
+    fn output_bits(&self) -> usize {
+        self.0.output_bits()
+    }
+
+    fn block_size(&self) -> usize {
+        self.1
+    }
+}
+
+fn pbkdf2_hmac(
+    hash: Hash,
+    iter: u32,
+    key: &[u8],
+    salt: &[u8],
+    derived_key: &mut [u8],
+    blake2_block: bool,
+) {
+    match hash {
+        Hash::RIPEMD160 => hmac_digest!(Ripemd160::new(), key, salt, iter, derived_key),
+        Hash::BLAKE2S if blake2_block => {
+            hmac_digest!(
+                Blake2Block(Blake2s::new(32), 64),
+                key,
+                salt,
+                iter,
+                derived_key
+            )
+        }
+        Hash::BLAKE2B if blake2_block => {
+            hmac_digest!(
+                Blake2Block(Blake2b::new(64), 128),
+                key,
+                salt,
+                iter,
+                derived_key
+            )
+        }
+        Hash::BLAKE2S => hmac_digest!(Blake2s::new(32), key, salt, iter, derived_key), // 256bits
+        Hash::BLAKE2B => hmac_digest!(Blake2b::new(64), key, salt, iter, derived_key), // 512bits
+        Hash::SHA2_256 => hmac_digest!(Sha256::new(), key, salt, iter, derived_key),
+        Hash::SHA2_384 => hmac_digest!(Sha384::new(), key, salt, iter, derived_key),
+        Hash::SHA2_512 => hmac_digest!(Sha512::new(), key, salt, iter, derived_key),
+        Hash::SHA3_256 => hmac_digest!(Sha3::new(Sha3Mode::Sha3_256), key, salt, iter, derived_key),
+        Hash::SHA3_384 => hmac_digest!(Sha3::new(Sha3Mode::Sha3_384), key, salt, iter, derived_key),
+        Hash::SHA3_512 => hmac_digest!(Sha3::new(Sha3Mode::Sha3_512), key, salt, iter, derived_key),
+    }
+}
+
+pub fn digest(hash: Hash, data: &[&[u8]]) -> Vec<u8> {
+    let mut digest: Box<dyn Digest> = match hash {
+        Hash::RIPEMD160 => Box::new(Ripemd160::new()),
+        Hash::BLAKE2S => Box::new(Blake2s::new(32)),
+        Hash::BLAKE2B => Box::new(Blake2b::new(64)),
+        Hash::SHA2_256 => Box::new(Sha256::new()),
+        Hash::SHA2_384 => Box::new(Sha384::new()),
+        Hash::SHA2_512 => Box::new(Sha512::new()),
+        Hash::SHA3_256 => Box::new(Sha3::new(Sha3Mode::Sha3_256)),
+        Hash::SHA3_384 => Box::new(Sha3::new(Sha3Mode::Sha3_384)),
+        Hash::SHA3_512 => Box::new(Sha3::new(Sha3Mode::Sha3_512)),
+    };
+    for d in data {
+        digest.input(d);
+    }
+    let mut out = vec![0; digest.output_bytes()];
+    digest.result(&mut out);
+    out
+}
+
+fn argon2_config<'a>(mem_cost: u32, time_cost: u32, hash_length: u32) -> argon2::Config<'a> {
+    argon2::Config {
+        variant: argon2::Variant::Argon2i,
+        version: argon2::Version::Version13,
+        mem_cost,
+        time_cost,
+        lanes: 4,
+        thread_mode: argon2::ThreadMode::Parallel,
+        secret: &[],
+        ad: &[],
+        hash_length,
+    }
+}
+
+fn derive_key(hash: Hash, kdf: KDF, key: &str, salt: &[u8], cost: Cost, derived_key: &mut [u8]) {
+    match kdf.cost(cost) {
+        KDFCost::PBKDF2(_) => derive(hash, kdf, cost, key.as_bytes(), salt, derived_key),
         KDFCost::ARGON2(mem_cost, time_cost) => {
-            let config = argon2::Config {
-                variant: argon2::Variant::Argon2i,
-                version: argon2::Version::Version13,
-                mem_cost,
-                time_cost,
-                lanes: 4,
-                thread_mode: argon2::ThreadMode::Parallel,
-                secret: &[],
-                ad: &[],
-                hash_length: 48,
-            };
-
-            let hash = argon2::hash_encoded(key.as_bytes(), salt, &config).unwrap();
+            // Headerless files took the key from the start of the encoded
+            // hash string, which only covers the parameters and the salt.
+            let config = argon2_config(mem_cost, time_cost, 48);
+            let hash = Zeroizing::new(argon2::hash_encoded(key.as_bytes(), salt, &config).unwrap());
             let bytes = hash.as_bytes();
-            for i in 0..48 {
-                derived_key[i] = bytes[i];
-            }
+            derived_key[..48].copy_from_slice(&bytes[..48]);
         }
     }
 }
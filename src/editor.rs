@@ -1,25 +1,44 @@
+use gdk;
 use gdk_pixbuf::Pixbuf;
 use glib::{Receiver, Sender};
 use gtk::prelude::*;
 use gtk::{
-    Adjustment, ButtonsType, DialogFlags, Entry, EntryBuffer, FileChooserAction, FileChooserDialog,
-    Label, Menu, MenuBar, MenuItem, MessageDialog, MessageType, PolicyType, ResponseType,
-    ScrolledWindow, TextBuffer, TextTagTable, TextView, WindowPosition, WrapMode,
+    Adjustment, Align, Button, ButtonsType, CheckButton, CheckMenuItem, ComboBoxText, Dialog,
+    DialogFlags, Entry, EntryBuffer, FileChooserAction, FileChooserDialog, Label, Menu, MenuBar,
+    MenuItem, MessageDialog, MessageType, PolicyType, ResponseType, ScrolledWindow, Stack,
+    TextBuffer, TextIter, TextTag, TextTagTable, TextView, WindowPosition, WrapMode,
 };
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
 use std::fs;
 use std::fs::File;
 use std::io::prelude::*;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
 use std::rc::Rc;
+use std::time::{Duration, Instant};
 
 use state::cipher::Cipher;
 use state::cost::Cost;
+use state::format::Format;
 use state::hash::Hash;
 use state::kdf::KDF;
 
+use age;
 use config;
+use container;
+use fernet;
+use history;
+use history::Revision;
+use openssl;
+use recipient::{Identity, Recipient};
+use section;
+use signature::SigningKey;
 use state;
+use state::Updater;
 use string;
+use string::StaticStr;
+use structured;
+use zeroize::Zeroizing;
 
 macro_rules! clear_buffer {
     ($buffer:ident) => {{
@@ -28,23 +47,626 @@ macro_rules! clear_buffer {
     }};
 }
 
-macro_rules! close_handler {
-    ($window:ident) => (
-        let flags = DialogFlags::empty();
-        let dialog = MessageDialog::new(Some(&$window), flags, MessageType::Question, ButtonsType::YesNo, "Are you sure you want to exit?");
-        dialog.connect_response(glib::clone!(@strong $window => move |_, resp| {
-            if resp == ResponseType::Yes.into() {
-                unsafe { $window.destroy() };
+pub enum Action {
+    // update text
+    UpdateTextView(Zeroizing<String>),
+    // the document was written
+    Saved,
+    // the document could not be written, and why
+    Failed(String),
+}
+
+// Applies what came back from state::State::action to the buffer. Returns
+// false when a save failed.
+fn apply(
+    window: &gtk::ApplicationWindow,
+    text_buffer: &TextBuffer,
+    action: Option<Action>,
+) -> bool {
+    match action {
+        Some(Action::UpdateTextView(text)) => {
+            clear_buffer!(text_buffer);
+            text_buffer.set_text(text.as_str());
+            text_buffer.set_modified(false);
+        }
+        Some(Action::Saved) => text_buffer.set_modified(false),
+        Some(Action::Failed(message)) => {
+            error_dialog(window, &message);
+            return false;
+        }
+        None => {}
+    }
+    true
+}
+
+enum Key {
+    Password(Zeroizing<String>),
+    Identity(Box<Identity>),
+    AgeIdentities(Vec<age::Identity>),
+}
+
+const RESPONSE_IDENTITY: ResponseType = ResponseType::Other(1);
+
+const UNLOCK_MESSAGE: &str = "Locked. Enter the document password to continue.";
+const UNLOCK_SECTIONS_MESSAGE: &str = "Locked. Enter a section password to continue.";
+
+// seconds between autosaves of unsaved changes
+const AUTOSAVE_EVERY: u32 = 30;
+
+fn error_dialog(window: &gtk::ApplicationWindow, message: &str) {
+    let flags = DialogFlags::empty();
+    let dialog = MessageDialog::new(
+        Some(window),
+        flags,
+        MessageType::Error,
+        ButtonsType::Ok,
+        message,
+    );
+    dialog.run();
+    dialog.close();
+}
+
+fn choose_file(
+    window: &gtk::ApplicationWindow,
+    title: &str,
+    action: FileChooserAction,
+) -> Option<PathBuf> {
+    let dialog = FileChooserDialog::new(Some(title), Some(window), action);
+    dialog.add_button("Open", ResponseType::Ok);
+    dialog.add_button("Cancel", ResponseType::Cancel);
+    let path = match dialog.run() {
+        ResponseType::Ok => dialog.get_filename(),
+        _ => None,
+    };
+    dialog.close();
+    path
+}
+
+fn choose_identity(window: &gtk::ApplicationWindow) -> Option<Identity> {
+    let path = choose_file(window, "Choosing identity...", FileChooserAction::Open)?;
+    match fs::read_to_string(&path)
+        .ok()
+        .as_ref()
+        .and_then(|s| Identity::parse(s))
+    {
+        Some(identity) => Some(identity),
+        None => {
+            error_dialog(
+                window,
+                &format!("{} is not a vault identity", path.display()),
+            );
+            None
+        }
+    }
+}
+
+fn choose_age_identities(window: &gtk::ApplicationWindow) -> Option<Vec<age::Identity>> {
+    let path = choose_file(window, "Choosing identity...", FileChooserAction::Open)?;
+    match fs::read_to_string(&path)
+        .ok()
+        .as_ref()
+        .and_then(|s| age::Identity::parse_list(s))
+    {
+        Some(identities) => Some(identities),
+        None => {
+            error_dialog(
+                window,
+                &format!("{} does not hold age identities", path.display()),
+            );
+            None
+        }
+    }
+}
+
+// Asks for a password, optionally offering to pick an identity file instead.
+fn key_dialog(window: &gtk::ApplicationWindow, message: &str, identity: bool) -> Option<Key> {
+    let entry_buffer = EntryBuffer::new(None);
+    let entry = Entry::with_buffer(&entry_buffer);
+    entry.set_visibility(false);
+    entry.set_activates_default(true);
+    entry.show();
+    let flags = DialogFlags::empty();
+    let pass_dialog = MessageDialog::new(
+        Some(window),
+        flags,
+        MessageType::Question,
+        ButtonsType::OkCancel,
+        message,
+    );
+    if identity {
+        pass_dialog.add_button("Identity file...", RESPONSE_IDENTITY);
+    }
+    pass_dialog.set_default_response(ResponseType::Ok);
+    let content_area = pass_dialog.get_content_area();
+    content_area.pack_start(&entry, true, true, 0);
+    let resp = pass_dialog.run();
+    let key = Zeroizing::new(entry_buffer.get_text());
+    entry_buffer.delete_text(0, Some(entry_buffer.get_length()));
+    pass_dialog.close();
+    match resp {
+        ResponseType::Ok => Some(Key::Password(key)),
+        RESPONSE_IDENTITY => {
+            choose_identity(window).map(|identity| Key::Identity(Box::new(identity)))
+        }
+        _ => None,
+    }
+}
+
+fn text_dialog(window: &gtk::ApplicationWindow, message: &str, text: &str) -> Option<String> {
+    let entry = Entry::new();
+    entry.set_text(text);
+    entry.set_activates_default(true);
+    entry.show();
+    let flags = DialogFlags::empty();
+    let dialog = MessageDialog::new(
+        Some(window),
+        flags,
+        MessageType::Question,
+        ButtonsType::OkCancel,
+        message,
+    );
+    dialog.set_default_response(ResponseType::Ok);
+    dialog.get_content_area().pack_start(&entry, true, true, 0);
+    let resp = dialog.run();
+    let text = entry.get_text().to_string();
+    dialog.close();
+    match resp {
+        ResponseType::Ok => Some(text),
+        _ => None,
+    }
+}
+
+// Asks where and how to save the document. Returns the action that writes
+// it, or None if the user backed out.
+fn save_as(
+    window: &gtk::ApplicationWindow,
+    text_buffer: &TextBuffer,
+    state: &RefCell<state::State>,
+    label: &Label,
+) -> Option<state::Action> {
+    let dialog = FileChooserDialog::new(
+        Some("Saving file..."),
+        Some(window),
+        FileChooserAction::Save,
+    );
+    // suggest writing back to the file that was opened
+    if let Some(path) = state.borrow().path() {
+        dialog.set_filename(path);
+    }
+    // output format, starting from the one picked in the Format menu
+    let formats = ComboBoxText::new();
+    for format in Format::ALL.iter() {
+        formats.append(Some(format.as_static_str()), format.as_static_str());
+    }
+    formats.set_active_id(Some(state.borrow().format().as_static_str()));
+    formats.show();
+    dialog.set_extra_widget(&formats);
+    dialog.add_button("Save", ResponseType::Ok);
+    dialog.add_button("Cancel", ResponseType::Cancel);
+    let path = match dialog.run() {
+        ResponseType::Ok => dialog.get_filename(),
+        _ => None,
+    };
+    dialog.close();
+    let path = path?;
+
+    if let Some(format) = formats
+        .get_active_id()
+        .and_then(|id| id.parse::<Format>().ok())
+    {
+        state.borrow_mut().update(format.update());
+        label.set_label(&state.borrow().to_string());
+    }
+    encrypt(window, text_buffer, state, path)
+}
+
+// Writes the document back to the file it came from, in the same format and
+// with the same parameters, asking only for the key unless the session has it.
+fn save(
+    window: &gtk::ApplicationWindow,
+    text_buffer: &TextBuffer,
+    state: &RefCell<state::State>,
+    label: &Label,
+) -> Option<state::Action> {
+    let path = state.borrow().path().map(Path::to_path_buf);
+    match path {
+        Some(path) if state.borrow().has_session_key() => {
+            if !check_recipients(window, state) {
+                return None;
             }
-        }));
-        dialog.run();
-        dialog.close();
-    )
+            let plaintext = document_text(window, text_buffer, state)?;
+            if !check_structured(window, state, &path, &plaintext) {
+                return None;
+            }
+            Some(state::Action::Save(plaintext))
+        }
+        Some(path) => encrypt(window, text_buffer, state, path),
+        None => save_as(window, text_buffer, state, label),
+    }
 }
 
-pub enum Action {
-    // update text
-    UpdateTextView(String),
+fn encrypt(
+    window: &gtk::ApplicationWindow,
+    text_buffer: &TextBuffer,
+    state: &RefCell<state::State>,
+    path: PathBuf,
+) -> Option<state::Action> {
+    let format = state.borrow().format();
+    if !check_recipients(window, state) {
+        return None;
+    }
+    if state.borrow().is_shared() && !shared_dialog(window) {
+        return None;
+    }
+    let plaintext = document_text(window, text_buffer, state)?;
+    if !check_structured(window, state, &path, &plaintext) {
+        return None;
+    }
+    let message = if format == Format::FERNET {
+        "Fernet key:"
+    } else {
+        "Encryption key:"
+    };
+    // an inline file is written as it is
+    let key = match format {
+        Format::INLINE => Zeroizing::new(String::new()),
+        _ => match key_dialog(window, message, false)? {
+            Key::Password(key) => key,
+            _ => return None,
+        },
+    };
+    if format == Format::FERNET && fernet::Key::parse(&key).is_none() {
+        error_dialog(window, "A Fernet key is 32 bytes in base64url");
+        return None;
+    }
+    if format == Format::AGE && key.is_empty() {
+        error_dialog(window, "age files need a passphrase");
+        return None;
+    }
+    Some(state::Action::Encrypt(key, plaintext, path))
+}
+
+// The Recipients menu holds vault recipients, which only vault files take.
+// An inline file is written as plain text around its sections.
+fn check_recipients(window: &gtk::ApplicationWindow, state: &RefCell<state::State>) -> bool {
+    let format = state.borrow().format();
+    if format == Format::VAULT || format == Format::INLINE || state.borrow().recipients().is_empty()
+    {
+        return true;
+    }
+    error_dialog(
+        window,
+        &format!(
+            "{} files can't have recipients. Clear them or save as VAULT",
+            format
+        ),
+    );
+    false
+}
+
+// A structured file needs to know from its name what it is, and its text
+// has to be that.
+fn check_structured(
+    window: &gtk::ApplicationWindow,
+    state: &RefCell<state::State>,
+    path: &Path,
+    text: &str,
+) -> bool {
+    if state.borrow().format() != Format::STRUCTURED {
+        return true;
+    }
+    let kind = state.borrow().structured_kind(path);
+    match kind {
+        Some(kind) if structured::check(kind, text).is_err() => {
+            error_dialog(window, &format!("The text is not a {:?} document", kind));
+            false
+        }
+        Some(_) => true,
+        None => {
+            error_dialog(
+                window,
+                "Structured files end in .yaml, .yml, .json, .toml or .env",
+            );
+            false
+        }
+    }
+}
+
+// The text to write: the buffer with the sections open in it sealed back
+// into their armor. A section whose text was deleted is left out.
+fn document_text(
+    window: &gtk::ApplicationWindow,
+    text_buffer: &TextBuffer,
+    state: &RefCell<state::State>,
+) -> Option<Zeroizing<String>> {
+    let text = |start: &TextIter, end: &TextIter| {
+        Zeroizing::new(
+            text_buffer
+                .get_text(start, end, true)
+                .map(|text| text.to_string())
+                .unwrap_or_default(),
+        )
+    };
+    let mut state = state.borrow_mut();
+    let opened = state.opened_sections();
+    let mut spans = (0..opened.len())
+        .filter_map(|index| {
+            let start = text_buffer.get_mark(&opened_mark(index, "start"))?;
+            let end = text_buffer.get_mark(&opened_mark(index, "end"))?;
+            Some((
+                text_buffer.get_iter_at_mark(&start),
+                text_buffer.get_iter_at_mark(&end),
+                index,
+            ))
+        })
+        .collect::<Vec<_>>();
+    spans.sort_by_key(|span| span.0.get_offset());
+
+    let mut out = Zeroizing::new(String::new());
+    let mut pos = text_buffer.get_start_iter();
+    for (start, end, index) in spans {
+        out.push_str(&text(&pos, &start));
+        let plaintext = text(&start, &end);
+        if !plaintext.is_empty() {
+            match opened[index].seal(&plaintext) {
+                Ok(armored) => out.push_str(armored),
+                Err(err) => {
+                    error_dialog(window, &err.to_string());
+                    return None;
+                }
+            }
+        }
+        pos = end;
+    }
+    out.push_str(&text(&pos, &text_buffer.get_end_iter()));
+    Some(out)
+}
+
+fn opened_mark(index: usize, edge: &str) -> String {
+    format!("opened-{}-{}", index, edge)
+}
+
+// Marks the edges of a section open in the text. Text typed at either edge
+// goes into the section.
+fn mark_opened(text_buffer: &TextBuffer, index: usize, start: &TextIter, end: &TextIter) {
+    for (edge, iter, left_gravity) in &[("start", start, true), ("end", end, false)] {
+        let name = opened_mark(index, edge);
+        if let Some(mark) = text_buffer.get_mark(&name) {
+            text_buffer.delete_mark(&mark);
+        }
+        text_buffer.create_mark(Some(&name), iter, *left_gravity);
+    }
+    text_buffer.apply_tag_by_name("section-open", start, end);
+}
+
+// Shows a section of an inline file as its text in place of its armor,
+// which `range` finds in `text`, the text of the buffer.
+fn open_in_place(
+    text_buffer: &TextBuffer,
+    state: &RefCell<state::State>,
+    text: &str,
+    range: Range<usize>,
+    opened: section::Opened,
+) {
+    let modified = text_buffer.get_modified();
+    let mut start = text_buffer.get_iter_at_offset(char_offset(text, range.start));
+    let mut end = text_buffer.get_iter_at_offset(char_offset(text, range.end));
+    text_buffer.delete(&mut start, &mut end);
+    text_buffer.insert(&mut start, opened.plaintext());
+    let first = text_buffer.get_iter_at_offset(char_offset(text, range.start));
+    let index = state.borrow_mut().open_section(opened);
+    mark_opened(text_buffer, index, &first, &start);
+    text_buffer.set_modified(modified);
+}
+
+// Whether to write a file shared with recipients under a new key, which
+// only opens for the password and the Recipients menu.
+fn shared_dialog(window: &gtk::ApplicationWindow) -> bool {
+    let dialog = MessageDialog::new(
+        Some(window),
+        DialogFlags::empty(),
+        MessageType::Warning,
+        ButtonsType::OkCancel,
+        "The file is also sealed for other recipients. Writing it with a new key \
+         or new parameters leaves out everyone not in the Recipients menu.",
+    );
+    dialog.set_default_response(ResponseType::Cancel);
+    let resp = dialog.run();
+    dialog.close();
+    resp == ResponseType::Ok
+}
+
+// Whether to seal structured values deterministically after all.
+fn deterministic_dialog(window: &gtk::ApplicationWindow) -> bool {
+    let dialog = MessageDialog::new(
+        Some(window),
+        DialogFlags::empty(),
+        MessageType::Warning,
+        ButtonsType::OkCancel,
+        "Deterministic values keep their ciphertext while they stay the same, so \
+         diffs only show the edited ones. Anyone who sees two versions of the file \
+         learns which values were left alone or changed back.",
+    );
+    dialog.set_default_response(ResponseType::Cancel);
+    let resp = dialog.run();
+    dialog.close();
+    resp == ResponseType::Ok
+}
+
+fn unsaved_dialog(window: &gtk::ApplicationWindow) -> ResponseType {
+    let dialog = MessageDialog::new(
+        Some(window),
+        DialogFlags::empty(),
+        MessageType::Question,
+        ButtonsType::None,
+        "Save changes to the document first?",
+    );
+    dialog.add_button("Discard", ResponseType::Reject);
+    dialog.add_button("Cancel", ResponseType::Cancel);
+    dialog.add_button("Save", ResponseType::Accept);
+    dialog.set_default_response(ResponseType::Accept);
+    let resp = dialog.run();
+    dialog.close();
+    resp
+}
+
+// Shows the earlier revisions of the document, each as it was or as the
+// changes from it to the text in the editor. Returns the one to restore.
+fn history_dialog(
+    window: &gtk::ApplicationWindow,
+    text_buffer: &TextBuffer,
+    revisions: Vec<Revision>,
+) -> Option<Zeroizing<String>> {
+    let dialog = Dialog::new();
+    dialog.set_title("History");
+    dialog.set_transient_for(Some(window));
+    dialog.set_modal(true);
+    dialog.set_default_size(640, 480);
+    dialog.add_button("Restore", ResponseType::Accept);
+    dialog.add_button("Close", ResponseType::Close);
+
+    let choice = ComboBoxText::new();
+    for revision in &revisions {
+        let time = glib::DateTime::from_unix_local(revision.time as i64)
+            .format("%Y-%m-%d %H:%M:%S")
+            .map(|time| time.to_string())
+            .unwrap_or_default();
+        choice.append_text(&time);
+    }
+    let changes = CheckButton::with_label("Changes since this revision");
+    let view = TextView::new();
+    view.set_editable(false);
+    view.set_monospace(true);
+    let scroll = ScrolledWindow::new(None::<&Adjustment>, None::<&Adjustment>);
+    scroll.set_vexpand(true);
+    scroll.add(&view);
+    let content_area = dialog.get_content_area();
+    content_area.pack_start(&choice, false, false, 0);
+    content_area.pack_start(&changes, false, false, 0);
+    content_area.pack_start(&scroll, true, true, 0);
+
+    let (start, end) = text_buffer.get_bounds();
+    let current = Zeroizing::new(
+        text_buffer
+            .get_text(&start, &end, true)
+            .map(|text| text.to_string())
+            .unwrap_or_default(),
+    );
+    let revisions = Rc::new(revisions);
+    let show = Rc::new(
+        glib::clone!(@weak choice, @weak changes, @weak view, @strong revisions => move || {
+            let revision = match choice.get_active() {
+                Some(index) => &revisions[index as usize],
+                None => return,
+            };
+            if let Some(buffer) = view.get_buffer() {
+                if changes.get_active() {
+                    buffer.set_text(&history::diff(&revision.text, &current));
+                } else {
+                    buffer.set_text(&revision.text);
+                }
+            }
+        }),
+    );
+    choice.connect_changed(glib::clone!(@strong show => move |_| show()));
+    changes.connect_toggled(move |_| show());
+    choice.set_active(Some(0));
+    dialog.show_all();
+
+    let resp = dialog.run();
+    let restored = match (resp, choice.get_active()) {
+        (ResponseType::Accept, Some(index)) => Some(revisions[index as usize].text.clone()),
+        _ => None,
+    };
+    if let Some(buffer) = view.get_buffer() {
+        clear_buffer!(buffer);
+    }
+    dialog.close();
+    restored
+}
+
+// Lets the text of an unlocked section be read and edited. Returns the text
+// to seal back into the document.
+fn section_dialog(window: &gtk::ApplicationWindow, plaintext: &str) -> Option<Zeroizing<String>> {
+    let dialog = Dialog::new();
+    dialog.set_title("Encrypted section");
+    dialog.set_transient_for(Some(window));
+    dialog.set_modal(true);
+    dialog.set_default_size(480, 320);
+    dialog.add_button("Save", ResponseType::Accept);
+    dialog.add_button("Close", ResponseType::Close);
+    let view = TextView::new();
+    view.set_wrap_mode(WrapMode::WordChar);
+    let scroll = ScrolledWindow::new(None::<&Adjustment>, None::<&Adjustment>);
+    scroll.set_vexpand(true);
+    scroll.add(&view);
+    dialog.get_content_area().pack_start(&scroll, true, true, 0);
+    let buffer = view.get_buffer()?;
+    buffer.set_text(plaintext);
+    dialog.show_all();
+
+    let resp = dialog.run();
+    let (start, end) = buffer.get_bounds();
+    let text = buffer
+        .get_text(&start, &end, true)
+        .map(|text| Zeroizing::new(text.to_string()));
+    clear_buffer!(buffer);
+    dialog.close();
+    match resp {
+        ResponseType::Accept => text,
+        _ => None,
+    }
+}
+
+// Character offset in the buffer of a byte offset into its text.
+fn char_offset(text: &str, byte: usize) -> i32 {
+    text[..byte].chars().count() as i32
+}
+
+// Tags the armored sections in the buffer so they show as their BEGIN line
+// and can't be edited in place.
+fn mark_sections(text_buffer: &TextBuffer) {
+    let (start, end) = text_buffer.get_bounds();
+    text_buffer.remove_tag_by_name("section", &start, &end);
+    text_buffer.remove_tag_by_name("section-body", &start, &end);
+    let text = Zeroizing::new(
+        text_buffer
+            .get_text(&start, &end, true)
+            .map(|text| text.to_string())
+            .unwrap_or_default(),
+    );
+    for range in section::find(&text) {
+        let start = text_buffer.get_iter_at_offset(char_offset(&text, range.start));
+        let body =
+            text_buffer.get_iter_at_offset(char_offset(&text, range.start + section::BEGIN.len()));
+        let end = text_buffer.get_iter_at_offset(char_offset(&text, range.end));
+        text_buffer.apply_tag_by_name("section", &start, &end);
+        text_buffer.apply_tag_by_name("section-body", &body, &end);
+    }
+}
+
+fn recover_dialog(window: &gtk::ApplicationWindow) -> bool {
+    let dialog = MessageDialog::new(
+        Some(window),
+        DialogFlags::empty(),
+        MessageType::Question,
+        ButtonsType::YesNo,
+        "This document has unsaved changes from an earlier session. Restore them?",
+    );
+    let resp = dialog.run();
+    dialog.close();
+    resp == ResponseType::Yes
+}
+
+// Whether one of our dialogs is up, which takes the focus from the window.
+fn dialog_open(window: &gtk::ApplicationWindow) -> bool {
+    gtk::Window::list_toplevels()
+        .iter()
+        .filter_map(|widget| widget.downcast_ref::<gtk::Window>())
+        .any(|other| {
+            other.is_visible()
+                && other.get_transient_for().as_ref() == Some(window.upcast_ref::<gtk::Window>())
+        })
 }
 
 pub struct Editor {
@@ -86,10 +708,6 @@ impl Editor {
         window.set_size_request(800, 500);
         window.set_icon(Some(&icon));
         window.set_resizable(false);
-        window.connect_delete_event(glib::clone!(@strong window => move |_, _| {
-            close_handler!(window);
-            Inhibit(true)
-        }));
 
         let v_box = gtk::Box::new(gtk::Orientation::Vertical, 10);
         let menu = MenuBar::new();
@@ -97,11 +715,17 @@ impl Editor {
         let file = MenuItem::with_label("File");
         let new_file = MenuItem::with_label("New File");
         let open_file = MenuItem::with_label("Open...");
+        let save_file = MenuItem::with_label("Save");
         let save_file_as = MenuItem::with_label("Save as..");
+        let history = MenuItem::with_label("History...");
+        let lock_now = MenuItem::with_label("Lock");
         let close = MenuItem::with_label("Close");
         filemenu.append(&new_file);
         filemenu.append(&open_file);
+        filemenu.append(&save_file);
         filemenu.append(&save_file_as);
+        filemenu.append(&history);
+        filemenu.append(&lock_now);
         filemenu.append(&close);
         file.set_submenu(Some(&filemenu));
         menu.append(&file);
@@ -159,10 +783,269 @@ impl Editor {
         kdf.set_submenu(Some(&kdfmenu));
         menu.append(&kdf);
 
-        let text_buffer = TextBuffer::new(None::<&TextTagTable>);
+        let formatmenu = Menu::new();
+        let format = MenuItem::with_label("Format");
+        formatmenu.append(&self.new_menu_item(Format::VAULT));
+        formatmenu.append(&self.new_menu_item(Format::AGE));
+        formatmenu.append(&self.new_menu_item(Format::OPENPGP));
+        formatmenu.append(&self.new_menu_item(Format::OPENSSL));
+        formatmenu.append(&self.new_menu_item(Format::ANSIBLE));
+        formatmenu.append(&self.new_menu_item(Format::FERNET));
+        formatmenu.append(&self.new_menu_item(Format::INLINE));
+        formatmenu.append(&self.new_menu_item(Format::STRUCTURED));
+        let deterministic = CheckMenuItem::with_label("Deterministic values (AES-SIV)");
+        deterministic.set_active(self.state.borrow().deterministic());
+        formatmenu.append(&deterministic);
+        let openssl_iter = MenuItem::with_label("OpenSSL iterations...");
+        formatmenu.append(&openssl_iter);
+        format.set_submenu(Some(&formatmenu));
+        menu.append(&format);
+
+        let recipientsmenu = Menu::new();
+        let recipients = MenuItem::with_label("Recipients");
+        let add_recipients = MenuItem::with_label("Add recipients...");
+        let clear_recipients = MenuItem::with_label("Clear recipients");
+        recipientsmenu.append(&add_recipients);
+        recipientsmenu.append(&clear_recipients);
+        recipients.set_submenu(Some(&recipientsmenu));
+        menu.append(&recipients);
+
+        let signingmenu = Menu::new();
+        let signing = MenuItem::with_label("Signing");
+        let load_signing_key = MenuItem::with_label("Load signing key...");
+        let stop_signing = MenuItem::with_label("Stop signing");
+        signingmenu.append(&load_signing_key);
+        signingmenu.append(&stop_signing);
+        signing.set_submenu(Some(&signingmenu));
+        menu.append(&signing);
+
+        let settingsmenu = Menu::new();
+        let settings = MenuItem::with_label("Settings");
+        let lock_after = MenuItem::with_label("Lock after...");
+        let clear_clipboard_after = MenuItem::with_label("Clear clipboard after...");
+        let keep_revisions = MenuItem::with_label("Keep revisions...");
+        let hide_unfocused = CheckMenuItem::with_label("Hide when unfocused");
+        hide_unfocused.set_active(self.state.borrow().hide_unfocused());
+        settingsmenu.append(&lock_after);
+        settingsmenu.append(&clear_clipboard_after);
+        settingsmenu.append(&keep_revisions);
+        settingsmenu.append(&hide_unfocused);
+        settings.set_submenu(Some(&settingsmenu));
+        menu.append(&settings);
+
+        let sectionsmenu = Menu::new();
+        let sections = MenuItem::with_label("Sections");
+        let encrypt_section = MenuItem::with_label("Encrypt selection...");
+        let open_section = MenuItem::with_label("Open section...");
+        let export_redacted = MenuItem::with_label("Export redacted...");
+        sectionsmenu.append(&encrypt_section);
+        sectionsmenu.append(&open_section);
+        sectionsmenu.append(&export_redacted);
+        sections.set_submenu(Some(&sectionsmenu));
+        menu.append(&sections);
+
+        // Sections show as their BEGIN line with the rest invisible, so the
+        // text of the document is always read with hidden characters included.
+        let tags = TextTagTable::new();
+        let section_tag = TextTag::new(Some("section"));
+        section_tag.set_property_editable(false);
+        section_tag.set_property_foreground(Some("gray"));
+        let body_tag = TextTag::new(Some("section-body"));
+        body_tag.set_property_invisible(true);
+        tags.add(&section_tag);
+        tags.add(&body_tag);
+        let open_tag = TextTag::new(Some("section-open"));
+        open_tag.set_property_background(Some("#fff4c2"));
+        tags.add(&open_tag);
+        let text_buffer = TextBuffer::new(Some(&tags));
         let text_view = TextView::with_buffer(&text_buffer);
         text_view.set_wrap_mode(WrapMode::WordChar);
         text_view.set_left_margin(8);
+        text_buffer.connect_changed(mark_sections);
+
+        // shown in place of the text while the editor is locked
+        let stack = Stack::new();
+        let unlock_message = Label::new(Some(UNLOCK_MESSAGE));
+        let unlock_entry = Entry::new();
+        unlock_entry.set_visibility(false);
+        let unlock_button = Button::with_label("Unlock");
+        let lock_box = gtk::Box::new(gtk::Orientation::Vertical, 10);
+        lock_box.set_halign(Align::Center);
+        lock_box.set_valign(Align::Center);
+        lock_box.pack_start(&unlock_message, false, false, 0);
+        lock_box.pack_start(&unlock_entry, false, false, 0);
+        lock_box.pack_start(&unlock_button, false, false, 0);
+        let hidden = Label::new(Some("Hidden while vault is in the background"));
+
+        // Screen sharing and open offices: the text is only shown while the
+        // window has the focus. Our own dialogs don't count as losing it.
+        window.connect_focus_out_event(glib::clone!(@weak stack, @strong self.state as state => @default-return Inhibit(false), move |window, _| {
+            if state.borrow().hide_unfocused() && !state.borrow().is_locked() && !dialog_open(window) {
+                stack.set_visible_child_name("hidden");
+            }
+            Inhibit(false)
+        }));
+        window.connect_window_state_event(glib::clone!(@weak stack, @strong self.state as state => @default-return Inhibit(false), move |_, event| {
+            let minimized = event.get_new_window_state().contains(gdk::WindowState::ICONIFIED);
+            if minimized && state.borrow().hide_unfocused() && !state.borrow().is_locked() {
+                stack.set_visible_child_name("hidden");
+            }
+            Inhibit(false)
+        }));
+        window.connect_focus_in_event(
+            glib::clone!(@weak stack => @default-return Inhibit(false), move |_, _| {
+                if stack.get_visible_child_name().as_deref() == Some("hidden") {
+                    stack.set_visible_child_name("editor");
+                }
+                Inhibit(false)
+            }),
+        );
+
+        // time of the last key press, click or pointer motion
+        let activity = Rc::new(Cell::new(Instant::now()));
+        let touch = glib::clone!(@strong activity => move || {
+            activity.set(Instant::now());
+            Inhibit(false)
+        });
+        window.connect_key_press_event(glib::clone!(@strong touch => move |_, _| touch()));
+        text_view.connect_button_press_event(glib::clone!(@strong touch => move |_, _| touch()));
+        text_view.connect_motion_notify_event(move |_, _| touch());
+
+        let lock = Rc::new(
+            glib::clone!(@weak window, @weak text_buffer, @weak stack, @weak menu, @weak unlock_entry, @weak unlock_message, @strong self.state as state, @strong self.label as label => move || {
+                let modified = text_buffer.get_modified();
+                let has_sections = !state.borrow_mut().opened_sections().is_empty();
+                let locked = if has_sections {
+                    // the open sections go back into their armor, with
+                    // their edits, and the rest of the text stays
+                    let text = match document_text(&window, &text_buffer, &state) {
+                        Some(text) => text,
+                        None => return,
+                    };
+                    text_buffer.set_text(&text);
+                    unlock_message.set_text(UNLOCK_SECTIONS_MESSAGE);
+                    state.borrow_mut().lock_sections()
+                } else {
+                    let (start, end) = text_buffer.get_bounds();
+                    let plaintext = Zeroizing::new(text_buffer.get_text(&start, &end, true).map(|text| text.to_string()).unwrap_or_default());
+                    // the edits are still there, sealed
+                    let locked = state.borrow_mut().lock(&plaintext);
+                    if locked {
+                        clear_buffer!(text_buffer);
+                    }
+                    locked
+                };
+                if locked {
+                    text_buffer.set_modified(modified);
+                    menu.set_sensitive(false);
+                    stack.set_visible_child_name("locked");
+                    unlock_entry.grab_focus();
+                    label.set_label(&state.borrow().to_string());
+                }
+            }),
+        );
+
+        glib::timeout_add_seconds_local(
+            1,
+            glib::clone!(@weak window, @strong activity, @strong lock, @strong self.state as state => @default-return glib::Continue(false), move || {
+                // an open dialog counts as activity, so that nothing it was
+                // opened for finds the text gone when it closes
+                if dialog_open(&window) {
+                    activity.set(Instant::now());
+                }
+                let idle = state.borrow().lock_after().map_or(false, |lock_after| activity.get().elapsed() >= lock_after);
+                if idle && state.borrow().can_lock() {
+                    lock();
+                }
+                glib::Continue(true)
+            }),
+        );
+
+        // Unsaved changes are sealed into a recovery file now and then, in
+        // case the editor doesn't get to close.
+        let changed = Rc::new(Cell::new(false));
+        text_buffer.connect_changed(glib::clone!(@strong changed => move |_| changed.set(true)));
+        glib::timeout_add_seconds_local(
+            AUTOSAVE_EVERY,
+            glib::clone!(@weak text_buffer, @strong self.state as state, @strong self.label as label => @default-return glib::Continue(false), move || {
+                if !changed.get() || !text_buffer.get_modified() || state.borrow().is_locked() {
+                    return glib::Continue(true);
+                }
+                changed.set(false);
+                let (start, end) = text_buffer.get_bounds();
+                let plaintext = Zeroizing::new(text_buffer.get_text(&start, &end, true).map(|text| text.to_string()).unwrap_or_default());
+                state.borrow_mut().autosave(&plaintext);
+                label.set_label(&state.borrow().to_string());
+                glib::Continue(true)
+            }),
+        );
+
+        // Copies and cuts are taken off the clipboard and the primary
+        // selection after a while, unless something else was copied since.
+        let copy = glib::clone!(@weak text_buffer, @strong self.state as state, @strong self.label as label => move |_: &TextView| {
+            if let Some((start, end)) = text_buffer.get_selection_bounds() {
+                let text = text_buffer.get_text(&start, &end, false).map(|text| text.to_string()).unwrap_or_default();
+                state.borrow_mut().copied(Zeroizing::new(text));
+                label.set_label(&state.borrow().to_string());
+            }
+        });
+        text_view.connect_copy_clipboard(copy.clone());
+        text_view.connect_cut_clipboard(copy);
+
+        glib::timeout_add_seconds_local(
+            1,
+            glib::clone!(@strong self.state as state, @strong self.label as label => move || {
+                if !state.borrow().is_copied() {
+                    return glib::Continue(true);
+                }
+                let due = state.borrow_mut().clipboard_due();
+                if let Some(text) = due {
+                    for selection in &[gdk::SELECTION_CLIPBOARD, gdk::SELECTION_PRIMARY] {
+                        let clipboard = gtk::Clipboard::get(selection);
+                        if clipboard.wait_for_text().as_deref() == Some(text.as_str()) {
+                            clipboard.set_text("");
+                        }
+                    }
+                }
+                label.set_label(&state.borrow().to_string());
+                glib::Continue(true)
+            }),
+        );
+
+        let unlock = glib::clone!(@weak text_buffer, @weak stack, @weak menu, @weak unlock_entry, @weak unlock_message, @strong activity, @strong self.state as state, @strong self.label as label => move || {
+            let password = Zeroizing::new(unlock_entry.get_text().to_string());
+            unlock_entry.set_text("");
+            let modified = text_buffer.get_modified();
+            let unlocked = if state.borrow().has_locked_sections() {
+                let opened = state.borrow_mut().unlock_sections(&password);
+                opened.map(|opened| {
+                    for opened in opened {
+                        let (first, last) = text_buffer.get_bounds();
+                        let text = Zeroizing::new(text_buffer.get_text(&first, &last, true).map(|text| text.to_string()).unwrap_or_default());
+                        let range = section::find(&text).into_iter().find(|range| text[range.clone()] == *opened.armored());
+                        if let Some(range) = range {
+                            open_in_place(&text_buffer, &state, &text, range, opened);
+                        }
+                    }
+                })
+            } else {
+                let plaintext = state.borrow_mut().unlock(&password);
+                plaintext.map(|plaintext| text_buffer.set_text(&plaintext))
+            };
+            match unlocked {
+                Some(()) => {
+                    text_buffer.set_modified(modified);
+                    unlock_message.set_text(UNLOCK_MESSAGE);
+                    stack.set_visible_child_name("editor");
+                    menu.set_sensitive(true);
+                    activity.set(Instant::now());
+                    label.set_label(&state.borrow().to_string());
+                }
+                None => unlock_message.set_text("Wrong password. Enter the document password to continue."),
+            }
+        });
+        unlock_entry.connect_activate(glib::clone!(@strong unlock => move |_| unlock()));
+        unlock_button.connect_clicked(move |_| unlock());
 
         let receiver = self
             .receiver
@@ -172,29 +1055,81 @@ impl Editor {
         let state = self.state.clone();
         receiver.attach(
             None,
-            glib::clone!(@strong text_buffer => move |action| {
-                match state.borrow().action(action) {
-                    Some(Action::UpdateTextView(text)) => {
+            glib::clone!(@strong window, @strong text_buffer, @strong deterministic, @strong self.label as label => move |action| {
+                let action = state.borrow_mut().action(action);
+                apply(&window, &text_buffer, action);
+                // an opened structured file says how its values are sealed
+                let sealed_deterministic = state.borrow().deterministic();
+                deterministic.set_active(sealed_deterministic);
+                let recovered = state.borrow_mut().take_recovered();
+                if let Some(text) = recovered {
+                    if recover_dialog(&window) {
                         clear_buffer!(text_buffer);
                         text_buffer.set_text(text.as_str());
-                    },
-                    None => {},
+                    } else {
+                        state.borrow().discard_recovery();
+                    }
                 }
+                window.set_title(&state.borrow().title());
+                label.set_label(&state.borrow().to_string());
                 glib::Continue(true)
             }),
         );
 
-        new_file.connect_activate(glib::clone!(@weak text_buffer => move |_| {
+        text_buffer.connect_modified_changed(glib::clone!(@weak window, @strong self.state as state, @strong self.label as label => move |text_buffer| {
+            state.borrow_mut().set_modified(text_buffer.get_modified());
+            window.set_title(&state.borrow().title());
+            label.set_label(&state.borrow().to_string());
+        }));
+
+        // Whether unsaved changes may be dropped, after offering to save them.
+        let confirm = Rc::new(
+            glib::clone!(@weak window, @weak text_buffer, @strong self.state as state, @strong self.label as label => @default-return false, move || {
+                if !text_buffer.get_modified() {
+                    return true;
+                }
+                if state.borrow().is_locked() {
+                    error_dialog(&window, "Unlock the editor to save or discard the changes first");
+                    return false;
+                }
+                match unsaved_dialog(&window) {
+                    ResponseType::Accept => match save(&window, &text_buffer, &state, &label) {
+                        Some(action) => {
+                            let action = state.borrow_mut().action(action);
+                            apply(&window, &text_buffer, action)
+                        }
+                        None => false,
+                    },
+                    ResponseType::Reject => {
+                        state.borrow().discard_recovery();
+                        true
+                    }
+                    _ => false,
+                }
+            }),
+        );
+
+        new_file.connect_activate(glib::clone!(@strong window, @weak text_buffer, @strong confirm, @strong self.label as label, @strong self.state as state => move |_| {
+            if !confirm() {
+                return;
+            }
             clear_buffer!(text_buffer);
+            text_buffer.set_modified(false);
+            state.borrow_mut().new_document();
+            window.set_title(&state.borrow().title());
+            label.set_label(&state.borrow().to_string());
         }));
 
-        open_file.connect_activate(glib::clone!(@strong window, @strong self.sender as sender => move |_| {
+        open_file.connect_activate(glib::clone!(@strong window, @strong confirm, @strong self.sender as sender => move |_| {
+            if !confirm() {
+                return;
+            }
             let dialog = FileChooserDialog::new(Some("Opening file..."), Some(&window), FileChooserAction::Open);
             dialog.set_select_multiple(false);
-            dialog.add_button("Open", ResponseType::Ok.into());
-            dialog.add_button("Cancel", ResponseType::Cancel.into());
+            dialog.add_button("Open", ResponseType::Ok);
+            dialog.add_button("Cancel", ResponseType::Cancel);
             dialog.connect_response(glib::clone!(@strong window, @strong sender => move |dialog, resp| {
-                if resp == ResponseType::Ok.into() {
+                if resp == ResponseType::Ok {
                     if let Some(path) = dialog.get_filename() {
                         let display = path.display();
                         let mut file = match File::open(&path) {
@@ -213,23 +1148,36 @@ impl Editor {
                                 Ok(_) => { },
                             }
 
-                            let entry_buffer = EntryBuffer::new(None);
-                            let entry = Entry::with_buffer(&entry_buffer);
-                            entry.set_visibility(false);
-                            entry.show();
-                            let flags = DialogFlags::empty();
-                            let pass_dialog = MessageDialog::new(Some(&window), flags, MessageType::Question, ButtonsType::OkCancel, "Decryption key:");
-                            let content_area = pass_dialog.get_content_area();
-                            content_area.pack_start(&entry, true, true, 0);
-                            pass_dialog.connect_response(glib::clone!(@weak entry_buffer, @strong sender => move |_, resp| {
-                                if resp == ResponseType::Ok.into() {
-                                    let key = entry_buffer.get_text();
-                                    entry_buffer.delete_text(0, Some(entry_buffer.get_length()));
-                                    sender.send(state::Action::Decrypt(key, content.clone())).unwrap();
+                            let key = match Format::detect(&content) {
+                                // age files hold either a passphrase or recipients, never both
+                                Format::AGE => match age::Encrypted::parse(&content).map(|file| file.has_passphrase()) {
+                                    Ok(false) => choose_age_identities(&window).map(Key::AgeIdentities),
+                                    _ => key_dialog(&window, "Decryption key:", false),
+                                },
+                                Format::OPENPGP | Format::OPENSSL | Format::ANSIBLE | Format::STRUCTURED => key_dialog(&window, "Decryption key:", false),
+                                Format::FERNET => key_dialog(&window, "Fernet key:", false),
+                                // only its sections are encrypted, each with a password of its own
+                                Format::INLINE => {
+                                    sender.send(state::Action::Load(content, path)).unwrap();
+                                    return;
+                                }
+                                Format::VAULT => {
+                                    // containers without a password slot can only be opened with an identity
+                                    let slots = container::Container::parse(&content)
+                                        .map(|c| (c.has_password_slot(), c.has_recipient_slot()))
+                                        .unwrap_or((true, false));
+                                    match slots {
+                                        (false, true) => choose_identity(&window).map(|identity| Key::Identity(Box::new(identity))),
+                                        (_, identity) => key_dialog(&window, "Decryption key:", identity),
+                                    }
                                 }
-                            }));
-                            pass_dialog.run();
-                            pass_dialog.close();
+                            };
+                            match key {
+                                Some(Key::Password(key)) => sender.send(state::Action::Decrypt(key, content, path)).unwrap(),
+                                Some(Key::Identity(identity)) => sender.send(state::Action::DecryptWithIdentity(identity, content, path)).unwrap(),
+                                Some(Key::AgeIdentities(identities)) => sender.send(state::Action::DecryptWithAgeIdentities(identities, content, path)).unwrap(),
+                                None => {},
+                            }
                         }
                     }
                 }
@@ -238,43 +1186,278 @@ impl Editor {
             dialog.close();
         }));
 
-        save_file_as.connect_activate(glib::clone!(@strong window, @strong self.sender as sender => move |_| {
-            let dialog = FileChooserDialog::new(Some("Saving file..."), Some(&window), FileChooserAction::Save);
-            dialog.add_button("Save", ResponseType::Ok.into());
-            dialog.add_button("Cancel", ResponseType::Cancel.into());
-            dialog.connect_response(glib::clone!(@weak window, @weak text_buffer, @strong sender => move |dialog, resp| {
-                if resp == ResponseType::Ok.into() {
-                    if let Some(path) = dialog.get_filename() {
-                        let entry_buffer = EntryBuffer::new(None);
-                        let entry = Entry::with_buffer(&entry_buffer);
-                        entry.set_visibility(false);
-                        entry.show();
-                        let flags = DialogFlags::empty();
-                        let pass_dialog = MessageDialog::new(Some(&window), flags, MessageType::Question, ButtonsType::OkCancel, "Encryption key:");
-                        let content_area = pass_dialog.get_content_area();
-                        content_area.pack_start(&entry, true, true, 0);
-                        pass_dialog.connect_response(glib::clone!(@weak entry_buffer, @weak text_buffer, @strong sender => move |_, resp| {
-                            if resp == ResponseType::Ok.into() {
-                                let start = text_buffer.get_start_iter();
-                                let end  = text_buffer.get_end_iter();
-                                if let Some(plaintext) = text_buffer.get_text(&start, &end, false) {
-                                    let key = entry_buffer.get_text();
-                                    entry_buffer.delete_text(0, Some(entry_buffer.get_length()));
-                                    sender.send(state::Action::Encrypt(key, plaintext.to_string(), path.clone())).unwrap();
-                                }
-                            }
-                        }));
-                        pass_dialog.run();
-                        pass_dialog.close();
+        save_file.connect_activate(glib::clone!(@strong window, @weak text_buffer, @strong self.sender as sender, @strong self.state as state, @strong self.label as label => move |_| {
+            if let Some(action) = save(&window, &text_buffer, &state, &label) {
+                sender.send(action).unwrap();
+            }
+        }));
+
+        save_file_as.connect_activate(glib::clone!(@strong window, @weak text_buffer, @strong self.sender as sender, @strong self.state as state, @strong self.label as label => move |_| {
+            if let Some(action) = save_as(&window, &text_buffer, &state, &label) {
+                sender.send(action).unwrap();
+            }
+        }));
+
+        openssl_iter.connect_activate(glib::clone!(@strong window, @strong self.label as label, @strong self.state as state => move |_| {
+            // 0 stands for files written without -pbkdf2 or -iter
+            let message = "PBKDF2 iterations (0 for EVP_BytesToKey):";
+            if let Some(text) = text_dialog(&window, message, &openssl::DEFAULT_ITER.to_string()) {
+                match text.trim().parse::<u32>() {
+                    Ok(0) => state.borrow_mut().set_openssl_iter(None),
+                    Ok(iter) => state.borrow_mut().set_openssl_iter(Some(iter)),
+                    Err(_) => error_dialog(&window, &format!("{} is not a number of iterations", text)),
+                }
+                label.set_label(&state.borrow().to_string());
+            }
+        }));
+
+        add_recipients.connect_activate(glib::clone!(@strong window, @strong self.label as label, @strong self.state as state => move |_| {
+            if let Some(path) = choose_file(&window, "Adding recipients...", FileChooserAction::Open) {
+                match fs::read_to_string(&path).ok().as_ref().and_then(|s| Recipient::parse_list(s)) {
+                    Some(recipients) => {
+                        state.borrow_mut().add_recipients(recipients);
+                        label.set_label(&state.borrow().to_string());
                     }
+                    None => error_dialog(&window, &format!("{} does not hold vault recipients", path.display())),
                 }
-            }));
-            dialog.run();
+            }
+        }));
+
+        clear_recipients.connect_activate(
+            glib::clone!(@strong self.label as label, @strong self.state as state => move |_| {
+                state.borrow_mut().clear_recipients();
+                label.set_label(&state.borrow().to_string());
+            }),
+        );
+
+        load_signing_key.connect_activate(glib::clone!(@strong window, @strong self.label as label, @strong self.state as state => move |_| {
+            if let Some(path) = choose_file(&window, "Loading signing key...", FileChooserAction::Open) {
+                match fs::read_to_string(&path).ok().as_ref().and_then(|s| SigningKey::parse(s)) {
+                    Some(signing_key) => {
+                        state.borrow_mut().set_signing_key(Some(signing_key));
+                        label.set_label(&state.borrow().to_string());
+                    }
+                    None => error_dialog(&window, &format!("{} is not a vault signing key", path.display())),
+                }
+            }
+        }));
+
+        stop_signing.connect_activate(
+            glib::clone!(@strong self.label as label, @strong self.state as state => move |_| {
+                state.borrow_mut().set_signing_key(None);
+                label.set_label(&state.borrow().to_string());
+            }),
+        );
+
+        history.connect_activate(glib::clone!(@strong window, @weak text_buffer, @strong self.state as state => move |_| {
+            let revisions = state.borrow().history().to_vec();
+            if revisions.is_empty() {
+                error_dialog(&window, "This document has no earlier revisions");
+                return;
+            }
+            if let Some(text) = history_dialog(&window, &text_buffer, revisions) {
+                clear_buffer!(text_buffer);
+                text_buffer.set_text(&text);
+            }
+        }));
+
+        encrypt_section.connect_activate(glib::clone!(@strong window, @weak text_buffer, @strong self.state as state => move |_| {
+            let (mut start, mut end) = match text_buffer.get_selection_bounds() {
+                Some(bounds) => bounds,
+                None => {
+                    error_dialog(&window, "Select the text to encrypt first");
+                    return;
+                }
+            };
+            let (first, last) = text_buffer.get_bounds();
+            let text = text_buffer.get_text(&first, &last, true).map(|text| text.to_string()).unwrap_or_default();
+            let overlaps = section::find(&text).iter().any(|range| {
+                char_offset(&text, range.start) < end.get_offset() && start.get_offset() < char_offset(&text, range.end)
+            });
+            if overlaps {
+                error_dialog(&window, "The selection overlaps an encrypted section");
+                return;
+            }
+            let password = match key_dialog(&window, "Section password:", false) {
+                Some(Key::Password(password)) if !password.is_empty() => password,
+                Some(_) => {
+                    error_dialog(&window, "A section needs a password");
+                    return;
+                }
+                None => return,
+            };
+            let plaintext = Zeroizing::new(text_buffer.get_text(&start, &end, true).map(|text| text.to_string()).unwrap_or_default());
+            let params = state.borrow().params();
+            let mut armored = match section::seal(params, &password, &plaintext) {
+                Ok(armored) => armored,
+                Err(err) => {
+                    error_dialog(&window, &err.to_string());
+                    return;
+                }
+            };
+            state.borrow_mut().unlocked_section(&armored, plaintext);
+            // the armor goes on lines of its own
+            if !start.starts_line() {
+                armored.insert(0, '\n');
+            }
+            if !end.ends_line() {
+                armored.push('\n');
+            }
+            text_buffer.delete(&mut start, &mut end);
+            text_buffer.insert(&mut start, &armored);
+        }));
+
+        open_section.connect_activate(glib::clone!(@strong window, @weak text_buffer, @strong self.state as state => move |_| {
+            let cursor = text_buffer.get_property_cursor_position();
+            let (first, last) = text_buffer.get_bounds();
+            let text = Zeroizing::new(text_buffer.get_text(&first, &last, true).map(|text| text.to_string()).unwrap_or_default());
+            let range = section::find(&text).into_iter().find(|range| {
+                char_offset(&text, range.start) <= cursor && cursor <= char_offset(&text, range.end)
+            });
+            let range = match range {
+                Some(range) => range,
+                None => {
+                    error_dialog(&window, "Put the cursor on an encrypted section first");
+                    return;
+                }
+            };
+            let password = match key_dialog(&window, "Section password:", false) {
+                Some(Key::Password(password)) => password,
+                _ => return,
+            };
+            // in an inline file the section opens in place, and is sealed
+            // again when the file is saved
+            if state.borrow().format() == Format::INLINE {
+                let opened = match section::Opened::open(&text[range.clone()], &password) {
+                    Ok(opened) => opened,
+                    Err(_) => {
+                        error_dialog(&window, "Wrong password for this section");
+                        return;
+                    }
+                };
+                open_in_place(&text_buffer, &state, &text, range, opened);
+                return;
+            }
+            let (plaintext, params) = match section::open(&text[range.clone()], &password) {
+                Ok(opened) => opened,
+                Err(_) => {
+                    error_dialog(&window, "Wrong password for this section");
+                    return;
+                }
+            };
+            // goes into redacted copies from now on
+            state.borrow_mut().unlocked_section(&text[range.clone()], plaintext.clone());
+            let edited = match section_dialog(&window, &plaintext) {
+                Some(edited) if edited != plaintext => edited,
+                _ => return,
+            };
+            match section::seal(params, &password, &edited) {
+                Ok(armored) => {
+                    state.borrow_mut().unlocked_section(&armored, edited);
+                    let mut start = text_buffer.get_iter_at_offset(char_offset(&text, range.start));
+                    let mut end = text_buffer.get_iter_at_offset(char_offset(&text, range.end));
+                    text_buffer.delete(&mut start, &mut end);
+                    text_buffer.insert(&mut start, &armored);
+                }
+                Err(err) => error_dialog(&window, &err.to_string()),
+            }
+        }));
+
+        export_redacted.connect_activate(glib::clone!(@strong window, @weak text_buffer, @strong self.state as state => move |_| {
+            let dialog = FileChooserDialog::new(Some("Exporting redacted copy..."), Some(&window), FileChooserAction::Save);
+            dialog.add_button("Export", ResponseType::Ok);
+            dialog.add_button("Cancel", ResponseType::Cancel);
+            let path = match dialog.run() {
+                ResponseType::Ok => dialog.get_filename(),
+                _ => None,
+            };
             dialog.close();
+            let path = match path {
+                Some(path) => path,
+                None => return,
+            };
+            let password = match key_dialog(&window, "Password for the copy (empty for plain text):", false) {
+                Some(Key::Password(password)) => password,
+                _ => return,
+            };
+            let (start, end) = text_buffer.get_bounds();
+            let text = Zeroizing::new(text_buffer.get_text(&start, &end, true).map(|text| text.to_string()).unwrap_or_default());
+            let exported = state.borrow().export_redacted(&text, &password, &path);
+            if let Err(err) = exported {
+                error_dialog(&window, &format!("Error writing to file {}: {}", path.display(), err));
+            }
+        }));
+
+        lock_now.connect_activate(
+            glib::clone!(@strong window, @strong lock, @strong self.state as state => move |_| {
+                if state.borrow().can_lock() {
+                    lock();
+                } else {
+                    error_dialog(&window, "Only documents with a password or open sections can be locked");
+                }
+            }),
+        );
+
+        clear_clipboard_after.connect_activate(glib::clone!(@strong window, @strong self.state as state => move |_| {
+            let seconds = state.borrow().clear_clipboard_after().map_or(0, |after| after.as_secs());
+            if let Some(text) = text_dialog(&window, "Seconds before copied text is cleared (0 for never):", &seconds.to_string()) {
+                match text.trim().parse::<u64>() {
+                    Ok(0) => state.borrow_mut().set_clear_clipboard_after(None),
+                    Ok(seconds) => state.borrow_mut().set_clear_clipboard_after(Some(Duration::from_secs(seconds))),
+                    Err(_) => error_dialog(&window, &format!("{} is not a number of seconds", text)),
+                }
+            }
         }));
 
-        close.connect_activate(glib::clone!(@strong window => move |_| {
-            close_handler!(window);
+        keep_revisions.connect_activate(glib::clone!(@strong window, @strong self.state as state => move |_| {
+            let keep = state.borrow().keep_revisions();
+            if let Some(text) = text_dialog(&window, "Earlier revisions kept in vault files:", &keep.to_string()) {
+                match text.trim().parse::<usize>() {
+                    Ok(keep) => state.borrow_mut().set_keep_revisions(keep),
+                    Err(_) => error_dialog(&window, &format!("{} is not a number of revisions", text)),
+                }
+            }
+        }));
+
+        deterministic.connect_toggled(glib::clone!(@strong window, @strong self.state as state, @strong self.label as label => move |item| {
+            let active = item.get_active();
+            if active == state.borrow().deterministic() {
+                return;
+            }
+            if active && !deterministic_dialog(&window) {
+                item.set_active(false);
+                return;
+            }
+            state.borrow_mut().set_deterministic(active);
+            label.set_label(&state.borrow().to_string());
+        }));
+
+        hide_unfocused.connect_toggled(glib::clone!(@strong self.state as state => move |item| {
+            state.borrow_mut().set_hide_unfocused(item.get_active());
+        }));
+
+        lock_after.connect_activate(glib::clone!(@strong window, @strong self.state as state => move |_| {
+            let minutes = state.borrow().lock_after().map_or(0, |lock_after| lock_after.as_secs() / 60);
+            if let Some(text) = text_dialog(&window, "Minutes of inactivity before locking (0 for never):", &minutes.to_string()) {
+                match text.trim().parse::<u64>() {
+                    Ok(0) => state.borrow_mut().set_lock_after(None),
+                    Ok(minutes) => state.borrow_mut().set_lock_after(Some(Duration::from_secs(minutes * 60))),
+                    Err(_) => error_dialog(&window, &format!("{} is not a number of minutes", text)),
+                }
+            }
+        }));
+
+        window.connect_destroy(glib::clone!(@strong self.state as state => move |_| {
+            state.borrow_mut().close();
+        }));
+
+        window
+            .connect_delete_event(glib::clone!(@strong confirm => move |_, _| Inhibit(!confirm())));
+
+        close.connect_activate(glib::clone!(@strong window, @strong confirm => move |_| {
+            if confirm() {
+                unsafe { window.destroy() };
+            }
         }));
 
         let scroll_window = ScrolledWindow::new(None::<&Adjustment>, None::<&Adjustment>);
@@ -283,7 +1466,10 @@ impl Editor {
 
         v_box.pack_start(&menu, false, false, 0);
         v_box.pack_start(&self.label, false, true, 0);
-        v_box.pack_start(&scroll_window, true, true, 0);
+        stack.add_named(&scroll_window, "editor");
+        stack.add_named(&lock_box, "locked");
+        stack.add_named(&hidden, "hidden");
+        v_box.pack_start(&stack, true, true, 0);
         window.add(&v_box);
         window.show_all();
     }
@@ -0,0 +1,215 @@
+use cryptolib::util::fixed_time_eq;
+use rustc_serialize::base64::{CharacterSet, Config, FromBase64, Newline, ToBase64};
+use std::fmt;
+use std::str;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use container::Error;
+use crypto;
+use state::cipher::Cipher;
+use zeroize::{Zeroize, Zeroizing};
+
+// Fernet tokens, see https://github.com/fernet/spec. The key is 16 bytes for
+// HMAC-SHA256 followed by 16 bytes for AES-128-CBC, and a token is
+//
+//   0x80 | timestamp (8) | IV (16) | ciphertext | HMAC (32)
+//
+// in padded base64url.
+const VERSION: u8 = 0x80;
+// what every token made before the year 10889 starts with
+const TOKEN_PREFIX: &[u8] = b"gAAAAA";
+
+const KEY_LEN: usize = 16;
+const IV_LEN: usize = 16;
+const MAC_LEN: usize = 32;
+const HEADER_LEN: usize = 1 + 8 + IV_LEN;
+
+// how far in the future a token with a TTL may be dated
+const MAX_CLOCK_SKEW: u64 = 60;
+
+const BASE64: Config = Config {
+    char_set: CharacterSet::UrlSafe,
+    newline: Newline::LF,
+    pad: true,
+    line_length: None,
+};
+
+pub struct Key {
+    signing: [u8; KEY_LEN],
+    encryption: [u8; KEY_LEN],
+}
+
+impl Key {
+    pub fn generate() -> Key {
+        let mut key = Key {
+            signing: [0; KEY_LEN],
+            encryption: [0; KEY_LEN],
+        };
+        crypto::random_bytes(&mut key.signing);
+        crypto::random_bytes(&mut key.encryption);
+        key
+    }
+
+    pub fn parse(s: &str) -> Option<Key> {
+        let bytes = s.trim().from_base64().ok()?;
+        if bytes.len() != 2 * KEY_LEN {
+            return None;
+        }
+        let mut key = Key {
+            signing: [0; KEY_LEN],
+            encryption: [0; KEY_LEN],
+        };
+        key.signing.copy_from_slice(&bytes[..KEY_LEN]);
+        key.encryption.copy_from_slice(&bytes[KEY_LEN..]);
+        Some(key)
+    }
+}
+
+impl fmt::Display for Key {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut bytes = Zeroizing::new(self.signing.to_vec());
+        bytes.extend_from_slice(&self.encryption);
+        write!(f, "{}", bytes.to_base64(BASE64))
+    }
+}
+
+impl Drop for Key {
+    fn drop(&mut self) {
+        self.signing.zeroize();
+        self.encryption.zeroize();
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+pub fn is_fernet(data: &[u8]) -> bool {
+    data.starts_with(TOKEN_PREFIX)
+}
+
+pub fn encrypt(key: &Key, plaintext: &[u8]) -> String {
+    let mut iv = [0; IV_LEN];
+    crypto::random_bytes(&mut iv);
+    encrypt_with(key, now(), &iv, plaintext)
+}
+
+fn encrypt_with(key: &Key, timestamp: u64, iv: &[u8], plaintext: &[u8]) -> String {
+    let mut token = vec![VERSION];
+    token.extend_from_slice(&timestamp.to_be_bytes());
+    token.extend_from_slice(iv);
+    token.extend(
+        crypto::encrypt_with(Cipher::AESCBC, &key.encryption, iv, plaintext)
+            .expect("CBC encryption does not fail"),
+    );
+    let mac = crypto::hmac_sha256(&key.signing, &[&token]);
+    token.extend_from_slice(&mac);
+    token.to_base64(BASE64)
+}
+
+// Returns the plaintext and the time the token was made. With a TTL, tokens
+// older than that many seconds are refused.
+pub fn decrypt(key: &Key, data: &[u8], ttl: Option<u64>) -> Result<(Vec<u8>, u64), Error> {
+    decrypt_at(key, data, ttl, now())
+}
+
+fn decrypt_at(key: &Key, data: &[u8], ttl: Option<u64>, now: u64) -> Result<(Vec<u8>, u64), Error> {
+    let text = str::from_utf8(data).map_err(|_| Error::Format)?;
+    let token = text.trim().from_base64().map_err(|_| Error::Format)?;
+    if token.len() < HEADER_LEN + MAC_LEN || token[0] != VERSION {
+        return Err(Error::Format);
+    }
+    let (signed, mac) = token.split_at(token.len() - MAC_LEN);
+    if !fixed_time_eq(&crypto::hmac_sha256(&key.signing, &[signed]), mac) {
+        return Err(Error::NoMatchingKey);
+    }
+
+    let mut timestamp = [0; 8];
+    timestamp.copy_from_slice(&signed[1..9]);
+    let timestamp = u64::from_be_bytes(timestamp);
+    if let Some(ttl) = ttl {
+        if timestamp.saturating_add(ttl) < now || now + MAX_CLOCK_SKEW < timestamp {
+            return Err(Error::Expired);
+        }
+    }
+    let plaintext = crypto::decrypt_with(
+        Cipher::AESCBC,
+        &key.encryption,
+        &signed[9..HEADER_LEN],
+        &signed[HEADER_LEN..],
+    )
+    .map_err(|_| Error::Cipher)?;
+    Ok((plaintext, timestamp))
+}
+
+// Seconds since the epoch as a UTC date and time.
+pub fn format_timestamp(timestamp: u64) -> String {
+    let days = (timestamp / 86400) as i64;
+    let secs = timestamp % 86400;
+    // civil from days, http://howardhinnant.github.io/date_algorithms.html
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02} UTC",
+        year,
+        month,
+        day,
+        secs / 3600,
+        secs / 60 % 60,
+        secs % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the "generate" vector of https://github.com/fernet/spec
+    const KEY: &str = "cw_0x689RpI-jtRR7oE8h_eQsKImvJapLeSbXpwF4e4=";
+    const TOKEN: &str = "gAAAAAAdwJ6wAAECAwQFBgcICQoLDA0ODy021cpGVWKZ_eEwCGM4BLLF_5CV9dOPmrhuVUPgJobwOz7JcbmrR64jVmpU4IwqDA==";
+    const NOW: u64 = 499_162_800;
+
+    #[test]
+    fn test_spec() {
+        let key = Key::parse(KEY).unwrap();
+        let iv: Vec<u8> = (0..16).collect();
+        assert_eq!(encrypt_with(&key, NOW, &iv, b"hello"), TOKEN);
+        assert_eq!(key.to_string(), KEY);
+
+        let (plaintext, timestamp) = decrypt_at(&key, TOKEN.as_bytes(), Some(60), NOW).unwrap();
+        assert_eq!(plaintext, b"hello");
+        assert_eq!(format_timestamp(timestamp), "1985-10-26 08:20:00 UTC");
+        assert_eq!(format_timestamp(951_782_400), "2000-02-29 00:00:00 UTC");
+        assert_eq!(format_timestamp(4_102_444_799), "2099-12-31 23:59:59 UTC");
+        assert_eq!(
+            decrypt_at(&key, TOKEN.as_bytes(), Some(60), NOW + 61).unwrap_err(),
+            Error::Expired
+        );
+        assert!(decrypt_at(&key, TOKEN.as_bytes(), None, NOW + 61).is_ok());
+        assert_eq!(
+            decrypt_at(&key, TOKEN.as_bytes(), Some(60), NOW - 61).unwrap_err(),
+            Error::Expired
+        );
+        let other = Key::generate();
+        assert!(decrypt_at(&other, TOKEN.as_bytes(), None, NOW).is_err());
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let key = Key::generate();
+        let token = encrypt(&key, b"secret");
+        assert!(is_fernet(token.as_bytes()));
+        let (plaintext, _) = decrypt(&key, token.as_bytes(), Some(60)).unwrap();
+        assert_eq!(plaintext, b"secret");
+    }
+}
@@ -0,0 +1,169 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::process::Command;
+use zeroize::Zeroizing;
+
+use container;
+use container::{Container, Error, Params};
+
+// Vault files are kept in git as ciphertext and checked out as plain text.
+// git runs the clean filter on what is added, the smudge filter on what is
+// checked out and textconv on both sides of a diff:
+//
+//   *.vault filter=vault diff=vault
+//
+// Encryption is randomized, so clean reuses the indexed file as long as its
+// text is unchanged; otherwise git would see every vault file as modified.
+// The filters never ask for a password: it comes from the environment or
+// from the agent.
+
+pub const PASSWORD_VAR: &str = "VAULT_PASSWORD";
+pub const PATTERN: &str = "*.vault";
+const ATTRIBUTES: &str = "filter=vault diff=vault";
+
+// The config entries the attributes refer to. A failing clean stops the
+// commit rather than letting plain text through. textconv output isn't
+// cached, since git would keep it in the repository.
+pub const CONFIG: [(&str, &str); 4] = [
+    ("filter.vault.clean", "vault git-filter clean %f"),
+    ("filter.vault.smudge", "vault git-filter smudge %f"),
+    ("filter.vault.required", "true"),
+    ("diff.vault.textconv", "vault git-filter textconv"),
+];
+
+// Seals `plaintext` for the repository. `indexed` is the file git holds now,
+// which is kept when it has the same text.
+pub fn clean(
+    params: Params,
+    password: &str,
+    plaintext: &[u8],
+    indexed: Option<&[u8]>,
+) -> Result<Vec<u8>, Error> {
+    if container::is_container(plaintext) {
+        return Ok(plaintext.to_vec());
+    }
+    let mut params = params;
+    if let Some(indexed) = indexed.filter(|indexed| container::is_container(indexed)) {
+        let container = Container::parse(indexed)?;
+        // a mistyped password must not seal the next commit
+        let text = Zeroizing::new(container.open_with_password(password)?);
+        if text.as_slice() == plaintext {
+            return Ok(indexed.to_vec());
+        }
+        params = container.params();
+    }
+    container::seal(params, Some(password), &[], None, plaintext)
+}
+
+// The text of a file from the repository. Files that aren't vault
+// containers are their own text.
+pub fn smudge(password: &str, data: &[u8]) -> Result<Vec<u8>, Error> {
+    if !container::is_container(data) {
+        return Ok(data.to_vec());
+    }
+    Container::parse(data)?.open_with_password(password)
+}
+
+// The file at `path` as git holds it in the index.
+pub fn indexed(path: &str) -> Option<Vec<u8>> {
+    let output = Command::new("git")
+        .args(&["cat-file", "blob", &format!(":{}", path)])
+        .output()
+        .ok()?;
+    if output.status.success() {
+        Some(output.stdout)
+    } else {
+        None
+    }
+}
+
+// Points the repository in the working directory at the filters: writes the
+// config entries and adds a line for each pattern to .gitattributes.
+pub fn setup(patterns: &[String]) -> io::Result<()> {
+    for (key, value) in CONFIG.iter() {
+        git(&["config", key, value])?;
+    }
+    let top = git(&["rev-parse", "--show-toplevel"])?;
+    let path = Path::new(top.trim_end()).join(".gitattributes");
+    let contents = match fs::read_to_string(&path) {
+        Ok(contents) => contents,
+        Err(ref err) if err.kind() == io::ErrorKind::NotFound => String::new(),
+        Err(err) => return Err(err),
+    };
+    let added = attributes(&contents, patterns);
+    if added != contents {
+        fs::write(&path, added)?;
+    }
+    Ok(())
+}
+
+fn git(args: &[&str]) -> io::Result<String> {
+    let output = Command::new("git").args(args).output()?;
+    if !output.status.success() {
+        let message = String::from_utf8_lossy(&output.stderr).trim().to_string();
+        return Err(io::Error::new(io::ErrorKind::Other, message));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+// `contents` with a line for each pattern it doesn't have yet.
+fn attributes(contents: &str, patterns: &[String]) -> String {
+    let mut contents = contents.to_string();
+    for pattern in patterns {
+        let line = format!("{} {}", pattern, ATTRIBUTES);
+        if contents.lines().any(|existing| existing.trim() == line) {
+            continue;
+        }
+        if !contents.is_empty() && !contents.ends_with('\n') {
+            contents.push('\n');
+        }
+        contents.push_str(&line);
+        contents.push('\n');
+    }
+    contents
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use state::cipher::Cipher;
+    use state::cost::Cost;
+    use state::hash::Hash;
+    use state::kdf::KDF;
+
+    #[test]
+    fn test_clean_smudge() {
+        let params = Params {
+            cipher: Cipher::CHACHA20,
+            hash: Hash::SHA2_256,
+            cost: Cost::LOW,
+            kdf: KDF::PBKDF2,
+        };
+        let sealed = clean(params, "password", b"notes", None).unwrap();
+        assert!(container::is_container(&sealed));
+        assert_eq!(smudge("password", &sealed).unwrap(), b"notes");
+        assert_eq!(smudge("wrong", &sealed).err(), Some(Error::NoMatchingKey));
+        assert_eq!(smudge("password", b"plain").unwrap(), b"plain");
+
+        // unchanged text keeps the indexed file, changed text is sealed again
+        let again = clean(params, "password", b"notes", Some(&sealed)).unwrap();
+        assert_eq!(again, sealed);
+        let changed = clean(params, "password", b"more notes", Some(&sealed)).unwrap();
+        assert_ne!(changed, sealed);
+        assert_eq!(smudge("password", &changed).unwrap(), b"more notes");
+        assert!(clean(params, "wrong", b"notes", Some(&sealed)).is_err());
+        assert_eq!(clean(params, "password", &sealed, None).unwrap(), sealed);
+    }
+
+    #[test]
+    fn test_attributes() {
+        let patterns = ["*.vault".to_string(), "secrets/*".to_string()];
+        let added = attributes("*.png binary", &patterns);
+        assert_eq!(
+            added,
+            "*.png binary\n*.vault filter=vault diff=vault\nsecrets/* filter=vault diff=vault\n"
+        );
+        assert_eq!(attributes(&added, &patterns), added);
+    }
+}
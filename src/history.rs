@@ -0,0 +1,195 @@
+use miniz_oxide::{deflate, inflate};
+use std::cmp;
+use std::str;
+use std::time::{SystemTime, UNIX_EPOCH};
+use zeroize::Zeroizing;
+
+use memory;
+
+// Earlier revisions of a vault document, newest first. They are kept in the
+// container next to the body, deflated and encrypted under the file key.
+
+pub const KEEP: usize = 10;
+
+// inflating stops here, so a small record can't unpack to gigabytes
+const MAX_LEN: usize = 256 << 20;
+// cells of the table diff builds, 16 MiB
+const MAX_CELLS: usize = 1 << 22;
+
+#[derive(Clone)]
+pub struct Revision {
+    // seconds since the epoch the revision was saved at
+    pub time: u64,
+    pub text: Zeroizing<String>,
+}
+
+pub fn seconds(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|since| since.as_secs())
+        .unwrap_or(0)
+}
+
+impl Revision {
+    pub fn new(time: u64, text: &str) -> Revision {
+        let text = Zeroizing::new(text.to_string());
+        memory::lock(text.as_bytes());
+        Revision { time, text }
+    }
+
+    pub fn now(text: &str) -> Revision {
+        Revision::new(seconds(SystemTime::now()), text)
+    }
+}
+
+// time (8, big endian) | length (4, big endian) | text, for each revision
+pub fn encode(revisions: &[Revision]) -> Zeroizing<Vec<u8>> {
+    let mut data = Zeroizing::new(Vec::new());
+    for revision in revisions {
+        data.extend_from_slice(&revision.time.to_be_bytes());
+        data.extend_from_slice(&(revision.text.len() as u32).to_be_bytes());
+        data.extend_from_slice(revision.text.as_bytes());
+    }
+    Zeroizing::new(deflate::compress_to_vec(&data, 6))
+}
+
+pub fn decode(data: &[u8]) -> Option<Vec<Revision>> {
+    let data = Zeroizing::new(inflate::decompress_to_vec_with_limit(data, MAX_LEN).ok()?);
+    let mut revisions = Vec::new();
+    let mut rest = &data[..];
+    while !rest.is_empty() {
+        if rest.len() < 12 {
+            return None;
+        }
+        let mut time = [0; 8];
+        let mut len = [0; 4];
+        time.copy_from_slice(&rest[..8]);
+        len.copy_from_slice(&rest[8..12]);
+        let len = u32::from_be_bytes(len) as usize;
+        rest = &rest[12..];
+        if rest.len() < len {
+            return None;
+        }
+        let text = str::from_utf8(&rest[..len]).ok()?;
+        revisions.push(Revision::new(u64::from_be_bytes(time), text));
+        rest = &rest[len..];
+    }
+    Some(revisions)
+}
+
+// Line diff from `old` to `new` along their longest common subsequence.
+// Kept lines start with two spaces, removed ones with "- " and added ones
+// with "+ ".
+pub fn diff(old: &str, new: &str) -> Zeroizing<String> {
+    let old = old.lines().collect::<Vec<_>>();
+    let new = new.lines().collect::<Vec<_>>();
+    // only the middle that differs needs the quadratic table
+    let prefix = old.iter().zip(&new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let (a, b) = (
+        &old[prefix..old.len() - suffix],
+        &new[prefix..new.len() - suffix],
+    );
+
+    // lcs[i][j]: length of the common subsequence of a[i..] and b[j..].
+    // Past MAX_CELLS the middle is listed as removed and then added.
+    let table = (a.len() + 1).saturating_mul(b.len() + 1) <= MAX_CELLS;
+    let mut lcs: Vec<Vec<u32>> = Vec::new();
+    if table {
+        lcs = vec![vec![0; b.len() + 1]; a.len() + 1];
+        for i in (0..a.len()).rev() {
+            for j in (0..b.len()).rev() {
+                lcs[i][j] = if a[i] == b[j] {
+                    lcs[i + 1][j + 1] + 1
+                } else {
+                    cmp::max(lcs[i + 1][j], lcs[i][j + 1])
+                };
+            }
+        }
+    }
+
+    let mut out = Zeroizing::new(String::new());
+    let mut line = |mark: &str, text: &str| {
+        out.push_str(mark);
+        out.push_str(text);
+        out.push('\n');
+    };
+    for text in &old[..prefix] {
+        line("  ", text);
+    }
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() || j < b.len() {
+        if i < a.len() && j < b.len() && a[i] == b[j] {
+            line("  ", a[i]);
+            i += 1;
+            j += 1;
+        } else if i < a.len() && (j == b.len() || !table || lcs[i + 1][j] >= lcs[i][j + 1]) {
+            line("- ", a[i]);
+            i += 1;
+        } else {
+            line("+ ", b[j]);
+            j += 1;
+        }
+    }
+    for text in &old[old.len() - suffix..] {
+        line("  ", text);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode_decode() {
+        let revisions = vec![
+            Revision {
+                time: 1_700_000_000,
+                text: Zeroizing::new("second\n".to_string()),
+            },
+            Revision {
+                time: 1_600_000_000,
+                text: Zeroizing::new(String::new()),
+            },
+        ];
+        let decoded = decode(&encode(&revisions)).unwrap();
+        assert_eq!(decoded.len(), 2);
+        assert_eq!(decoded[0].time, 1_700_000_000);
+        assert_eq!(decoded[0].text.as_str(), "second\n");
+        assert_eq!(decoded[1].text.as_str(), "");
+        assert!(decode(&encode(&[])).unwrap().is_empty());
+        assert!(decode(b"not deflate").is_none());
+    }
+
+    #[test]
+    fn test_diff() {
+        let old = "host: a\nport: 1\nuser: root\nend";
+        let new = "host: a\nport: 2\nuser: root\nkey: x\nend";
+        assert_eq!(
+            diff(old, new).as_str(),
+            "  host: a\n- port: 1\n+ port: 2\n  user: root\n+ key: x\n  end\n"
+        );
+        assert_eq!(diff("same", "same").as_str(), "  same\n");
+
+        // too long to compare line by line
+        let old = (0..3000).map(|i| format!("{}\n", i)).collect::<String>();
+        let new = (0..3000)
+            .map(|i| format!("{}\n", i * 2))
+            .collect::<String>();
+        let lines = diff(&old, &new);
+        let side = |skip: &str| {
+            lines
+                .lines()
+                .filter(|line| !line.starts_with(skip))
+                .map(|line| format!("{}\n", &line[2..]))
+                .collect::<String>()
+        };
+        assert_eq!(side("+ "), old);
+        assert_eq!(side("- "), new);
+    }
+}
@@ -0,0 +1,91 @@
+use zeroize::Zeroizing;
+
+use crypto;
+use memory;
+use state::cost::Cost;
+use state::hash::Hash;
+use state::kdf::KDF;
+
+// Locking the editor keeps the text sealed in memory under a key derived from
+// the document password. The key itself is dropped on lock, so only the
+// password opens the text again.
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+
+pub struct Key {
+    salt: [u8; SALT_LEN],
+    key: Zeroizing<Vec<u8>>,
+}
+
+pub struct Locked {
+    salt: [u8; SALT_LEN],
+    sealed: Vec<u8>,
+}
+
+fn derive(password: &str, salt: &[u8]) -> Zeroizing<Vec<u8>> {
+    let mut key = memory::key(32);
+    crypto::derive(
+        Hash::SHA2_256,
+        KDF::ARGON2,
+        Cost::LOW,
+        password.as_bytes(),
+        salt,
+        &mut key,
+    );
+    key
+}
+
+impl Key {
+    pub fn new(password: &str) -> Key {
+        let mut salt = [0; SALT_LEN];
+        crypto::random_bytes(&mut salt);
+        Key {
+            salt,
+            key: derive(password, &salt),
+        }
+    }
+
+    pub fn seal(self, plaintext: &str) -> Locked {
+        let mut nonce = [0; NONCE_LEN];
+        crypto::random_bytes(&mut nonce);
+        let mut sealed = nonce.to_vec();
+        sealed.extend(crypto::seal(&self.key, &nonce, &[], plaintext.as_bytes()));
+        Locked {
+            salt: self.salt,
+            sealed,
+        }
+    }
+}
+
+impl Locked {
+    // Gives the text back together with the key to lock it with next time.
+    pub fn open(&self, password: &str) -> Option<(Zeroizing<String>, Key)> {
+        let key = derive(password, &self.salt);
+        let (nonce, sealed) = self.sealed.split_at(NONCE_LEN);
+        let plaintext = crypto::open(&key, nonce, &[], sealed)?;
+        let plaintext = String::from_utf8(plaintext.to_vec()).ok()?;
+        Some((
+            Zeroizing::new(plaintext),
+            Key {
+                salt: self.salt,
+                key,
+            },
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lock() {
+        let locked = Key::new("password").seal("unsaved notes");
+        assert!(locked.open("wrong").is_none());
+        let (plaintext, key) = locked.open("password").unwrap();
+        assert_eq!(plaintext.as_str(), "unsaved notes");
+
+        let locked = key.seal("more notes");
+        assert_eq!(locked.open("password").unwrap().0.as_str(), "more notes");
+    }
+}
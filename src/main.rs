@@ -1,21 +1,57 @@
 extern crate argon2;
 extern crate crypto as cryptolib;
+extern crate gdk;
 extern crate gdk_pixbuf;
 extern crate gio;
 extern crate glib;
 extern crate gtk;
+extern crate libc;
+extern crate miniz_oxide;
 extern crate rand;
+extern crate rpassword;
+extern crate rustc_serialize;
+extern crate zeroize;
 
 use gio::prelude::*;
 use std::env::args;
 
+mod age;
+mod agent;
+mod ansible;
+mod atomic;
+mod cli;
 mod config;
+mod container;
 mod crypto;
 mod editor;
+mod fernet;
+mod gitfilter;
+mod history;
+mod lock;
+mod memory;
+mod mlkem;
+mod openpgp;
+mod openssl;
+mod recipient;
+mod recovery;
+mod sandbox;
+mod section;
+mod signature;
 mod state;
 mod string;
+mod structured;
 
 fn main() {
+    if let Err(err) = memory::disable_core_dumps() {
+        eprintln!("vault: could not disable core dumps: {}", err);
+    }
+    memory::lock_all();
+
+    let args = args().collect::<Vec<_>>();
+    if let Some(code) = cli::run(&args) {
+        std::process::exit(code);
+    }
+
     let application = gtk::Application::new(Some(config::ID), Default::default())
         .expect("Failed to initialize GTK.");
 
@@ -23,5 +59,5 @@ fn main() {
         editor::Editor::new().run(app);
     });
 
-    application.run(&args().collect::<Vec<_>>());
+    application.run(&args);
 }
@@ -0,0 +1,180 @@
+use libc;
+use std::fmt;
+use std::io;
+use std::sync::atomic::{AtomicI32, Ordering};
+use zeroize::Zeroizing;
+
+// Keeps decrypted text and keys out of swap and core dumps. A process that
+// may lock any amount of memory locks its whole address space, which also
+// covers GTK's copies of the text. Otherwise the pages holding keys,
+// passwords and plaintext are locked as they are allocated, which fits in the
+// RLIMIT_MEMLOCK desktops default to. Pages stay locked once freed, since
+// other secrets may share them.
+
+// errno of the first lock that failed
+static FAILED: AtomicI32 = AtomicI32::new(0);
+
+#[derive(Debug)]
+pub enum Error {
+    // RLIMIT_MEMLOCK in bytes
+    Limit(u64),
+    Os(io::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Limit(limit) => write!(
+                f,
+                "memory not locked, RLIMIT_MEMLOCK is {} KiB",
+                limit / 1024
+            ),
+            Error::Os(err) => write!(f, "memory not locked: {}", err),
+        }
+    }
+}
+
+pub fn disable_core_dumps() -> io::Result<()> {
+    let limit = libc::rlimit {
+        rlim_cur: 0,
+        rlim_max: 0,
+    };
+    if unsafe { libc::setrlimit(libc::RLIMIT_CORE, &limit) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // also keeps other processes of the same user from attaching with ptrace
+    #[cfg(target_os = "linux")]
+    {
+        if unsafe { libc::prctl(libc::PR_SET_DUMPABLE, 0, 0, 0, 0) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+// Locks every page mapped now or later when no limit applies. Once
+// MCL_FUTURE is set, allocations past RLIMIT_MEMLOCK fail, so a limited
+// process only locks its secrets.
+pub fn lock_all() {
+    // root has CAP_IPC_LOCK, which lifts the limit
+    if memlock_limit() != Some(libc::RLIM_INFINITY) && unsafe { libc::geteuid() } != 0 {
+        return;
+    }
+    if unsafe { libc::mlockall(libc::MCL_CURRENT | libc::MCL_FUTURE) } != 0 {
+        failed();
+    }
+}
+
+// Locks the pages `bytes` lies on.
+pub fn lock(bytes: &[u8]) {
+    if bytes.is_empty() {
+        return;
+    }
+    let page = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as usize;
+    let start = bytes.as_ptr() as usize & !(page - 1);
+    let end = bytes.as_ptr() as usize + bytes.len();
+    if unsafe { libc::mlock(start as *const libc::c_void, end - start) } != 0 {
+        failed();
+    }
+}
+
+// A zeroed buffer of `len` bytes on locked pages, for a key.
+pub fn key(len: usize) -> Zeroizing<Vec<u8>> {
+    let key = Zeroizing::new(vec![0; len]);
+    lock(&key);
+    key
+}
+
+// Why secrets may still reach swap, once locking some of them failed.
+pub fn warning() -> Option<Error> {
+    match FAILED.load(Ordering::Relaxed) {
+        0 => None,
+        libc::ENOMEM | libc::EPERM => match memlock_limit() {
+            Some(limit) if limit != libc::RLIM_INFINITY => Some(Error::Limit(limit)),
+            _ => Some(Error::Os(io::Error::from_raw_os_error(libc::ENOMEM))),
+        },
+        errno => Some(Error::Os(io::Error::from_raw_os_error(errno))),
+    }
+}
+
+fn failed() {
+    let errno = io::Error::last_os_error()
+        .raw_os_error()
+        .unwrap_or(libc::EINVAL);
+    let _ = FAILED.compare_exchange(0, errno, Ordering::Relaxed, Ordering::Relaxed);
+}
+
+fn memlock_limit() -> Option<u64> {
+    let mut limit = libc::rlimit {
+        rlim_cur: 0,
+        rlim_max: 0,
+    };
+    if unsafe { libc::getrlimit(libc::RLIMIT_MEMLOCK, &mut limit) } != 0 {
+        return None;
+    }
+    Some(limit.rlim_cur)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs;
+    use std::process::Command;
+
+    const CHILD: &str = "VAULT_TEST_CHILD";
+
+    // Runs `check` in a child process started for the test named `test`,
+    // since what it checks applies to the whole process.
+    fn in_child(test: &str, check: fn()) {
+        if env::var_os(CHILD).is_some() {
+            check();
+            return;
+        }
+        let output = Command::new(env::current_exe().unwrap())
+            .args(&[test, "--exact", "--test-threads=1"])
+            .env(CHILD, "1")
+            .output()
+            .unwrap();
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        assert!(output.status.success(), "{}", stdout);
+        assert!(stdout.contains("1 passed"), "{}", stdout);
+    }
+
+    #[test]
+    fn test_disable_core_dumps() {
+        in_child("memory::tests::test_disable_core_dumps", || {
+            disable_core_dumps().unwrap();
+            let mut limit = libc::rlimit {
+                rlim_cur: 1,
+                rlim_max: 1,
+            };
+            unsafe { libc::getrlimit(libc::RLIMIT_CORE, &mut limit) };
+            assert_eq!(limit.rlim_cur, 0);
+            assert_eq!(unsafe { libc::prctl(libc::PR_GET_DUMPABLE) }, 0);
+        });
+    }
+
+    #[test]
+    fn test_lock() {
+        in_child("memory::tests::test_lock", || {
+            let key = key(32);
+            assert_eq!(*key, vec![0; 32]);
+            assert!(warning().is_none());
+            let status = fs::read_to_string("/proc/self/status").unwrap();
+            let locked = status
+                .lines()
+                .find(|line| line.starts_with("VmLck:"))
+                .unwrap();
+            assert_ne!(locked.split_whitespace().nth(1), Some("0"));
+        });
+    }
+
+    #[test]
+    fn test_error() {
+        assert_eq!(
+            Error::Limit(8 << 20).to_string(),
+            "memory not locked, RLIMIT_MEMLOCK is 8192 KiB"
+        );
+    }
+}
@@ -0,0 +1,495 @@
+// ML-KEM-768 as specified in FIPS 203.
+use cryptolib::digest::Digest;
+use cryptolib::sha3::Sha3;
+use cryptolib::util::fixed_time_eq;
+use zeroize::Zeroize;
+
+const N: usize = 256;
+const Q: u32 = 3329;
+const K: usize = 3;
+const ETA1: usize = 2;
+const ETA2: usize = 2;
+const DU: usize = 10;
+const DV: usize = 4;
+
+pub const ENCAPSULATION_KEY_LEN: usize = 384 * K + 32;
+pub const DECAPSULATION_KEY_LEN: usize = 768 * K + 96;
+pub const CIPHERTEXT_LEN: usize = 32 * (DU * K + DV);
+pub const SHARED_SECRET_LEN: usize = 32;
+
+type Poly = [u16; N];
+
+// 17^BitRev7(i) mod q
+const ZETAS: [u16; 128] = [
+    1, 1729, 2580, 3289, 2642, 630, 1897, 848, 1062, 1919, 193, 797, 2786, 3260, 569, 1746, 296,
+    2447, 1339, 1476, 3046, 56, 2240, 1333, 1426, 2094, 535, 2882, 2393, 2879, 1974, 821, 289, 331,
+    3253, 1756, 1197, 2304, 2277, 2055, 650, 1977, 2513, 632, 2865, 33, 1320, 1915, 2319, 1435,
+    807, 452, 1438, 2868, 1534, 2402, 2647, 2617, 1481, 648, 2474, 3110, 1227, 910, 17, 2761, 583,
+    2649, 1637, 723, 2288, 1100, 1409, 2662, 3281, 233, 756, 2156, 3015, 3050, 1703, 1651, 2789,
+    1789, 1847, 952, 1461, 2687, 939, 2308, 2437, 2388, 733, 2337, 268, 641, 1584, 2298, 2037,
+    3220, 375, 2549, 2090, 1645, 1063, 319, 2773, 757, 2099, 561, 2466, 2594, 2804, 1092, 403,
+    1026, 1143, 2150, 2775, 886, 1722, 1212, 1874, 1029, 2110, 2935, 885, 2154,
+];
+
+// 17^(2 * BitRev7(i) + 1) mod q
+const GAMMAS: [u16; 128] = [
+    17, 3312, 2761, 568, 583, 2746, 2649, 680, 1637, 1692, 723, 2606, 2288, 1041, 1100, 2229, 1409,
+    1920, 2662, 667, 3281, 48, 233, 3096, 756, 2573, 2156, 1173, 3015, 314, 3050, 279, 1703, 1626,
+    1651, 1678, 2789, 540, 1789, 1540, 1847, 1482, 952, 2377, 1461, 1868, 2687, 642, 939, 2390,
+    2308, 1021, 2437, 892, 2388, 941, 733, 2596, 2337, 992, 268, 3061, 641, 2688, 1584, 1745, 2298,
+    1031, 2037, 1292, 3220, 109, 375, 2954, 2549, 780, 2090, 1239, 1645, 1684, 1063, 2266, 319,
+    3010, 2773, 556, 757, 2572, 2099, 1230, 561, 2768, 2466, 863, 2594, 735, 2804, 525, 1092, 2237,
+    403, 2926, 1026, 2303, 1143, 2186, 2150, 1179, 2775, 554, 886, 2443, 1722, 1607, 1212, 2117,
+    1874, 1455, 1029, 2300, 2110, 1219, 2935, 394, 885, 2444, 2154, 1175,
+];
+
+pub struct EncapsulationKey([u8; ENCAPSULATION_KEY_LEN]);
+
+pub struct DecapsulationKey([u8; DECAPSULATION_KEY_LEN]);
+
+impl EncapsulationKey {
+    pub fn from_bytes(bytes: &[u8]) -> Option<EncapsulationKey> {
+        if bytes.len() != ENCAPSULATION_KEY_LEN {
+            return None;
+        }
+        // modulus check: every coefficient of t must already be reduced
+        for chunk in bytes[..384 * K].chunks(384) {
+            if byte_decode(chunk, 12).iter().any(|&c| c as u32 >= Q) {
+                return None;
+            }
+        }
+        let mut ek = [0; ENCAPSULATION_KEY_LEN];
+        ek.copy_from_slice(bytes);
+        Some(EncapsulationKey(ek))
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    pub fn encapsulate(&self, m: &[u8; 32]) -> ([u8; CIPHERTEXT_LEN], [u8; SHARED_SECRET_LEN]) {
+        let mut input = [0; 64];
+        input[..32].copy_from_slice(m);
+        h(&self.0, &mut input[32..]);
+        let (key, r) = g(&input);
+        (k_pke_encrypt(&self.0, m, &r), key)
+    }
+}
+
+impl DecapsulationKey {
+    pub fn from_seed(d: &[u8; 32], z: &[u8; 32]) -> (EncapsulationKey, DecapsulationKey) {
+        let (ek, dk_pke) = k_pke_keygen(d);
+        let mut dk = [0; DECAPSULATION_KEY_LEN];
+        dk[..384 * K].copy_from_slice(&dk_pke);
+        dk[384 * K..768 * K + 32].copy_from_slice(&ek);
+        h(&ek, &mut dk[768 * K + 32..768 * K + 64]);
+        dk[768 * K + 64..].copy_from_slice(z);
+        (EncapsulationKey(ek), DecapsulationKey(dk))
+    }
+
+    pub fn decapsulate(&self, c: &[u8]) -> Option<[u8; SHARED_SECRET_LEN]> {
+        if c.len() != CIPHERTEXT_LEN {
+            return None;
+        }
+        let dk_pke = &self.0[..384 * K];
+        let ek = &self.0[384 * K..768 * K + 32];
+        let hash = &self.0[768 * K + 32..768 * K + 64];
+        let z = &self.0[768 * K + 64..];
+
+        let m = k_pke_decrypt(dk_pke, c);
+        let mut input = [0; 64];
+        input[..32].copy_from_slice(&m);
+        input[32..].copy_from_slice(hash);
+        let (key, r) = g(&input);
+
+        let mut rejected = [0; SHARED_SECRET_LEN];
+        let mut shake = Sha3::shake_256();
+        shake.input(z);
+        shake.input(c);
+        shake.result(&mut rejected);
+
+        let c_prime = k_pke_encrypt(ek, &m, &r);
+        if fixed_time_eq(c, &c_prime[..]) {
+            Some(key)
+        } else {
+            Some(rejected)
+        }
+    }
+}
+
+impl Drop for DecapsulationKey {
+    fn drop(&mut self) {
+        self.0.zeroize();
+    }
+}
+
+fn h(input: &[u8], out: &mut [u8]) {
+    let mut sha3 = Sha3::sha3_256();
+    sha3.input(input);
+    sha3.result(out);
+}
+
+fn g(input: &[u8]) -> ([u8; 32], [u8; 32]) {
+    let mut out = [0; 64];
+    let mut sha3 = Sha3::sha3_512();
+    sha3.input(input);
+    sha3.result(&mut out);
+    let (mut a, mut b) = ([0; 32], [0; 32]);
+    a.copy_from_slice(&out[..32]);
+    b.copy_from_slice(&out[32..]);
+    (a, b)
+}
+
+fn prf(eta: usize, s: &[u8], b: u8) -> Poly {
+    let mut out = [0; 64 * 3];
+    let out = &mut out[..64 * eta];
+    let mut shake = Sha3::shake_256();
+    shake.input(s);
+    shake.input(&[b]);
+    shake.result(out);
+    sample_poly_cbd(eta, out)
+}
+
+fn sample_ntt(rho: &[u8], j: u8, i: u8) -> Poly {
+    let mut xof = Sha3::shake_128();
+    xof.input(rho);
+    xof.input(&[j, i]);
+
+    let mut a = [0; N];
+    let mut block = [0; 168];
+    let mut pos = block.len();
+    let mut n = 0;
+    while n < N {
+        if pos == block.len() {
+            xof.result(&mut block);
+            pos = 0;
+        }
+        let c = &block[pos..pos + 3];
+        pos += 3;
+        let d1 = c[0] as u16 | ((c[1] as u16 & 0x0f) << 8);
+        let d2 = (c[1] as u16 >> 4) | ((c[2] as u16) << 4);
+        if (d1 as u32) < Q {
+            a[n] = d1;
+            n += 1;
+        }
+        if (d2 as u32) < Q && n < N {
+            a[n] = d2;
+            n += 1;
+        }
+    }
+    a
+}
+
+fn sample_poly_cbd(eta: usize, bytes: &[u8]) -> Poly {
+    let bit = |i: usize| ((bytes[i / 8] >> (i % 8)) & 1) as u32;
+    let mut f = [0; N];
+    for (i, coeff) in f.iter_mut().enumerate() {
+        let x: u32 = (0..eta).map(|j| bit(2 * i * eta + j)).sum();
+        let y: u32 = (0..eta).map(|j| bit(2 * i * eta + eta + j)).sum();
+        *coeff = ((x + Q - y) % Q) as u16;
+    }
+    f
+}
+
+fn ntt(f: &mut Poly) {
+    let mut i = 1;
+    let mut len = 128;
+    while len >= 2 {
+        for start in (0..N).step_by(2 * len) {
+            let zeta = ZETAS[i] as u32;
+            i += 1;
+            for j in start..start + len {
+                let t = zeta * f[j + len] as u32 % Q;
+                f[j + len] = ((f[j] as u32 + Q - t) % Q) as u16;
+                f[j] = ((f[j] as u32 + t) % Q) as u16;
+            }
+        }
+        len /= 2;
+    }
+}
+
+fn ntt_inverse(f: &mut Poly) {
+    let mut i = 127;
+    let mut len = 2;
+    while len <= 128 {
+        for start in (0..N).step_by(2 * len) {
+            let zeta = ZETAS[i] as u32;
+            i -= 1;
+            for j in start..start + len {
+                let t = f[j] as u32;
+                f[j] = ((t + f[j + len] as u32) % Q) as u16;
+                f[j + len] = (zeta * ((f[j + len] as u32 + Q - t) % Q) % Q) as u16;
+            }
+        }
+        len *= 2;
+    }
+    for c in f.iter_mut() {
+        *c = (*c as u32 * 3303 % Q) as u16;
+    }
+}
+
+fn multiply_ntts(f: &Poly, g: &Poly) -> Poly {
+    let mut h = [0; N];
+    for i in 0..N / 2 {
+        let (a0, a1) = (f[2 * i] as u32, f[2 * i + 1] as u32);
+        let (b0, b1) = (g[2 * i] as u32, g[2 * i + 1] as u32);
+        let gamma = GAMMAS[i] as u32;
+        h[2 * i] = ((a0 * b0 + a1 * b1 % Q * gamma) % Q) as u16;
+        h[2 * i + 1] = ((a0 * b1 + a1 * b0) % Q) as u16;
+    }
+    h
+}
+
+fn add(f: &mut Poly, g: &Poly) {
+    for (a, b) in f.iter_mut().zip(g.iter()) {
+        *a = ((*a as u32 + *b as u32) % Q) as u16;
+    }
+}
+
+fn sub(f: &Poly, g: &Poly) -> Poly {
+    let mut h = [0; N];
+    for i in 0..N {
+        h[i] = ((f[i] as u32 + Q - g[i] as u32) % Q) as u16;
+    }
+    h
+}
+
+// The divisions below are by the constant 2q, which compiles to a
+// multiplication and keeps Compress free of secret-dependent timing.
+fn compress(f: &mut Poly, d: usize) {
+    for c in f.iter_mut() {
+        let x = ((*c as u64) << (d + 1)) + Q as u64;
+        *c = ((x / (2 * Q as u64)) & ((1 << d) - 1)) as u16;
+    }
+}
+
+fn decompress(f: &mut Poly, d: usize) {
+    for c in f.iter_mut() {
+        *c = ((*c as u32 * Q + (1 << (d - 1))) >> d) as u16;
+    }
+}
+
+fn byte_encode(f: &Poly, d: usize, out: &mut [u8]) {
+    let mut acc: u32 = 0;
+    let mut bits = 0;
+    let mut pos = 0;
+    for &c in f.iter() {
+        acc |= (c as u32) << bits;
+        bits += d;
+        while bits >= 8 {
+            out[pos] = acc as u8;
+            pos += 1;
+            acc >>= 8;
+            bits -= 8;
+        }
+    }
+}
+
+fn byte_decode(bytes: &[u8], d: usize) -> Poly {
+    let mut f = [0; N];
+    let mut acc: u32 = 0;
+    let mut bits = 0;
+    let mut n = 0;
+    for &b in bytes.iter() {
+        acc |= (b as u32) << bits;
+        bits += 8;
+        while bits >= d && n < N {
+            f[n] = (acc & ((1 << d) - 1)) as u16;
+            n += 1;
+            acc >>= d;
+            bits -= d;
+        }
+    }
+    f
+}
+
+fn matrix(rho: &[u8]) -> [[Poly; K]; K] {
+    let mut a = [[[0; N]; K]; K];
+    for (i, row) in a.iter_mut().enumerate() {
+        for (j, entry) in row.iter_mut().enumerate() {
+            *entry = sample_ntt(rho, j as u8, i as u8);
+        }
+    }
+    a
+}
+
+fn k_pke_keygen(d: &[u8; 32]) -> ([u8; ENCAPSULATION_KEY_LEN], [u8; 384 * K]) {
+    let mut seed = [0; 33];
+    seed[..32].copy_from_slice(d);
+    seed[32] = K as u8;
+    let (rho, sigma) = g(&seed);
+    let a = matrix(&rho);
+
+    let mut n = 0;
+    let mut s = [[0; N]; K];
+    for poly in s.iter_mut() {
+        *poly = prf(ETA1, &sigma, n);
+        ntt(poly);
+        n += 1;
+    }
+    let mut e = [[0; N]; K];
+    for poly in e.iter_mut() {
+        *poly = prf(ETA1, &sigma, n);
+        ntt(poly);
+        n += 1;
+    }
+
+    let mut ek = [0; ENCAPSULATION_KEY_LEN];
+    let mut dk = [0; 384 * K];
+    for i in 0..K {
+        let mut t = e[i];
+        for j in 0..K {
+            add(&mut t, &multiply_ntts(&a[i][j], &s[j]));
+        }
+        byte_encode(&t, 12, &mut ek[384 * i..384 * (i + 1)]);
+        byte_encode(&s[i], 12, &mut dk[384 * i..384 * (i + 1)]);
+    }
+    ek[384 * K..].copy_from_slice(&rho);
+    (ek, dk)
+}
+
+fn k_pke_encrypt(ek: &[u8], m: &[u8; 32], r: &[u8; 32]) -> [u8; CIPHERTEXT_LEN] {
+    let mut t = [[0; N]; K];
+    for (i, poly) in t.iter_mut().enumerate() {
+        *poly = byte_decode(&ek[384 * i..384 * (i + 1)], 12);
+    }
+    let a = matrix(&ek[384 * K..]);
+
+    let mut n = 0;
+    let mut y = [[0; N]; K];
+    for poly in y.iter_mut() {
+        *poly = prf(ETA1, r, n);
+        ntt(poly);
+        n += 1;
+    }
+    let mut e1 = [[0; N]; K];
+    for poly in e1.iter_mut() {
+        *poly = prf(ETA2, r, n);
+        n += 1;
+    }
+    let e2 = prf(ETA2, r, n);
+
+    let mut c = [0; CIPHERTEXT_LEN];
+    for i in 0..K {
+        let mut u = [0; N];
+        for j in 0..K {
+            add(&mut u, &multiply_ntts(&a[j][i], &y[j]));
+        }
+        ntt_inverse(&mut u);
+        add(&mut u, &e1[i]);
+        compress(&mut u, DU);
+        byte_encode(&u, DU, &mut c[32 * DU * i..32 * DU * (i + 1)]);
+    }
+
+    let mut mu = byte_decode(m, 1);
+    decompress(&mut mu, 1);
+    let mut v = [0; N];
+    for j in 0..K {
+        add(&mut v, &multiply_ntts(&t[j], &y[j]));
+    }
+    ntt_inverse(&mut v);
+    add(&mut v, &e2);
+    add(&mut v, &mu);
+    compress(&mut v, DV);
+    byte_encode(&v, DV, &mut c[32 * DU * K..]);
+    c
+}
+
+fn k_pke_decrypt(dk: &[u8], c: &[u8]) -> [u8; 32] {
+    let mut w = [0; N];
+    for i in 0..K {
+        let mut u = byte_decode(&c[32 * DU * i..32 * DU * (i + 1)], DU);
+        decompress(&mut u, DU);
+        ntt(&mut u);
+        let s = byte_decode(&dk[384 * i..384 * (i + 1)], 12);
+        add(&mut w, &multiply_ntts(&s, &u));
+    }
+    ntt_inverse(&mut w);
+
+    let mut v = byte_decode(&c[32 * DU * K..], DV);
+    decompress(&mut v, DV);
+    let mut w = sub(&v, &w);
+    compress(&mut w, 1);
+
+    let mut m = [0; 32];
+    byte_encode(&w, 1, &mut m);
+    m
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rustc_serialize::hex::ToHex;
+
+    fn digest(bytes: &[u8]) -> String {
+        let mut out = [0; 32];
+        h(bytes, &mut out);
+        out.to_hex()
+    }
+
+    #[test]
+    fn test_known_answer() {
+        // computed with the ML-KEM-768 of OpenSSL 3.5; the keys and the
+        // ciphertext are compared by their SHA3-256 digests
+        let mut d = [0; 32];
+        let mut z = [0; 32];
+        let mut m = [0; 32];
+        for i in 0..32 {
+            d[i] = i as u8;
+            z[i] = 32 + i as u8;
+            m[i] = 64 + i as u8;
+        }
+        let (ek, dk) = DecapsulationKey::from_seed(&d, &z);
+        assert_eq!(
+            digest(ek.as_bytes()),
+            "a24e16d8f8f9383a95b77050f4d9fd2f5733eec1d63ef3c23ebf9918173669a7"
+        );
+        assert_eq!(
+            digest(&dk.0),
+            "1149f17c3c4ac6ab1e3e2d9d8bd0171355ac0fa31bb8855c48ceade874c0864b"
+        );
+
+        let (c, key) = ek.encapsulate(&m);
+        assert_eq!(
+            digest(&c),
+            "b4cfbd24cef67afd3764276c6980e0f88f8e9ca57f59b7f12fe1a9c1e72f4710"
+        );
+        assert_eq!(
+            key.to_hex(),
+            "9cddd089ffe70e3996e76f7c8d06746df34d07e8657bc0fcf2bb0e1c3084aea1"
+        );
+        assert_eq!(dk.decapsulate(&c).unwrap(), key);
+
+        // implicit rejection
+        let mut tampered = c;
+        tampered[0] ^= 1;
+        assert_eq!(
+            dk.decapsulate(&tampered).unwrap().to_hex(),
+            "dcfc80c6db46ff7028e3a4398651c063ae7a42c107a6dc8cb07141861698ab92"
+        );
+    }
+
+    #[test]
+    fn test_encapsulate_decapsulate() {
+        for seed in 0..4u8 {
+            let (ek, dk) = DecapsulationKey::from_seed(&[seed; 32], &[!seed; 32]);
+            let (c, key) = ek.encapsulate(&[seed.wrapping_mul(7); 32]);
+            assert_eq!(dk.decapsulate(&c).unwrap(), key);
+
+            let mut tampered = c;
+            tampered[0] ^= 1;
+            assert!(dk.decapsulate(&tampered).unwrap() != key);
+        }
+    }
+
+    #[test]
+    fn test_encapsulation_key_modulus_check() {
+        let (ek, _) = DecapsulationKey::from_seed(&[1; 32], &[2; 32]);
+        assert!(EncapsulationKey::from_bytes(ek.as_bytes()).is_some());
+
+        let mut bytes = ek.as_bytes().to_vec();
+        bytes[0] = 0xff;
+        bytes[1] |= 0x0f;
+        assert!(EncapsulationKey::from_bytes(&bytes).is_none());
+    }
+}
@@ -0,0 +1,956 @@
+use argon2;
+use cryptolib::aead::AeadDecryptor;
+use cryptolib::aes::KeySize;
+use cryptolib::aes_gcm::AesGcm;
+use cryptolib::aessafe::{
+    AesSafe128Decryptor, AesSafe128Encryptor, AesSafe192Decryptor, AesSafe192Encryptor,
+    AesSafe256Decryptor, AesSafe256Encryptor,
+};
+use cryptolib::digest::Digest;
+use cryptolib::md5::Md5;
+use cryptolib::ripemd160::Ripemd160;
+use cryptolib::sha1::Sha1;
+use cryptolib::sha2::{Sha224, Sha256, Sha384, Sha512};
+use cryptolib::symmetriccipher::{BlockDecryptor, BlockEncryptor};
+use cryptolib::util::fixed_time_eq;
+use miniz_oxide::inflate;
+use rustc_serialize::base64::{FromBase64, Newline, ToBase64, STANDARD};
+use std::str;
+use zeroize::Zeroizing;
+
+use container::Error;
+use crypto;
+
+// Symmetric encryption as done by `gpg --symmetric`: a password encrypted
+// session key (SKESK) followed by integrity protected data. Version 1 data
+// (CFB with a SHA-1 MDC) is written since every OpenPGP implementation reads
+// it; the OCB and GCM packets of newer implementations are read as well.
+const ARMOR_BEGIN: &str = "-----BEGIN PGP MESSAGE-----";
+const ARMOR_END: &str = "-----END PGP MESSAGE-----";
+
+const TAG_PKESK: u8 = 1;
+const TAG_SIGNATURE: u8 = 2;
+const TAG_SKESK: u8 = 3;
+const TAG_ONE_PASS_SIGNATURE: u8 = 4;
+const TAG_COMPRESSED: u8 = 8;
+const TAG_SED: u8 = 9;
+const TAG_MARKER: u8 = 10;
+const TAG_LITERAL: u8 = 11;
+const TAG_SEIPD: u8 = 18;
+// LibrePGP OCB encrypted data, written by GnuPG 2.3 and later
+const TAG_AEAD: u8 = 20;
+
+const AES128: u8 = 7;
+const AES192: u8 = 8;
+const AES256: u8 = 9;
+
+const MD5: u8 = 1;
+const SHA1: u8 = 2;
+const RIPEMD160: u8 = 3;
+const SHA256: u8 = 8;
+const SHA384: u8 = 9;
+const SHA512: u8 = 10;
+const SHA224: u8 = 11;
+
+const EAX: u8 = 1;
+const OCB: u8 = 2;
+const GCM: u8 = 3;
+
+const BLOCK_LEN: usize = 16;
+const TAG_LEN: usize = 16;
+const MDC_LEN: usize = 22;
+
+// coded iteration count, 65011712 bytes hashed like GnuPG does by default
+const S2K_COUNT: u8 = 0xff;
+// the most Argon2 memory a file may ask for, 2 GiB as in the first parameter
+// choice of RFC 9580
+const MAX_ARGON2_MEMORY: u8 = 21;
+// the largest message that is decompressed
+const MAX_LEN: usize = 256 << 20;
+
+enum S2K {
+    Simple(u8),
+    Salted(u8, Vec<u8>),
+    // hash, salt, coded count
+    Iterated(u8, Vec<u8>, u8),
+    // salt, passes, parallelism, log2 of the memory in KiB
+    Argon2(Vec<u8>, u8, u8, u8),
+}
+
+pub fn is_openpgp(data: &[u8]) -> bool {
+    if data.starts_with(ARMOR_BEGIN.as_bytes()) {
+        return true;
+    }
+    // symmetrically encrypted messages start with a SKESK packet, possibly
+    // behind a marker packet
+    let mut pos = 0;
+    match read_packet(data, &mut pos) {
+        Ok((TAG_SKESK, body)) => match body.first() {
+            Some(4) | Some(5) => body.len() > 2 && key_len(body[1]).is_ok(),
+            Some(6) => body.len() > 3 && key_len(body[2]).is_ok(),
+            _ => false,
+        },
+        Ok((TAG_MARKER, body)) => body == b"PGP",
+        _ => false,
+    }
+}
+
+pub fn encrypt(passphrase: &str, plaintext: &[u8]) -> Vec<u8> {
+    encrypt_with_count(passphrase, S2K_COUNT, plaintext)
+}
+
+fn encrypt_with_count(passphrase: &str, count: u8, plaintext: &[u8]) -> Vec<u8> {
+    let mut salt = vec![0; 8];
+    crypto::random_bytes(&mut salt);
+    let s2k = S2K::Iterated(SHA256, salt, count);
+    let mut key = Zeroizing::new(vec![0; 32]);
+    s2k.derive(passphrase, &mut key)
+        .expect("SHA-256 is supported");
+
+    let mut out = Vec::new();
+    let mut skesk = vec![4, AES256];
+    s2k.write(&mut skesk);
+    write_packet(&mut out, TAG_SKESK, &skesk);
+
+    // binary literal data without a file name or date
+    let mut literal = vec![b'b', 0, 0, 0, 0, 0];
+    literal.extend_from_slice(plaintext);
+
+    // random block whose last two bytes are repeated for a quick key check
+    let mut data = vec![0; BLOCK_LEN + 2];
+    crypto::random_bytes(&mut data[..BLOCK_LEN]);
+    data[BLOCK_LEN] = data[BLOCK_LEN - 2];
+    data[BLOCK_LEN + 1] = data[BLOCK_LEN - 1];
+    write_packet(&mut data, TAG_LITERAL, &literal);
+    data.extend_from_slice(&[0xd3, 0x14]);
+    let mut mdc = [0; 20];
+    let mut sha1 = Sha1::new();
+    sha1.input(&data);
+    sha1.result(&mut mdc);
+    data.extend_from_slice(&mdc);
+
+    let mut seipd = vec![1];
+    seipd.extend(cfb(AES256, &key, &data, false).expect("AES-256 is supported"));
+    write_packet(&mut out, TAG_SEIPD, &seipd);
+    out
+}
+
+pub fn decrypt(data: &[u8], passphrase: &str) -> Result<Vec<u8>, Error> {
+    let data = if data.starts_with(ARMOR_BEGIN.as_bytes()) {
+        dearmor(data)?
+    } else {
+        data.to_vec()
+    };
+
+    let mut skesks = Vec::new();
+    let mut pos = 0;
+    while pos < data.len() {
+        let (tag, body) = read_packet(&data, &mut pos)?;
+        match tag {
+            TAG_SKESK => skesks.push(body),
+            TAG_PKESK | TAG_MARKER => {}
+            TAG_SEIPD | TAG_AEAD => {
+                for skesk in &skesks {
+                    let (cipher, key) = match session_key(skesk, passphrase)? {
+                        Some(session_key) => session_key,
+                        None => continue,
+                    };
+                    match decrypt_data(tag, &body, cipher, &key) {
+                        Ok(packets) => return literal_data(&packets),
+                        Err(Error::NoMatchingKey) => continue,
+                        Err(err) => return Err(err),
+                    }
+                }
+                return Err(Error::NoMatchingKey);
+            }
+            // data without integrity protection is not worth reading
+            TAG_SED => return Err(Error::Unsupported),
+            _ => return Err(Error::Format),
+        }
+    }
+    Err(Error::Format)
+}
+
+pub fn armor(data: &[u8]) -> String {
+    let mut config = STANDARD;
+    config.newline = Newline::LF;
+    config.line_length = Some(64);
+    let crc = crc24(data).to_be_bytes();
+    format!(
+        "{}\n\n{}\n={}\n{}\n",
+        ARMOR_BEGIN,
+        data.to_base64(config),
+        crc[1..].to_base64(STANDARD),
+        ARMOR_END
+    )
+}
+
+fn dearmor(data: &[u8]) -> Result<Vec<u8>, Error> {
+    let text = str::from_utf8(data).map_err(|_| Error::Format)?;
+    let mut lines = text.lines().map(str::trim_end);
+    if lines.next() != Some(ARMOR_BEGIN) {
+        return Err(Error::Format);
+    }
+    // armor headers end with a blank line
+    let mut lines = lines
+        .skip_while(|line| line.contains(": "))
+        .skip_while(|line| line.is_empty());
+    let mut encoded = String::new();
+    let mut checksum = None;
+    loop {
+        match lines.next() {
+            Some(ARMOR_END) => break,
+            Some(line) if line.starts_with('=') => checksum = Some(&line[1..]),
+            Some(line) => encoded.push_str(line),
+            None => return Err(Error::Format),
+        }
+    }
+    let decoded = encoded.from_base64().map_err(|_| Error::Format)?;
+    if let Some(checksum) = checksum {
+        let crc = crc24(&decoded).to_be_bytes();
+        if checksum.from_base64().ok().as_deref() != Some(&crc[1..]) {
+            return Err(Error::Integrity);
+        }
+    }
+    Ok(decoded)
+}
+
+fn crc24(data: &[u8]) -> u32 {
+    let mut crc = 0xb704ceu32;
+    for &b in data {
+        crc ^= u32::from(b) << 16;
+        for _ in 0..8 {
+            crc <<= 1;
+            if crc & 0x1000000 != 0 {
+                crc ^= 0x1864cfb;
+            }
+        }
+    }
+    crc & 0xffffff
+}
+
+fn take<'a>(data: &'a [u8], pos: &mut usize, len: usize) -> Result<&'a [u8], Error> {
+    if data.len() - *pos < len {
+        return Err(Error::Format);
+    }
+    *pos += len;
+    Ok(&data[*pos - len..*pos])
+}
+
+fn be(bytes: &[u8]) -> usize {
+    bytes.iter().fold(0, |n, &b| n << 8 | b as usize)
+}
+
+// Returns the tag and body of the packet at `pos`, joining partial bodies.
+fn read_packet(data: &[u8], pos: &mut usize) -> Result<(u8, Vec<u8>), Error> {
+    let ctb = take(data, pos, 1)?[0];
+    if ctb & 0x80 == 0 {
+        return Err(Error::Format);
+    }
+    if ctb & 0x40 == 0 {
+        // old format, the length type is in the low bits
+        let tag = (ctb >> 2) & 0x0f;
+        let len = match ctb & 3 {
+            0 => be(take(data, pos, 1)?),
+            1 => be(take(data, pos, 2)?),
+            2 => be(take(data, pos, 4)?),
+            _ => data.len() - *pos,
+        };
+        return Ok((tag, take(data, pos, len)?.to_vec()));
+    }
+    let tag = ctb & 0x3f;
+    let mut body = Vec::new();
+    loop {
+        let first = take(data, pos, 1)?[0] as usize;
+        let (len, partial) = match first {
+            0..=191 => (first, false),
+            192..=223 => (
+                ((first - 192) << 8) + take(data, pos, 1)?[0] as usize + 192,
+                false,
+            ),
+            255 => (be(take(data, pos, 4)?), false),
+            _ => (1 << (first & 0x1f), true),
+        };
+        body.extend_from_slice(take(data, pos, len)?);
+        if !partial {
+            return Ok((tag, body));
+        }
+    }
+}
+
+fn write_packet(out: &mut Vec<u8>, tag: u8, body: &[u8]) {
+    out.push(0xc0 | tag);
+    let len = body.len();
+    if len < 192 {
+        out.push(len as u8);
+    } else if len < 8384 {
+        out.push((((len - 192) >> 8) + 192) as u8);
+        out.push((len - 192) as u8);
+    } else {
+        out.push(0xff);
+        out.extend_from_slice(&(len as u32).to_be_bytes());
+    }
+    out.extend_from_slice(body);
+}
+
+fn key_len(cipher: u8) -> Result<usize, Error> {
+    match cipher {
+        AES128 => Ok(16),
+        AES192 => Ok(24),
+        AES256 => Ok(32),
+        _ => Err(Error::Unsupported),
+    }
+}
+
+fn nonce_len(aead: u8) -> Result<usize, Error> {
+    match aead {
+        EAX => Ok(16),
+        OCB => Ok(15),
+        GCM => Ok(12),
+        _ => Err(Error::Unsupported),
+    }
+}
+
+fn block_encryptor(cipher: u8, key: &[u8]) -> Result<Box<dyn BlockEncryptor>, Error> {
+    if key.len() != key_len(cipher)? {
+        return Err(Error::Format);
+    }
+    Ok(match cipher {
+        AES128 => Box::new(AesSafe128Encryptor::new(key)),
+        AES192 => Box::new(AesSafe192Encryptor::new(key)),
+        _ => Box::new(AesSafe256Encryptor::new(key)),
+    })
+}
+
+fn block_decryptor(cipher: u8, key: &[u8]) -> Result<Box<dyn BlockDecryptor>, Error> {
+    if key.len() != key_len(cipher)? {
+        return Err(Error::Format);
+    }
+    Ok(match cipher {
+        AES128 => Box::new(AesSafe128Decryptor::new(key)),
+        AES192 => Box::new(AesSafe192Decryptor::new(key)),
+        _ => Box::new(AesSafe256Decryptor::new(key)),
+    })
+}
+
+fn digest(hash: u8) -> Result<Box<dyn Digest>, Error> {
+    Ok(match hash {
+        MD5 => Box::new(Md5::new()),
+        SHA1 => Box::new(Sha1::new()),
+        RIPEMD160 => Box::new(Ripemd160::new()),
+        SHA256 => Box::new(Sha256::new()),
+        SHA384 => Box::new(Sha384::new()),
+        SHA512 => Box::new(Sha512::new()),
+        SHA224 => Box::new(Sha224::new()),
+        _ => return Err(Error::Unsupported),
+    })
+}
+
+impl S2K {
+    fn parse(data: &[u8], pos: &mut usize) -> Result<S2K, Error> {
+        Ok(match take(data, pos, 1)?[0] {
+            0 => S2K::Simple(take(data, pos, 1)?[0]),
+            1 => S2K::Salted(take(data, pos, 1)?[0], take(data, pos, 8)?.to_vec()),
+            3 => S2K::Iterated(
+                take(data, pos, 1)?[0],
+                take(data, pos, 8)?.to_vec(),
+                take(data, pos, 1)?[0],
+            ),
+            4 => {
+                let salt = take(data, pos, 16)?.to_vec();
+                let params = take(data, pos, 3)?;
+                S2K::Argon2(salt, params[0], params[1], params[2])
+            }
+            _ => return Err(Error::Unsupported),
+        })
+    }
+
+    fn write(&self, out: &mut Vec<u8>) {
+        match self {
+            S2K::Simple(hash) => out.extend_from_slice(&[0, *hash]),
+            S2K::Salted(hash, salt) => {
+                out.extend_from_slice(&[1, *hash]);
+                out.extend_from_slice(salt);
+            }
+            S2K::Iterated(hash, salt, count) => {
+                out.extend_from_slice(&[3, *hash]);
+                out.extend_from_slice(salt);
+                out.push(*count);
+            }
+            S2K::Argon2(salt, passes, parallelism, memory) => {
+                out.push(4);
+                out.extend_from_slice(salt);
+                out.extend_from_slice(&[*passes, *parallelism, *memory]);
+            }
+        }
+    }
+
+    fn derive(&self, passphrase: &str, key: &mut [u8]) -> Result<(), Error> {
+        let passphrase = passphrase.as_bytes();
+        let (hash, salt, count) = match self {
+            S2K::Simple(hash) => (*hash, &[][..], 0),
+            S2K::Salted(hash, salt) => (*hash, &salt[..], 0),
+            S2K::Iterated(hash, salt, count) => (
+                *hash,
+                &salt[..],
+                (16 + (*count as usize & 15)) << ((*count >> 4) + 6),
+            ),
+            S2K::Argon2(salt, passes, parallelism, memory) => {
+                if *passes == 0 || *parallelism == 0 {
+                    return Err(Error::Format);
+                }
+                if *memory > MAX_ARGON2_MEMORY {
+                    return Err(Error::Unsupported);
+                }
+                let config = argon2::Config {
+                    variant: argon2::Variant::Argon2id,
+                    version: argon2::Version::Version13,
+                    mem_cost: 1 << memory,
+                    time_cost: u32::from(*passes),
+                    lanes: u32::from(*parallelism),
+                    thread_mode: argon2::ThreadMode::Sequential,
+                    secret: &[],
+                    ad: &[],
+                    hash_length: key.len() as u32,
+                };
+                let hash =
+                    argon2::hash_raw(passphrase, salt, &config).map_err(|_| Error::Format)?;
+                key.copy_from_slice(&hash);
+                return Ok(());
+            }
+        };
+
+        // each further hash context is preloaded with one more zero byte
+        let mut salted = salt.to_vec();
+        salted.extend_from_slice(passphrase);
+        let count = count.max(salted.len());
+        let mut filled = 0;
+        let mut preload = 0;
+        while filled < key.len() {
+            let mut digest = digest(hash)?;
+            digest.input(&vec![0; preload]);
+            for _ in 0..count / salted.len() {
+                digest.input(&salted);
+            }
+            digest.input(&salted[..count % salted.len()]);
+            let mut out = vec![0; digest.output_bytes()];
+            digest.result(&mut out);
+            let n = out.len().min(key.len() - filled);
+            key[filled..filled + n].copy_from_slice(&out[..n]);
+            filled += n;
+            preload += 1;
+        }
+        Ok(())
+    }
+}
+
+// cipher and key
+type SessionKey = (u8, Zeroizing<Vec<u8>>);
+
+// Returns the cipher and session key from a SKESK packet, or None if the
+// passphrase can be told apart as wrong already.
+fn session_key(skesk: &[u8], passphrase: &str) -> Result<Option<SessionKey>, Error> {
+    let mut pos = 0;
+    let version = take(skesk, &mut pos, 1)?[0];
+    match version {
+        4 => {
+            let cipher = take(skesk, &mut pos, 1)?[0];
+            let s2k = S2K::parse(skesk, &mut pos)?;
+            let mut key = Zeroizing::new(vec![0; key_len(cipher)?]);
+            s2k.derive(passphrase, &mut key)?;
+            if pos == skesk.len() {
+                return Ok(Some((cipher, key)));
+            }
+            // the session key is encrypted, prefixed with its cipher
+            let session_key = Zeroizing::new(cfb(cipher, &key, &skesk[pos..], true)?);
+            match key_len(session_key[0]) {
+                Ok(len) if len == session_key.len() - 1 => Ok(Some((
+                    session_key[0],
+                    Zeroizing::new(session_key[1..].to_vec()),
+                ))),
+                _ => Ok(None),
+            }
+        }
+        5 | 6 => {
+            if version == 6 {
+                take(skesk, &mut pos, 1)?;
+            }
+            let cipher = take(skesk, &mut pos, 1)?[0];
+            let aead = take(skesk, &mut pos, 1)?[0];
+            if version == 6 {
+                take(skesk, &mut pos, 1)?;
+            }
+            let s2k = S2K::parse(skesk, &mut pos)?;
+            let nonce = take(skesk, &mut pos, nonce_len(aead)?)?;
+            let mut key = Zeroizing::new(vec![0; key_len(cipher)?]);
+            s2k.derive(passphrase, &mut key)?;
+            let ad = [0xc0 | TAG_SKESK, version, cipher, aead];
+            if version == 6 {
+                let ikm = key.clone();
+                crypto::hkdf_sha256(&[], &ikm, &ad, &mut key);
+            }
+            match aead_open(aead, cipher, &key, nonce, &ad, &skesk[pos..]) {
+                Ok(session_key) => Ok(Some((cipher, Zeroizing::new(session_key)))),
+                Err(Error::Integrity) => Ok(None),
+                Err(err) => Err(err),
+            }
+        }
+        _ => Err(Error::Unsupported),
+    }
+}
+
+fn decrypt_data(tag: u8, body: &[u8], cipher: u8, key: &[u8]) -> Result<Vec<u8>, Error> {
+    let mut pos = 0;
+    let version = take(body, &mut pos, 1)?[0];
+    match (tag, version) {
+        (TAG_SEIPD, 1) => {
+            let data = Zeroizing::new(cfb(cipher, key, &body[1..], true)?);
+            if data.len() < BLOCK_LEN + 2 + MDC_LEN {
+                return Err(Error::Format);
+            }
+            if data[BLOCK_LEN - 2..BLOCK_LEN] != data[BLOCK_LEN..BLOCK_LEN + 2] {
+                return Err(Error::NoMatchingKey);
+            }
+            let (covered, mdc) = data.split_at(data.len() - 20);
+            let mut hash = [0; 20];
+            let mut sha1 = Sha1::new();
+            sha1.input(covered);
+            sha1.result(&mut hash);
+            if !covered.ends_with(&[0xd3, 0x14]) || !fixed_time_eq(&hash, mdc) {
+                return Err(Error::Integrity);
+            }
+            Ok(data[BLOCK_LEN + 2..data.len() - MDC_LEN].to_vec())
+        }
+        (TAG_SEIPD, 2) | (TAG_AEAD, 1) => {
+            let header = take(body, &mut pos, 3)?;
+            let (cipher, aead, chunk_octet) = (header[0], header[1], header[2]);
+            if chunk_octet > 16 {
+                return Err(Error::Format);
+            }
+            let chunk_len = 1 << (chunk_octet + 6);
+            let ad = [0xc0 | tag, version, cipher, aead, chunk_octet];
+            if version == 2 {
+                // key and nonce prefix come from the salted session key
+                let salt = take(body, &mut pos, 32)?;
+                let mut okm = Zeroizing::new(vec![0; key_len(cipher)? + nonce_len(aead)? - 8]);
+                crypto::hkdf_sha256(salt, key, &ad, &mut okm);
+                let (key, iv) = okm.split_at(key_len(cipher)?);
+                aead_chunks(
+                    &body[pos..],
+                    chunk_len,
+                    |index, total| {
+                        let mut nonce = iv.to_vec();
+                        nonce.extend_from_slice(&index.to_be_bytes());
+                        let mut chunk_ad = ad.to_vec();
+                        if let Some(total) = total {
+                            chunk_ad.extend_from_slice(&total.to_be_bytes());
+                        }
+                        (nonce, chunk_ad)
+                    },
+                    |nonce, ad, sealed| aead_open(aead, cipher, key, nonce, ad, sealed),
+                )
+            } else {
+                let iv = take(body, &mut pos, nonce_len(aead)?)?;
+                aead_chunks(
+                    &body[pos..],
+                    chunk_len,
+                    |index, total| {
+                        let mut nonce = iv.to_vec();
+                        let n = nonce.len();
+                        for (b, i) in nonce[n - 8..].iter_mut().zip(index.to_be_bytes().iter()) {
+                            *b ^= i;
+                        }
+                        let mut chunk_ad = ad.to_vec();
+                        chunk_ad.extend_from_slice(&index.to_be_bytes());
+                        if let Some(total) = total {
+                            chunk_ad.extend_from_slice(&total.to_be_bytes());
+                        }
+                        (nonce, chunk_ad)
+                    },
+                    |nonce, ad, sealed| aead_open(aead, cipher, key, nonce, ad, sealed),
+                )
+            }
+        }
+        _ => Err(Error::Unsupported),
+    }
+}
+
+// Opens the chunks of an AEAD packet followed by the final tag, which
+// authenticates the total length. `params` returns the nonce and associated
+// data of a chunk index, with the total length for the final tag.
+fn aead_chunks<P, O>(data: &[u8], chunk_len: usize, params: P, open: O) -> Result<Vec<u8>, Error>
+where
+    P: Fn(u64, Option<u64>) -> (Vec<u8>, Vec<u8>),
+    O: Fn(&[u8], &[u8], &[u8]) -> Result<Vec<u8>, Error>,
+{
+    if data.len() < TAG_LEN {
+        return Err(Error::Format);
+    }
+    let (chunks, final_tag) = data.split_at(data.len() - TAG_LEN);
+    let mut plaintext = Vec::with_capacity(chunks.len());
+    let mut index = 0;
+    for chunk in chunks.chunks(chunk_len + TAG_LEN) {
+        let (nonce, ad) = params(index, None);
+        plaintext.extend(open(&nonce, &ad, chunk)?);
+        index += 1;
+    }
+    let (nonce, ad) = params(index, Some(plaintext.len() as u64));
+    open(&nonce, &ad, final_tag)?;
+    Ok(plaintext)
+}
+
+fn aead_open(
+    aead: u8,
+    cipher: u8,
+    key: &[u8],
+    nonce: &[u8],
+    ad: &[u8],
+    sealed: &[u8],
+) -> Result<Vec<u8>, Error> {
+    if sealed.len() < TAG_LEN {
+        return Err(Error::Format);
+    }
+    let (ciphertext, tag) = sealed.split_at(sealed.len() - TAG_LEN);
+    match aead {
+        OCB => {
+            let (plaintext, expected) = ocb(cipher, key, nonce, ad, ciphertext, true)?;
+            if !fixed_time_eq(&expected, tag) {
+                return Err(Error::Integrity);
+            }
+            Ok(plaintext)
+        }
+        GCM => {
+            let key_size = match cipher {
+                AES128 => KeySize::KeySize128,
+                AES192 => KeySize::KeySize192,
+                AES256 => KeySize::KeySize256,
+                _ => return Err(Error::Unsupported),
+            };
+            if key.len() != key_len(cipher)? || nonce.len() != 12 {
+                return Err(Error::Format);
+            }
+            let mut plaintext = vec![0; ciphertext.len()];
+            if !AesGcm::new(key_size, key, nonce, ad).decrypt(ciphertext, &mut plaintext, tag) {
+                return Err(Error::Integrity);
+            }
+            Ok(plaintext)
+        }
+        _ => Err(Error::Unsupported),
+    }
+}
+
+fn cfb(cipher: u8, key: &[u8], data: &[u8], decrypt: bool) -> Result<Vec<u8>, Error> {
+    let encryptor = block_encryptor(cipher, key)?;
+    let mut register = [0; BLOCK_LEN];
+    let mut out = Vec::with_capacity(data.len());
+    for block in data.chunks(BLOCK_LEN) {
+        let mut keystream = [0; BLOCK_LEN];
+        encryptor.encrypt_block(&register, &mut keystream);
+        let start = out.len();
+        out.extend(block.iter().zip(keystream.iter()).map(|(a, b)| a ^ b));
+        let ciphertext = if decrypt { block } else { &out[start..] };
+        register[..ciphertext.len()].copy_from_slice(ciphertext);
+    }
+    Ok(out)
+}
+
+fn xor(a: &mut [u8; BLOCK_LEN], b: &[u8]) {
+    for (x, y) in a.iter_mut().zip(b) {
+        *x ^= y;
+    }
+}
+
+fn double(block: &[u8; BLOCK_LEN]) -> [u8; BLOCK_LEN] {
+    let mut out = [0; BLOCK_LEN];
+    for i in 0..BLOCK_LEN {
+        out[i] = block[i] << 1 | block.get(i + 1).map_or(0, |b| b >> 7);
+    }
+    if block[0] & 0x80 != 0 {
+        out[BLOCK_LEN - 1] ^= 0x87;
+    }
+    out
+}
+
+// OCB3 (RFC 7253) with a 128 bit tag. Returns the processed data together
+// with the tag, which the caller compares when decrypting.
+fn ocb(
+    cipher: u8,
+    key: &[u8],
+    nonce: &[u8],
+    ad: &[u8],
+    input: &[u8],
+    decrypt: bool,
+) -> Result<(Vec<u8>, [u8; BLOCK_LEN]), Error> {
+    if nonce.is_empty() || nonce.len() >= BLOCK_LEN {
+        return Err(Error::Format);
+    }
+    let encryptor = block_encryptor(cipher, key)?;
+    let decryptor = block_decryptor(cipher, key)?;
+    let encrypt = |block: &[u8; BLOCK_LEN]| {
+        let mut out = [0; BLOCK_LEN];
+        encryptor.encrypt_block(block, &mut out);
+        out
+    };
+
+    let l_star = encrypt(&[0; BLOCK_LEN]);
+    let l_dollar = double(&l_star);
+    let mut l = vec![double(&l_dollar)];
+    while l.len() < 64 {
+        let next = double(&l[l.len() - 1]);
+        l.push(next);
+    }
+    let l_at = |i: usize| &l[i.trailing_zeros() as usize];
+
+    let mut full_nonce = [0; BLOCK_LEN];
+    full_nonce[BLOCK_LEN - nonce.len()..].copy_from_slice(nonce);
+    full_nonce[BLOCK_LEN - 1 - nonce.len()] |= 1;
+    let bottom = (full_nonce[BLOCK_LEN - 1] & 0x3f) as usize;
+    full_nonce[BLOCK_LEN - 1] &= 0xc0;
+    let ktop = encrypt(&full_nonce);
+    let mut stretch = [0; 24];
+    stretch[..BLOCK_LEN].copy_from_slice(&ktop);
+    for i in 0..8 {
+        stretch[BLOCK_LEN + i] = ktop[i] ^ ktop[i + 1];
+    }
+    let (byte, bit) = (bottom / 8, bottom % 8);
+    let mut offset = [0; BLOCK_LEN];
+    for (i, o) in offset.iter_mut().enumerate() {
+        *o = match bit {
+            0 => stretch[byte + i],
+            _ => stretch[byte + i] << bit | stretch[byte + i + 1] >> (8 - bit),
+        };
+    }
+
+    let mut checksum = [0; BLOCK_LEN];
+    let mut out = Vec::with_capacity(input.len());
+    let mut blocks = input.chunks_exact(BLOCK_LEN);
+    for (i, block) in (&mut blocks).enumerate() {
+        xor(&mut offset, l_at(i + 1));
+        let mut tmp = offset;
+        xor(&mut tmp, block);
+        let mut processed = [0; BLOCK_LEN];
+        if decrypt {
+            decryptor.decrypt_block(&tmp, &mut processed);
+        } else {
+            processed = encrypt(&tmp);
+        }
+        xor(&mut processed, &offset);
+        xor(&mut checksum, if decrypt { &processed } else { block });
+        out.extend_from_slice(&processed);
+    }
+    let rest = blocks.remainder();
+    if !rest.is_empty() {
+        xor(&mut offset, &l_star);
+        let pad = encrypt(&offset);
+        let start = out.len();
+        out.extend(rest.iter().zip(pad.iter()).map(|(a, b)| a ^ b));
+        let plaintext = if decrypt { &out[start..] } else { rest };
+        let mut padded = [0; BLOCK_LEN];
+        padded[..plaintext.len()].copy_from_slice(plaintext);
+        padded[plaintext.len()] = 0x80;
+        xor(&mut checksum, &padded);
+    }
+    xor(&mut checksum, &offset);
+    xor(&mut checksum, &l_dollar);
+    let mut tag = encrypt(&checksum);
+
+    let mut sum = [0; BLOCK_LEN];
+    let mut offset = [0; BLOCK_LEN];
+    let mut blocks = ad.chunks_exact(BLOCK_LEN);
+    for (i, block) in (&mut blocks).enumerate() {
+        xor(&mut offset, l_at(i + 1));
+        let mut tmp = offset;
+        xor(&mut tmp, block);
+        xor(&mut sum, &encrypt(&tmp));
+    }
+    let rest = blocks.remainder();
+    if !rest.is_empty() {
+        xor(&mut offset, &l_star);
+        let mut padded = [0; BLOCK_LEN];
+        padded[..rest.len()].copy_from_slice(rest);
+        padded[rest.len()] = 0x80;
+        xor(&mut padded, &offset);
+        xor(&mut sum, &encrypt(&padded));
+    }
+    xor(&mut tag, &sum);
+    Ok((out, tag))
+}
+
+// Unwraps compressed data down to the contents of the literal data packet.
+// Only one layer of compression is unwrapped.
+fn literal_data(packets: &[u8]) -> Result<Vec<u8>, Error> {
+    literal_data_in(packets, false)
+}
+
+fn literal_data_in(packets: &[u8], compressed: bool) -> Result<Vec<u8>, Error> {
+    let mut pos = 0;
+    while pos < packets.len() {
+        let (tag, body) = read_packet(packets, &mut pos)?;
+        match tag {
+            TAG_COMPRESSED if compressed => return Err(Error::Unsupported),
+            TAG_COMPRESSED => return literal_data_in(&decompress(&body)?, true),
+            TAG_LITERAL => {
+                let mut pos = 1;
+                let name_len = take(&body, &mut pos, 1)?[0] as usize;
+                take(&body, &mut pos, name_len + 4)?;
+                return Ok(body[pos..].to_vec());
+            }
+            TAG_ONE_PASS_SIGNATURE | TAG_SIGNATURE | TAG_MARKER => {}
+            _ => return Err(Error::Format),
+        }
+    }
+    Err(Error::Format)
+}
+
+fn decompress(body: &[u8]) -> Result<Vec<u8>, Error> {
+    let (algorithm, data) = body.split_first().ok_or(Error::Format)?;
+    match algorithm {
+        0 => Ok(data.to_vec()),
+        1 => inflate::decompress_to_vec_with_limit(data, MAX_LEN).map_err(|_| Error::Format),
+        2 => inflate::decompress_to_vec_zlib_with_limit(data, MAX_LEN).map_err(|_| Error::Format),
+        _ => Err(Error::Unsupported),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rustc_serialize::hex::FromHex;
+
+    // gpg --symmetric --s2k-count 65536 with the passphrase "vault"
+    const GNUPG_FILE: &str = "jA0ECQMCxaSXW1FRdsBg0kQBbqXOTFtkLcRsCJSFOdH/eO1bcg3X+antPy5KrNwNcef/4lN5Rw0yIc/th1abZFwLIaU1wMpbYcGmUu0dhA6Q5sBeTw==";
+
+    #[test]
+    fn test_gnupg_file() {
+        let data = GNUPG_FILE.from_base64().unwrap();
+        assert!(is_openpgp(&data));
+        assert_eq!(decrypt(&data, "vault").unwrap(), b"hello pgp\n");
+        assert_eq!(decrypt(&data, "wrong").err(), Some(Error::NoMatchingKey));
+        assert_eq!(
+            decrypt(&armor(&data).into_bytes(), "vault").unwrap(),
+            b"hello pgp\n"
+        );
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let plaintext: Vec<u8> = (0..10_000).map(|i| i as u8).collect();
+        let mut data = encrypt_with_count("vault", 0x60, &plaintext);
+        assert!(is_openpgp(&data));
+        assert!(!is_openpgp(&plaintext));
+        assert_eq!(decrypt(&data, "vault").unwrap(), plaintext);
+
+        let last = data.len() - 1;
+        data[last] ^= 1;
+        assert_eq!(decrypt(&data, "vault").err(), Some(Error::Integrity));
+    }
+
+    #[test]
+    fn test_argon2_memory_limit() {
+        let mut key = [0; 16];
+        let s2k = S2K::Argon2(vec![0; 16], 1, 1, MAX_ARGON2_MEMORY + 1);
+        assert_eq!(
+            s2k.derive("vault", &mut key).err(),
+            Some(Error::Unsupported)
+        );
+        let s2k = S2K::Argon2(vec![0; 16], 1, 1, 30);
+        assert_eq!(
+            s2k.derive("vault", &mut key).err(),
+            Some(Error::Unsupported)
+        );
+        let s2k = S2K::Argon2(vec![0; 16], 1, 1, 3);
+        assert!(s2k.derive("vault", &mut key).is_ok());
+    }
+
+    #[test]
+    fn test_compressed() {
+        let mut literal = Vec::new();
+        write_packet(&mut literal, TAG_LITERAL, b"b\x00\x00\x00\x00\x00text");
+        let mut compressed = Vec::new();
+        write_packet(
+            &mut compressed,
+            TAG_COMPRESSED,
+            &[&[0][..], &literal].concat(),
+        );
+        assert_eq!(literal_data(&compressed).unwrap(), b"text");
+
+        let mut nested = Vec::new();
+        write_packet(
+            &mut nested,
+            TAG_COMPRESSED,
+            &[&[0][..], &compressed].concat(),
+        );
+        assert_eq!(literal_data(&nested).err(), Some(Error::Unsupported));
+
+        assert_eq!(decompress(&zeros(3)).unwrap(), vec![0; 1 + 3 * 258]);
+        assert_eq!(
+            decompress(&zeros(MAX_LEN / 258 + 1)).err(),
+            Some(Error::Format)
+        );
+    }
+
+    // A compressed packet body inflating to a zero followed by `runs` runs of
+    // 258 more: one final deflate block with fixed codes.
+    fn zeros(runs: usize) -> Vec<u8> {
+        let mut body = vec![1];
+        let mut bits = 0u32;
+        let mut len = 0;
+        let mut push = |body: &mut Vec<u8>, code: u32, n: u32| {
+            for i in (0..n).rev() {
+                bits |= ((code >> i) & 1) << len;
+                len += 1;
+                if len == 8 {
+                    body.push(bits as u8);
+                    bits = 0;
+                    len = 0;
+                }
+            }
+        };
+        push(&mut body, 0b110, 3);
+        push(&mut body, 0x30, 8);
+        for _ in 0..runs {
+            // length 258 at distance 1
+            push(&mut body, 0xc5, 8);
+            push(&mut body, 0, 5);
+        }
+        // end of block and padding
+        push(&mut body, 0, 7);
+        push(&mut body, 0, 7);
+        body
+    }
+
+    #[test]
+    fn test_ocb() {
+        // RFC 7253 appendix A
+        let key = "000102030405060708090A0B0C0D0E0F".from_hex().unwrap();
+        let vectors = [
+            (
+                "BBAA99887766554433221100",
+                "",
+                "785407BFFFC8AD9EDCC5520AC9111EE6",
+            ),
+            (
+                "BBAA99887766554433221101",
+                "0001020304050607",
+                "6820B3657B6F615A5725BDA0D3B4EB3A257C9AF1F8F03009",
+            ),
+            (
+                "BBAA99887766554433221104",
+                "000102030405060708090A0B0C0D0E0F",
+                "571D535B60B277188BE5147170A9A22C3AD7A4FF3835B8C5701C1CCEC8FC3358",
+            ),
+        ];
+        for (nonce, plaintext, sealed) in vectors.iter() {
+            let nonce = nonce.from_hex().unwrap();
+            let plaintext = plaintext.from_hex().unwrap();
+            let sealed = sealed.from_hex().unwrap();
+            let (ciphertext, tag) =
+                ocb(AES128, &key, &nonce, &plaintext, &plaintext, false).unwrap();
+            assert_eq!([&ciphertext[..], &tag[..]].concat(), sealed);
+            assert_eq!(
+                aead_open(OCB, AES128, &key, &nonce, &plaintext, &sealed).unwrap(),
+                plaintext
+            );
+        }
+    }
+}
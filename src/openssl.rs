@@ -0,0 +1,182 @@
+use rustc_serialize::base64::{FromBase64, Newline, ToBase64, STANDARD};
+
+use container::Error;
+use crypto;
+use state::cipher::Cipher;
+use state::hash::Hash;
+use zeroize::Zeroizing;
+
+// Files written by `openssl enc -aes-256-cbc`: the magic, an 8 byte salt and
+// the CBC ciphertext. Key and IV both come from the password, through PBKDF2
+// with -pbkdf2 or -iter and through EVP_BytesToKey otherwise.
+pub const MAGIC: &[u8] = b"Salted__";
+// what `openssl enc -a` turns the magic into
+const BASE64_MAGIC: &[u8] = b"U2FsdGVkX1";
+
+// iterations of -pbkdf2 without -iter
+pub const DEFAULT_ITER: u32 = 10000;
+
+const SALT_LEN: usize = 8;
+const KEY_LEN: usize = 32;
+const IV_LEN: usize = 16;
+const COLUMNS: usize = 64;
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Params {
+    pub md: Hash,
+    // None derives the key with EVP_BytesToKey, as openssl does without
+    // -pbkdf2 and -iter
+    pub iter: Option<u32>,
+}
+
+impl Default for Params {
+    fn default() -> Self {
+        Params {
+            md: Hash::SHA2_256,
+            iter: Some(DEFAULT_ITER),
+        }
+    }
+}
+
+// Takes the digest names of `openssl enc -md` as well as our own.
+pub fn parse_md(name: &str) -> Option<Hash> {
+    match name.to_ascii_lowercase().as_str() {
+        "sha256" | "sha2-256" => Some(Hash::SHA2_256),
+        "sha384" | "sha2-384" => Some(Hash::SHA2_384),
+        "sha512" | "sha2-512" => Some(Hash::SHA2_512),
+        "sha3-256" => Some(Hash::SHA3_256),
+        "sha3-384" => Some(Hash::SHA3_384),
+        "sha3-512" => Some(Hash::SHA3_512),
+        "ripemd160" | "rmd160" | "ripemd" => Some(Hash::RIPEMD160),
+        "blake2b512" => Some(Hash::BLAKE2B),
+        "blake2s256" => Some(Hash::BLAKE2S),
+        _ => name.parse().ok(),
+    }
+}
+
+pub fn is_openssl(data: &[u8]) -> bool {
+    data.starts_with(MAGIC) || data.starts_with(BASE64_MAGIC)
+}
+
+fn derive(params: Params, password: &str, salt: &[u8]) -> Zeroizing<[u8; KEY_LEN + IV_LEN]> {
+    let mut derived = Zeroizing::new([0; KEY_LEN + IV_LEN]);
+    match params.iter {
+        Some(iter) => {
+            crypto::pbkdf2_with(params.md, iter, password.as_bytes(), salt, &mut *derived)
+        }
+        None => {
+            // EVP_BytesToKey with a count of one: D_i = md(D_(i-1) || password || salt)
+            let mut block = Zeroizing::new(Vec::new());
+            let mut filled = 0;
+            while filled < derived.len() {
+                block = Zeroizing::new(crypto::digest(
+                    params.md,
+                    &[&block, password.as_bytes(), salt],
+                ));
+                let n = block.len().min(derived.len() - filled);
+                derived[filled..filled + n].copy_from_slice(&block[..n]);
+                filled += n;
+            }
+        }
+    }
+    derived
+}
+
+pub fn encrypt(params: Params, password: &str, plaintext: &[u8]) -> Vec<u8> {
+    let mut salt = [0; SALT_LEN];
+    crypto::random_bytes(&mut salt);
+    let derived = derive(params, password, &salt);
+    let ciphertext = crypto::encrypt_with(
+        Cipher::AESCBC,
+        &derived[..KEY_LEN],
+        &derived[KEY_LEN..],
+        plaintext,
+    )
+    .expect("CBC encryption does not fail");
+
+    let mut data = MAGIC.to_vec();
+    data.extend_from_slice(&salt);
+    data.extend(ciphertext);
+    data
+}
+
+pub fn decrypt(params: Params, password: &str, data: &[u8]) -> Result<Vec<u8>, Error> {
+    let data = if data.starts_with(BASE64_MAGIC) {
+        data.from_base64().map_err(|_| Error::Format)?
+    } else {
+        data.to_vec()
+    };
+    if !data.starts_with(MAGIC) || data.len() < MAGIC.len() + SALT_LEN + IV_LEN {
+        return Err(Error::Format);
+    }
+    let (salt, ciphertext) = data[MAGIC.len()..].split_at(SALT_LEN);
+    let derived = derive(params, password, salt);
+    // a wrong password mostly shows up as bad padding
+    crypto::decrypt_with(
+        Cipher::AESCBC,
+        &derived[..KEY_LEN],
+        &derived[KEY_LEN..],
+        ciphertext,
+    )
+    .map_err(|_| Error::Cipher)
+}
+
+// Base64 in lines of 64 columns, like `openssl enc -a`.
+pub fn armor(data: &[u8]) -> String {
+    let mut config = STANDARD;
+    config.newline = Newline::LF;
+    config.line_length = Some(COLUMNS);
+    let mut armored = data.to_base64(config);
+    armored.push('\n');
+    armored
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_openssl_file() {
+        // echo -n secret | openssl enc -aes-256-cbc -pbkdf2 -pass pass:vault -a
+        let pbkdf2 = b"U2FsdGVkX1/jEA2kK3kuIJLnVnsGvk8v6FLXjuthhRs=\n";
+        assert_eq!(
+            decrypt(Params::default(), "vault", pbkdf2).unwrap(),
+            b"secret"
+        );
+        assert!(decrypt(Params::default(), "wrong", pbkdf2).is_err());
+
+        // echo -n secret | openssl enc -aes-256-cbc -md sha512 -iter 1000 -pass pass:vault -a
+        let iter = b"U2FsdGVkX18Dl1JlPeoN2BVEuujUMqxqD7H0J/0qlWM=\n";
+        let params = Params {
+            md: Hash::SHA2_512,
+            iter: Some(1000),
+        };
+        assert_eq!(decrypt(params, "vault", iter).unwrap(), b"secret");
+
+        // echo -n secret | openssl enc -aes-256-cbc -pass pass:vault -a
+        let legacy = b"U2FsdGVkX19TRO8z/bAFEM78rGrkpS1yd6/MvJ8ERrw=\n";
+        let params = Params {
+            md: Hash::SHA2_256,
+            iter: None,
+        };
+        assert_eq!(decrypt(params, "vault", legacy).unwrap(), b"secret");
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        for &iter in &[None, Some(1)] {
+            for &md in Hash::ALL.iter() {
+                let params = Params { md, iter };
+                let data = encrypt(params, "vault", b"secret");
+                assert!(is_openssl(&data));
+                assert_eq!(decrypt(params, "vault", &data).unwrap(), b"secret");
+                let armored = armor(&data);
+                assert!(is_openssl(armored.as_bytes()));
+                assert_eq!(
+                    decrypt(params, "vault", armored.as_bytes()).unwrap(),
+                    b"secret"
+                );
+            }
+        }
+    }
+}
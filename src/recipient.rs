@@ -0,0 +1,229 @@
+use cryptolib::curve25519::{curve25519, curve25519_base};
+use cryptolib::digest::Digest;
+use cryptolib::sha3::Sha3;
+use rustc_serialize::base64::{FromBase64, ToBase64, URL_SAFE};
+use std::fmt;
+
+use crypto;
+use mlkem;
+use zeroize::{Zeroize, Zeroizing};
+
+pub const RECIPIENT_PREFIX: &str = "vault-pq1:";
+pub const IDENTITY_PREFIX: &str = "VAULT-PQ-SECRET-KEY-1:";
+
+// ephemeral X25519 public key followed by the ML-KEM-768 ciphertext
+pub const ENCAPSULATION_LEN: usize = 32 + mlkem::CIPHERTEXT_LEN;
+
+const COMBINER_LABEL: &[u8] = b"vault x25519+ml-kem-768";
+
+// Hybrid X25519 + ML-KEM-768 public key. A wrapped file key stays secret as
+// long as either of the two key exchanges holds.
+pub struct Recipient {
+    x25519: [u8; 32],
+    mlkem: mlkem::EncapsulationKey,
+}
+
+pub struct Identity {
+    x25519: [u8; 32],
+    // ML-KEM key generation seed (d || z)
+    seed: [u8; 64],
+    mlkem: mlkem::DecapsulationKey,
+    recipient: Recipient,
+}
+
+impl Recipient {
+    pub fn parse(s: &str) -> Option<Recipient> {
+        if !s.starts_with(RECIPIENT_PREFIX) {
+            return None;
+        }
+        let bytes = s[RECIPIENT_PREFIX.len()..].from_base64().ok()?;
+        if bytes.len() != 32 + mlkem::ENCAPSULATION_KEY_LEN {
+            return None;
+        }
+        let mut x25519 = [0; 32];
+        x25519.copy_from_slice(&bytes[..32]);
+        Some(Recipient {
+            x25519,
+            mlkem: mlkem::EncapsulationKey::from_bytes(&bytes[32..])?,
+        })
+    }
+
+    // Parses one recipient per line, skipping blank lines and `#` comments.
+    pub fn parse_list(s: &str) -> Option<Vec<Recipient>> {
+        s.lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(Recipient::parse)
+            .collect()
+    }
+
+    // Returns the encapsulation to store in the key slot together with the
+    // key that wraps the file key.
+    pub fn wrap(&self) -> (Vec<u8>, Zeroizing<[u8; 32]>) {
+        let mut ephemeral = Zeroizing::new([0; 32]);
+        let mut m = Zeroizing::new([0; 32]);
+        crypto::random_bytes(&mut *ephemeral);
+        crypto::random_bytes(&mut *m);
+
+        let ephemeral_public = curve25519_base(&*ephemeral);
+        let x25519_shared = Zeroizing::new(curve25519(&*ephemeral, &self.x25519));
+        let (ciphertext, mlkem_shared) = self.mlkem.encapsulate(&m);
+
+        let mut encapsulation = Vec::with_capacity(ENCAPSULATION_LEN);
+        encapsulation.extend_from_slice(&ephemeral_public);
+        encapsulation.extend_from_slice(&ciphertext);
+        let key = combine(
+            &mlkem_shared,
+            &*x25519_shared,
+            &ephemeral_public,
+            &self.x25519,
+        );
+        (encapsulation, key)
+    }
+}
+
+impl fmt::Display for Recipient {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut bytes = self.x25519.to_vec();
+        bytes.extend_from_slice(self.mlkem.as_bytes());
+        write!(f, "{}{}", RECIPIENT_PREFIX, bytes.to_base64(URL_SAFE))
+    }
+}
+
+impl Identity {
+    pub fn generate() -> Identity {
+        let mut x25519 = [0; 32];
+        let mut seed = [0; 64];
+        crypto::random_bytes(&mut x25519);
+        crypto::random_bytes(&mut seed);
+        Identity::from_secrets(x25519, seed)
+    }
+
+    fn from_secrets(x25519: [u8; 32], seed: [u8; 64]) -> Identity {
+        let mut d = [0; 32];
+        let mut z = [0; 32];
+        d.copy_from_slice(&seed[..32]);
+        z.copy_from_slice(&seed[32..]);
+        let (ek, dk) = mlkem::DecapsulationKey::from_seed(&d, &z);
+        Identity {
+            x25519,
+            seed,
+            mlkem: dk,
+            recipient: Recipient {
+                x25519: curve25519_base(&x25519),
+                mlkem: ek,
+            },
+        }
+    }
+
+    // Accepts the contents of an identity file: the first line that is not
+    // blank or a `#` comment holds the secret key.
+    pub fn parse(s: &str) -> Option<Identity> {
+        let line = s
+            .lines()
+            .map(str::trim)
+            .find(|line| !line.is_empty() && !line.starts_with('#'))?;
+        if !line.starts_with(IDENTITY_PREFIX) {
+            return None;
+        }
+        let bytes = line[IDENTITY_PREFIX.len()..].from_base64().ok()?;
+        if bytes.len() != 32 + 64 {
+            return None;
+        }
+        let mut x25519 = [0; 32];
+        let mut seed = [0; 64];
+        x25519.copy_from_slice(&bytes[..32]);
+        seed.copy_from_slice(&bytes[32..]);
+        Some(Identity::from_secrets(x25519, seed))
+    }
+
+    pub fn recipient(&self) -> &Recipient {
+        &self.recipient
+    }
+
+    pub fn unwrap(&self, encapsulation: &[u8]) -> Option<Zeroizing<[u8; 32]>> {
+        if encapsulation.len() != ENCAPSULATION_LEN {
+            return None;
+        }
+        let (ephemeral_public, ciphertext) = encapsulation.split_at(32);
+        let x25519_shared = Zeroizing::new(curve25519(&self.x25519, ephemeral_public));
+        if x25519_shared.iter().all(|&b| b == 0) {
+            return None;
+        }
+        let mlkem_shared = self.mlkem.decapsulate(ciphertext)?;
+        Some(combine(
+            &mlkem_shared,
+            &*x25519_shared,
+            ephemeral_public,
+            &self.recipient.x25519,
+        ))
+    }
+}
+
+impl fmt::Display for Identity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut bytes = self.x25519.to_vec();
+        bytes.extend_from_slice(&self.seed);
+        writeln!(f, "# public key: {}", self.recipient)?;
+        write!(f, "{}{}", IDENTITY_PREFIX, bytes.to_base64(URL_SAFE))
+    }
+}
+
+impl Drop for Identity {
+    fn drop(&mut self) {
+        self.x25519.zeroize();
+        self.seed.zeroize();
+    }
+}
+
+// Same shape as the X-Wing combiner: the ML-KEM ciphertext is already bound
+// to its shared secret, the X25519 one is bound through the public values.
+fn combine(
+    mlkem_shared: &[u8],
+    x25519_shared: &[u8],
+    ephemeral_public: &[u8],
+    recipient_public: &[u8],
+) -> Zeroizing<[u8; 32]> {
+    let mut key = Zeroizing::new([0; 32]);
+    let mut sha3 = Sha3::sha3_256();
+    sha3.input(mlkem_shared);
+    sha3.input(x25519_shared);
+    sha3.input(ephemeral_public);
+    sha3.input(recipient_public);
+    sha3.input(COMBINER_LABEL);
+    sha3.result(&mut *key);
+    key
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wrap_unwrap() {
+        let identity = Identity::generate();
+        let (encapsulation, key) = identity.recipient().wrap();
+        assert_eq!(encapsulation.len(), ENCAPSULATION_LEN);
+        assert_eq!(identity.unwrap(&encapsulation).unwrap(), key);
+
+        let other = Identity::generate();
+        assert!(other.unwrap(&encapsulation).unwrap() != key);
+    }
+
+    #[test]
+    fn test_encoding_roundtrip() {
+        let identity = Identity::generate();
+        let parsed = Identity::parse(&identity.to_string()).unwrap();
+        assert_eq!(
+            parsed.recipient().to_string(),
+            identity.recipient().to_string()
+        );
+
+        let recipient = Recipient::parse(&identity.recipient().to_string()).unwrap();
+        let (encapsulation, key) = recipient.wrap();
+        assert_eq!(identity.unwrap(&encapsulation).unwrap(), key);
+
+        assert!(Recipient::parse("vault-pq1:AAAA").is_none());
+        assert!(Identity::parse("# only a comment").is_none());
+    }
+}
@@ -0,0 +1,88 @@
+use rustc_serialize::hex::ToHex;
+use std::fs;
+use std::fs::DirBuilder;
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::DirBuilderExt;
+use std::path::{Path, PathBuf};
+
+use atomic;
+use container;
+use container::{Container, PasswordKey};
+use crypto;
+use memory;
+use state::hash::Hash;
+use zeroize::Zeroizing;
+
+// Autosaves of unsaved changes. Each one is a vault file sealed with the
+// session key of its document, so it opens with the same password and never
+// holds plaintext, and is named after a hash of the document's path.
+
+fn file(dir: &Path, document: &Path) -> PathBuf {
+    let document = fs::canonicalize(document).unwrap_or_else(|_| document.to_path_buf());
+    let name = crypto::digest(Hash::SHA2_256, &[document.as_os_str().as_bytes()]);
+    dir.join(name.to_hex())
+}
+
+pub fn save(dir: &Path, key: &PasswordKey, document: &Path, plaintext: &str) -> io::Result<()> {
+    DirBuilder::new().recursive(true).mode(0o700).create(dir)?;
+    let data = container::seal_with_key(
+        key.params(),
+        Some(key),
+        &[],
+        None,
+        plaintext.as_bytes(),
+        &[],
+    )
+    .map_err(|err| io::Error::new(io::ErrorKind::Other, err.to_string()))?;
+    atomic::write(&file(dir, document), &data)
+}
+
+pub fn load(dir: &Path, key: &PasswordKey, document: &Path) -> Option<Zeroizing<String>> {
+    let data = fs::read(file(dir, document)).ok()?;
+    let plain_utf8 = Zeroizing::new(Container::parse(&data).ok()?.open_with_key(key).ok()?);
+    let text = Zeroizing::new(String::from_utf8(plain_utf8.to_vec()).ok()?);
+    memory::lock(text.as_bytes());
+    Some(text)
+}
+
+pub fn discard(dir: &Path, document: &Path) {
+    let _ = fs::remove_file(file(dir, document));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use state::cipher::Cipher;
+    use state::cost::Cost;
+    use state::kdf::KDF;
+    use std::env;
+
+    #[test]
+    fn test_save_load() {
+        let dir = env::temp_dir().join(format!("vault-recovery-{}", std::process::id()));
+        let params = container::Params {
+            cipher: Cipher::CHACHA20,
+            hash: Hash::SHA2_256,
+            cost: Cost::LOW,
+            kdf: KDF::PBKDF2,
+        };
+        let key = PasswordKey::new(params, "password");
+        let document = Path::new("/notes/diary.vault");
+
+        assert!(load(&dir, &key, document).is_none());
+        save(&dir, &key, document, "unsaved notes").unwrap();
+        assert_eq!(
+            load(&dir, &key, document).unwrap().as_str(),
+            "unsaved notes"
+        );
+        // the same password with another salt is another key
+        let other = PasswordKey::new(params, "password");
+        assert!(load(&dir, &other, document).is_none());
+        assert!(load(&dir, &key, Path::new("/notes/other.vault")).is_none());
+
+        discard(&dir, document);
+        assert!(load(&dir, &key, document).is_none());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
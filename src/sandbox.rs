@@ -0,0 +1,399 @@
+use libc;
+use std::env;
+use std::ffi::CString;
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::io::prelude::*;
+use std::os::raw::{c_int, c_long, c_ushort};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::{AsRawFd, FromRawFd};
+use std::path::Path;
+use std::process::{Command, Stdio};
+use zeroize::Zeroizing;
+
+use container::Params;
+use crypto;
+use string::StaticStr;
+
+// Headerless vault files are decrypted in a helper process, a copy of this
+// executable started with COMMAND. Before reading anything the helper is
+// confined with Landlock to reading the one input file and with seccomp to a
+// few syscalls that can't open files or sockets. The password arrives on
+// stdin, terminated by a newline, and the plaintext leaves through stdout.
+pub const COMMAND: &str = "sandboxed-decrypt";
+
+#[derive(Debug)]
+pub enum Error {
+    Spawn(io::Error),
+    // the helper failed, which includes a wrong password
+    Failed,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Spawn(err) => write!(f, "starting the decryption sandbox: {}", err),
+            Error::Failed => write!(f, "decryption failed"),
+        }
+    }
+}
+
+pub enum Input<'a> {
+    File(&'a Path),
+    // already read, from stdin say
+    Data(&'a [u8]),
+}
+
+pub fn decrypt(params: Params, password: &str, input: Input) -> Result<Vec<u8>, Error> {
+    let mut child = Command::new(env::current_exe().map_err(Error::Spawn)?)
+        .arg(COMMAND)
+        .args(&["--cipher", params.cipher.as_static_str()])
+        .args(&["--hash", params.hash.as_static_str()])
+        .args(&["--cost", params.cost.as_static_str()])
+        .args(&["--kdf", params.kdf.as_static_str()])
+        .arg(match input {
+            Input::File(path) => path.as_os_str(),
+            Input::Data(_) => "-".as_ref(),
+        })
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .map_err(Error::Spawn)?;
+
+    let mut line = Zeroizing::new(password.to_string());
+    line.push('\n');
+    {
+        let mut stdin = child.stdin.take().expect("stdin is piped");
+        // a helper that died early shows up in its exit status
+        let _ = stdin.write_all(line.as_bytes()).and_then(|()| match input {
+            Input::Data(data) => stdin.write_all(data),
+            Input::File(_) => Ok(()),
+        });
+    }
+    let mut plaintext = Vec::new();
+    let read = child
+        .stdout
+        .take()
+        .expect("stdout is piped")
+        .read_to_end(&mut plaintext);
+    let plaintext = Zeroizing::new(plaintext);
+    match child.wait() {
+        Ok(status) if status.success() && read.is_ok() => Ok(plaintext.to_vec()),
+        Ok(_) => Err(Error::Failed),
+        Err(err) => Err(Error::Spawn(err)),
+    }
+}
+
+// The helper side of decrypt, with "-" reading the file from stdin after the
+// password.
+pub fn helper(params: Params, input: &str) -> Result<(), String> {
+    let mut file = confine(if input == "-" {
+        None
+    } else {
+        Some(Path::new(input))
+    })?;
+
+    let stdin = io::stdin();
+    let mut stdin = stdin.lock();
+    let mut password = Zeroizing::new(String::new());
+    stdin
+        .read_line(&mut password)
+        .map_err(|err| format!("reading password: {}", err))?;
+    if password.ends_with('\n') {
+        password.pop();
+    }
+    let mut data = Vec::new();
+    match file {
+        Some(ref mut file) => file.read_to_end(&mut data),
+        None => stdin.read_to_end(&mut data),
+    }
+    .map_err(|err| format!("reading {}: {}", input, err))?;
+
+    let plaintext = Zeroizing::new(
+        crypto::decrypt(
+            params.cipher,
+            params.hash,
+            params.cost,
+            params.kdf,
+            &password,
+            data,
+        )
+        .map_err(|_| "decryption failed".to_string())?,
+    );
+    io::stdout()
+        .write_all(&plaintext)
+        .map_err(|err| format!("writing stdout: {}", err))
+}
+
+// Confines the calling thread and the threads it starts afterwards. The file
+// is opened in between, after which no other can be.
+fn confine(path: Option<&Path>) -> Result<Option<File>, String> {
+    if unsafe { libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) } != 0 {
+        return Err(format!("no_new_privs: {}", io::Error::last_os_error()));
+    }
+    match restrict_fs(path) {
+        Ok(true) => {}
+        // seccomp alone still keeps the helper from opening files
+        Ok(false) => eprintln!("vault: Landlock is not available, using seccomp only"),
+        Err(err) => return Err(format!("Landlock: {}", err)),
+    }
+    let file = match path {
+        Some(path) => {
+            Some(File::open(path).map_err(|err| format!("opening {}: {}", path.display(), err))?)
+        }
+        None => None,
+    };
+    restrict_syscalls().map_err(|err| format!("seccomp: {}", err))?;
+    Ok(file)
+}
+
+const LANDLOCK_CREATE_RULESET_VERSION: u32 = 1;
+const LANDLOCK_RULE_PATH_BENEATH: c_int = 1;
+const LANDLOCK_ACCESS_FS_READ_FILE: u64 = 1 << 2;
+
+#[repr(C)]
+struct RulesetAttr {
+    handled_access_fs: u64,
+}
+
+#[repr(C, packed)]
+struct PathBeneathAttr {
+    allowed_access: u64,
+    parent_fd: i32,
+}
+
+// Returns false when the kernel doesn't support Landlock.
+fn restrict_fs(path: Option<&Path>) -> io::Result<bool> {
+    let abi = unsafe {
+        libc::syscall(
+            libc::SYS_landlock_create_ruleset,
+            0,
+            0,
+            LANDLOCK_CREATE_RULESET_VERSION,
+        )
+    };
+    if abi < 0 {
+        let err = io::Error::last_os_error();
+        return match err.raw_os_error() {
+            Some(libc::ENOSYS) | Some(libc::EOPNOTSUPP) => Ok(false),
+            _ => Err(err),
+        };
+    }
+    // every file system access right known to the running kernel
+    let attr = RulesetAttr {
+        handled_access_fs: match abi {
+            1 => (1 << 13) - 1,
+            2 => (1 << 14) - 1,
+            3 | 4 => (1 << 15) - 1,
+            _ => (1 << 16) - 1,
+        },
+    };
+    let ruleset = unsafe {
+        libc::syscall(
+            libc::SYS_landlock_create_ruleset,
+            &attr as *const RulesetAttr,
+            std::mem::size_of::<RulesetAttr>(),
+            0,
+        )
+    };
+    if ruleset < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let ruleset = unsafe { File::from_raw_fd(ruleset as i32) };
+
+    if let Some(path) = path {
+        let path = CString::new(path.as_os_str().as_bytes())
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+        let fd = unsafe { libc::open(path.as_ptr(), libc::O_PATH | libc::O_CLOEXEC) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let file = unsafe { File::from_raw_fd(fd) };
+        let rule = PathBeneathAttr {
+            allowed_access: LANDLOCK_ACCESS_FS_READ_FILE,
+            parent_fd: file.as_raw_fd(),
+        };
+        let added = unsafe {
+            libc::syscall(
+                libc::SYS_landlock_add_rule,
+                ruleset.as_raw_fd(),
+                LANDLOCK_RULE_PATH_BENEATH,
+                &rule as *const PathBeneathAttr,
+                0,
+            )
+        };
+        if added < 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    if unsafe { libc::syscall(libc::SYS_landlock_restrict_self, ruleset.as_raw_fd(), 0) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(true)
+}
+
+#[cfg(target_arch = "x86_64")]
+const AUDIT_ARCH: u32 = 0xc000_003e;
+#[cfg(target_arch = "aarch64")]
+const AUDIT_ARCH: u32 = 0xc000_00b7;
+
+// What reading, decrypting with parallel Argon2 and writing takes. Anything
+// else fails with ENOSYS, which also makes glibc fall back from clone3 to
+// clone.
+#[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+const ALLOWED: &[c_long] = &[
+    libc::SYS_read,
+    libc::SYS_write,
+    libc::SYS_close,
+    libc::SYS_lseek,
+    libc::SYS_fstat,
+    libc::SYS_newfstatat,
+    libc::SYS_statx,
+    libc::SYS_brk,
+    libc::SYS_mmap,
+    libc::SYS_munmap,
+    libc::SYS_mremap,
+    libc::SYS_mprotect,
+    libc::SYS_madvise,
+    libc::SYS_futex,
+    libc::SYS_clone,
+    libc::SYS_set_robust_list,
+    libc::SYS_rseq,
+    libc::SYS_sched_yield,
+    libc::SYS_sched_getaffinity,
+    libc::SYS_getrandom,
+    libc::SYS_sigaltstack,
+    libc::SYS_rt_sigaction,
+    libc::SYS_rt_sigprocmask,
+    libc::SYS_rt_sigreturn,
+    libc::SYS_gettid,
+    libc::SYS_exit,
+    libc::SYS_exit_group,
+];
+
+#[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+fn restrict_syscalls() -> io::Result<()> {
+    fn statement(code: u32, k: u32) -> libc::sock_filter {
+        libc::sock_filter {
+            code: code as u16,
+            jt: 0,
+            jf: 0,
+            k,
+        }
+    }
+    fn jump_if(k: u32, jt: u8, jf: u8) -> libc::sock_filter {
+        libc::sock_filter {
+            code: (libc::BPF_JMP | libc::BPF_JEQ | libc::BPF_K) as u16,
+            jt,
+            jf,
+            k,
+        }
+    }
+
+    // offsets into struct seccomp_data
+    let nr = 0;
+    let arch = 4;
+    let mut filter = vec![
+        statement(libc::BPF_LD | libc::BPF_W | libc::BPF_ABS, arch),
+        jump_if(AUDIT_ARCH, 1, 0),
+        statement(libc::BPF_RET | libc::BPF_K, libc::SECCOMP_RET_KILL_PROCESS),
+        statement(libc::BPF_LD | libc::BPF_W | libc::BPF_ABS, nr),
+    ];
+    for syscall in ALLOWED {
+        filter.push(jump_if(*syscall as u32, 0, 1));
+        filter.push(statement(
+            libc::BPF_RET | libc::BPF_K,
+            libc::SECCOMP_RET_ALLOW,
+        ));
+    }
+    filter.push(statement(
+        libc::BPF_RET | libc::BPF_K,
+        libc::SECCOMP_RET_ERRNO | libc::ENOSYS as u32,
+    ));
+
+    let program = libc::sock_fprog {
+        len: filter.len() as c_ushort,
+        filter: filter.as_mut_ptr(),
+    };
+    let installed = unsafe {
+        libc::prctl(
+            libc::PR_SET_SECCOMP,
+            libc::SECCOMP_MODE_FILTER,
+            &program as *const libc::sock_fprog,
+        )
+    };
+    if installed != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+fn restrict_syscalls() -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Other,
+        "no syscall filter for this architecture",
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use state::cipher::Cipher;
+    use state::cost::Cost;
+    use state::hash::Hash;
+    use state::kdf::KDF;
+    use std::fs;
+    use std::thread;
+
+    // Landlock and seccomp apply to the thread that sets them up and the ones
+    // it starts, so the rest of the test process is left alone.
+    #[test]
+    fn test_confine() {
+        let dir = env::temp_dir().join(format!("vault-sandbox-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let input = dir.join("input");
+        let other = dir.join("other");
+        let (salt, ciphertext) = crypto::encrypt(
+            Cipher::CHACHA20,
+            Hash::SHA3_512,
+            Cost::LOW,
+            KDF::ARGON2,
+            "password",
+            b"secret",
+        )
+        .unwrap();
+        fs::write(&input, [&salt[..], &ciphertext].concat()).unwrap();
+        fs::write(&other, b"other").unwrap();
+
+        let confined = {
+            let (input, other) = (input.clone(), other.clone());
+            thread::spawn(move || {
+                let mut file = confine(Some(&input)).unwrap().unwrap();
+                let mut data = Vec::new();
+                file.read_to_end(&mut data).unwrap();
+                // Argon2 starts threads of its own
+                let plaintext = crypto::decrypt(
+                    Cipher::CHACHA20,
+                    Hash::SHA3_512,
+                    Cost::LOW,
+                    KDF::ARGON2,
+                    "password",
+                    data,
+                )
+                .unwrap();
+                (
+                    plaintext,
+                    File::open(&other).is_err(),
+                    File::create(&input).is_err(),
+                )
+            })
+            .join()
+            .unwrap()
+        };
+        assert_eq!(confined, (b"secret".to_vec(), true, true));
+        assert!(File::open(&other).is_ok());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
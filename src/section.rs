@@ -0,0 +1,236 @@
+use rustc_serialize::base64::{FromBase64, Newline, ToBase64, STANDARD};
+use std::ops::Range;
+use std::str;
+use zeroize::Zeroizing;
+
+use container;
+use container::{Container, Error, Params};
+use memory;
+
+// An encrypted section is a vault container of its own, with its own password
+// and parameters, armored into the text of the document:
+//
+//   -----BEGIN VAULT SECTION-----
+//   base64, 64 columns
+//   -----END VAULT SECTION-----
+//
+// The document around it is encrypted as usual, so a section is encrypted
+// twice and stays locked for whoever only has the document password.
+
+pub const BEGIN: &str = "-----BEGIN VAULT SECTION-----";
+pub const END: &str = "-----END VAULT SECTION-----";
+// stands in for a section in a redacted copy
+pub const REDACTED: &str = "[REDACTED]";
+
+pub fn seal(params: Params, password: &str, plaintext: &str) -> Result<String, Error> {
+    let data = container::seal(params, Some(password), &[], None, plaintext.as_bytes())?;
+    let mut config = STANDARD;
+    config.newline = Newline::LF;
+    config.line_length = Some(64);
+    Ok(format!("{}\n{}\n{}", BEGIN, data.to_base64(config), END))
+}
+
+// Also gives back the parameters the section was sealed with, so an edit can
+// be sealed the same way.
+pub fn open(armored: &str, password: &str) -> Result<(Zeroizing<String>, Params), Error> {
+    let encoded = armored
+        .trim()
+        .strip_prefix(BEGIN)
+        .and_then(|rest| rest.strip_suffix(END))
+        .ok_or(Error::Format)?
+        .split_whitespace()
+        .collect::<String>();
+    let data = encoded.from_base64().map_err(|_| Error::Format)?;
+    let container = Container::parse(&data)?;
+    let plain_utf8 = Zeroizing::new(container.open_with_password(password)?);
+    let plaintext = String::from_utf8(plain_utf8.to_vec()).map_err(|_| Error::Format)?;
+    Ok((Zeroizing::new(plaintext), container.params()))
+}
+
+// Byte ranges of the sections in `text`, from their BEGIN line to the end of
+// their END line.
+pub fn find(text: &str) -> Vec<Range<usize>> {
+    let mut sections = Vec::new();
+    let mut pos = 0;
+    while let Some(start) = text[pos..].find(BEGIN).map(|i| pos + i) {
+        match text[start..].find(END) {
+            Some(i) => {
+                pos = start + i + END.len();
+                sections.push(start..pos);
+            }
+            None => break,
+        }
+    }
+    sections
+}
+
+// A plain text file with sections in it, kept that way rather than encrypted
+// as a whole.
+pub fn is_inline(data: &[u8]) -> bool {
+    str::from_utf8(data).map_or(false, |text| !find(text).is_empty())
+}
+
+fn replace<F>(text: &str, mut with: F) -> Zeroizing<String>
+where
+    F: FnMut(&str) -> Zeroizing<String>,
+{
+    let mut out = Zeroizing::new(String::with_capacity(text.len()));
+    let mut pos = 0;
+    for range in find(text) {
+        out.push_str(&text[pos..range.start]);
+        out.push_str(&with(&text[range.clone()]));
+        pos = range.end;
+    }
+    out.push_str(&text[pos..]);
+    out
+}
+
+// Replaces every section with the text `open` gives for it, or with the
+// REDACTED marker.
+pub fn redact<F>(text: &str, open: F) -> Zeroizing<String>
+where
+    F: Fn(&str) -> Option<Zeroizing<String>>,
+{
+    replace(text, |armored| {
+        open(armored).unwrap_or_else(|| Zeroizing::new(REDACTED.to_string()))
+    })
+}
+
+// Replaces the sections that `password` opens with their text and leaves the
+// others armored. Also tells how many were opened.
+pub fn open_all(text: &str, password: &str) -> (Zeroizing<String>, usize) {
+    let mut opened = 0;
+    let text = replace(text, |armored| match open(armored, password) {
+        Ok((plaintext, _)) => {
+            opened += 1;
+            plaintext
+        }
+        Err(_) => Zeroizing::new(armored.to_string()),
+    });
+    (text, opened)
+}
+
+// A section shown as its text in the document, and sealed again on Save with
+// the password and parameters it was opened with. Its armor only changes
+// when its text does, so an untouched section stays the same in the file.
+pub struct Opened {
+    armored: String,
+    plaintext: Zeroizing<String>,
+    password: Zeroizing<String>,
+    params: Params,
+}
+
+impl Opened {
+    pub fn open(armored: &str, password: &str) -> Result<Opened, Error> {
+        let (plaintext, params) = open(armored, password)?;
+        let password = Zeroizing::new(password.to_string());
+        memory::lock(plaintext.as_bytes());
+        memory::lock(password.as_bytes());
+        Ok(Opened {
+            armored: armored.to_string(),
+            plaintext,
+            password,
+            params,
+        })
+    }
+
+    pub fn armored(&self) -> &str {
+        &self.armored
+    }
+
+    pub fn plaintext(&self) -> &str {
+        &self.plaintext
+    }
+
+    pub fn seal(&mut self, text: &str) -> Result<&str, Error> {
+        if text != self.plaintext.as_str() {
+            self.armored = seal(self.params, &self.password, text)?;
+            self.plaintext = Zeroizing::new(text.to_string());
+            memory::lock(self.plaintext.as_bytes());
+        }
+        Ok(&self.armored)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use state::cipher::Cipher;
+    use state::cost::Cost;
+    use state::hash::Hash;
+    use state::kdf::KDF;
+
+    #[test]
+    fn test_seal_open() {
+        let params = Params {
+            cipher: Cipher::CHACHA20,
+            hash: Hash::SHA3_256,
+            cost: Cost::LOW,
+            kdf: KDF::PBKDF2,
+        };
+        let armored = seal(params, "section", "root password: hunter2").unwrap();
+        assert!(armored.lines().all(|line| line.len() <= 64));
+
+        let text = format!("servers\n{}\nnotes\n{}\n", armored, armored);
+        let sections = find(&text);
+        assert_eq!(sections.len(), 2);
+        assert_eq!(&text[sections[0].clone()], armored);
+
+        let (plaintext, opened) = open(&text[sections[1].clone()], "section").unwrap();
+        assert_eq!(plaintext.as_str(), "root password: hunter2");
+        assert_eq!(opened, params);
+        assert_eq!(
+            open(&armored, "document").unwrap_err(),
+            Error::NoMatchingKey
+        );
+        assert!(find(BEGIN).is_empty());
+    }
+
+    #[test]
+    fn test_redact() {
+        let first = format!("{}\nAAAA\n{}", BEGIN, END);
+        let second = format!("{}\nBBBB\n{}", BEGIN, END);
+        let text = format!("intro\n{}\nmiddle\n{}\nend\n", first, second);
+        let redacted = redact(&text, |armored| {
+            if armored == first {
+                Some(Zeroizing::new("opened".to_string()))
+            } else {
+                None
+            }
+        });
+        assert_eq!(
+            redacted.as_str(),
+            "intro\nopened\nmiddle\n[REDACTED]\nend\n"
+        );
+        assert_eq!(redact("plain", |_| None).as_str(), "plain");
+    }
+
+    #[test]
+    fn test_inline() {
+        let params = Params {
+            cipher: Cipher::CHACHA20,
+            hash: Hash::SHA2_256,
+            cost: Cost::LOW,
+            kdf: KDF::PBKDF2,
+        };
+        let mine = seal(params, "mine", "token: abc").unwrap();
+        let theirs = seal(params, "theirs", "token: xyz").unwrap();
+        let text = format!("# hosts\n{}\n{}\n", mine, theirs);
+        assert!(is_inline(text.as_bytes()));
+        assert!(!is_inline(b"# hosts\n"));
+
+        let (opened, count) = open_all(&text, "mine");
+        assert_eq!(count, 1);
+        assert_eq!(
+            opened.as_str(),
+            format!("# hosts\ntoken: abc\n{}\n", theirs)
+        );
+
+        let mut block = Opened::open(&mine, "mine").unwrap();
+        assert_eq!(block.plaintext(), "token: abc");
+        assert_eq!(block.seal("token: abc").unwrap(), mine);
+        let edited = block.seal("token: def").unwrap().to_string();
+        assert_ne!(edited, mine);
+        assert_eq!(open(&edited, "mine").unwrap().0.as_str(), "token: def");
+    }
+}
@@ -0,0 +1,195 @@
+use cryptolib::ed25519;
+use rustc_serialize::base64::{FromBase64, ToBase64, URL_SAFE};
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+use crypto;
+use zeroize::Zeroize;
+
+pub const PUBLIC_PREFIX: &str = "vault-sig1:";
+pub const SECRET_PREFIX: &str = "VAULT-SIGNING-KEY-1:";
+
+pub const PUBLIC_KEY_LEN: usize = 32;
+pub const SIGNATURE_LEN: usize = 64;
+
+// prepended to the signed bytes so the key can't be tricked into signing
+// something that isn't a vault file
+const CONTEXT: &[u8] = b"vault signature v1\0";
+
+pub struct SigningKey {
+    seed: [u8; 32],
+    secret: [u8; 64],
+    public: [u8; PUBLIC_KEY_LEN],
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum Status {
+    Unsigned,
+    Invalid,
+    // name from the trust list
+    Trusted(String),
+    // valid signature from a key missing from the trust list
+    Untrusted(String),
+}
+
+pub struct TrustList {
+    signers: Vec<(String, [u8; PUBLIC_KEY_LEN])>,
+}
+
+fn message(data: &[u8]) -> Vec<u8> {
+    let mut message = CONTEXT.to_vec();
+    message.extend_from_slice(data);
+    message
+}
+
+pub fn public_key_to_string(public: &[u8]) -> String {
+    format!("{}{}", PUBLIC_PREFIX, public.to_base64(URL_SAFE))
+}
+
+pub fn parse_public_key(s: &str) -> Option<[u8; PUBLIC_KEY_LEN]> {
+    if !s.starts_with(PUBLIC_PREFIX) {
+        return None;
+    }
+    let bytes = s[PUBLIC_PREFIX.len()..].from_base64().ok()?;
+    if bytes.len() != PUBLIC_KEY_LEN {
+        return None;
+    }
+    let mut public = [0; PUBLIC_KEY_LEN];
+    public.copy_from_slice(&bytes);
+    Some(public)
+}
+
+pub fn verify(data: &[u8], public: &[u8], signature: &[u8]) -> bool {
+    ed25519::verify(&message(data), public, signature)
+}
+
+impl SigningKey {
+    pub fn generate() -> SigningKey {
+        let mut seed = [0; 32];
+        crypto::random_bytes(&mut seed);
+        SigningKey::from_seed(seed)
+    }
+
+    fn from_seed(seed: [u8; 32]) -> SigningKey {
+        let (secret, public) = ed25519::keypair(&seed);
+        SigningKey {
+            seed,
+            secret,
+            public,
+        }
+    }
+
+    // Accepts the contents of a signing key file, see `Identity::parse`.
+    pub fn parse(s: &str) -> Option<SigningKey> {
+        let line = s
+            .lines()
+            .map(str::trim)
+            .find(|line| !line.is_empty() && !line.starts_with('#'))?;
+        if !line.starts_with(SECRET_PREFIX) {
+            return None;
+        }
+        let bytes = line[SECRET_PREFIX.len()..].from_base64().ok()?;
+        if bytes.len() != 32 {
+            return None;
+        }
+        let mut seed = [0; 32];
+        seed.copy_from_slice(&bytes);
+        Some(SigningKey::from_seed(seed))
+    }
+
+    pub fn public_key(&self) -> &[u8; PUBLIC_KEY_LEN] {
+        &self.public
+    }
+
+    pub fn sign(&self, data: &[u8]) -> [u8; SIGNATURE_LEN] {
+        ed25519::signature(&message(data), &self.secret)
+    }
+}
+
+impl fmt::Display for SigningKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "# public key: {}", public_key_to_string(&self.public))?;
+        write!(f, "{}{}", SECRET_PREFIX, self.seed.to_base64(URL_SAFE))
+    }
+}
+
+impl Drop for SigningKey {
+    fn drop(&mut self) {
+        self.seed.zeroize();
+        self.secret.zeroize();
+    }
+}
+
+impl TrustList {
+    // One signer per line: a name followed by the signer's public key.
+    pub fn parse(s: &str) -> TrustList {
+        let signers = s
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .filter_map(|line| {
+                let split = line.rfind(char::is_whitespace)?;
+                let public = parse_public_key(line[split..].trim())?;
+                Some((line[..split].trim().to_string(), public))
+            })
+            .collect();
+        TrustList { signers }
+    }
+
+    pub fn load(path: &Path) -> TrustList {
+        TrustList::parse(&fs::read_to_string(path).unwrap_or_default())
+    }
+
+    pub fn status(&self, public: &[u8]) -> Status {
+        match self.signers.iter().find(|(_, key)| key[..] == *public) {
+            Some((name, _)) => Status::Trusted(name.clone()),
+            None => Status::Untrusted(public_key_to_string(public)),
+        }
+    }
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Status::Unsigned => write!(f, "unsigned"),
+            Status::Invalid => write!(f, "invalid signature"),
+            Status::Trusted(name) => write!(f, "signed by {}", name),
+            Status::Untrusted(key) => write!(f, "signed by untrusted {}", &key[..20]),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sign_verify() {
+        let key = SigningKey::generate();
+        let signature = key.sign(b"data");
+        assert!(verify(b"data", key.public_key(), &signature));
+        assert!(!verify(b"date", key.public_key(), &signature));
+
+        let parsed = SigningKey::parse(&key.to_string()).unwrap();
+        assert_eq!(parsed.public_key(), key.public_key());
+    }
+
+    #[test]
+    fn test_trust_list() {
+        let alice = SigningKey::generate();
+        let bob = SigningKey::generate();
+        let list = TrustList::parse(&format!(
+            "# trusted signers\nAlice Doe {}\n\nnot a signer\n",
+            public_key_to_string(alice.public_key())
+        ));
+        assert_eq!(
+            list.status(alice.public_key()),
+            Status::Trusted("Alice Doe".to_string())
+        );
+        assert_eq!(
+            list.status(bob.public_key()),
+            Status::Untrusted(public_key_to_string(bob.public_key()))
+        );
+    }
+}
@@ -1,9 +1,10 @@
 use state::UpdateMsg;
 use state::Updater;
 use std::fmt;
+use std::str::FromStr;
 use string::StaticStr;
 
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, PartialEq, Debug)]
 pub enum Cipher {
     AESCBC,
     CHACHA20,
@@ -37,3 +38,36 @@ impl Default for Cipher {
         Cipher::AESCBC
     }
 }
+
+impl Cipher {
+    pub const ALL: [Cipher; 3] = [Cipher::AESCBC, Cipher::CHACHA20, Cipher::SALSA20];
+
+    pub fn id(&self) -> u8 {
+        match self {
+            Cipher::AESCBC => 0,
+            Cipher::CHACHA20 => 1,
+            Cipher::SALSA20 => 2,
+        }
+    }
+
+    pub fn from_id(id: u8) -> Option<Cipher> {
+        match id {
+            0 => Some(Cipher::AESCBC),
+            1 => Some(Cipher::CHACHA20),
+            2 => Some(Cipher::SALSA20),
+            _ => None,
+        }
+    }
+}
+
+impl FromStr for Cipher {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Cipher::ALL
+            .iter()
+            .find(|v| v.as_static_str().eq_ignore_ascii_case(s))
+            .cloned()
+            .ok_or(())
+    }
+}
@@ -1,9 +1,10 @@
 use state::UpdateMsg;
 use state::Updater;
 use std::fmt;
+use std::str::FromStr;
 use string::StaticStr;
 
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, PartialEq, Debug)]
 pub enum Cost {
     LOW,
     MEDIUM,
@@ -37,3 +38,36 @@ impl Default for Cost {
         Cost::MEDIUM
     }
 }
+
+impl Cost {
+    pub const ALL: [Cost; 3] = [Cost::LOW, Cost::MEDIUM, Cost::HIGH];
+
+    pub fn id(&self) -> u8 {
+        match self {
+            Cost::LOW => 0,
+            Cost::MEDIUM => 1,
+            Cost::HIGH => 2,
+        }
+    }
+
+    pub fn from_id(id: u8) -> Option<Cost> {
+        match id {
+            0 => Some(Cost::LOW),
+            1 => Some(Cost::MEDIUM),
+            2 => Some(Cost::HIGH),
+            _ => None,
+        }
+    }
+}
+
+impl FromStr for Cost {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Cost::ALL
+            .iter()
+            .find(|v| v.as_static_str().eq_ignore_ascii_case(s))
+            .cloned()
+            .ok_or(())
+    }
+}
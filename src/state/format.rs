@@ -0,0 +1,107 @@
+use age;
+use ansible;
+use fernet;
+use openpgp;
+use openssl;
+use section;
+use state::UpdateMsg;
+use state::Updater;
+use std::fmt;
+use std::str::FromStr;
+use string::StaticStr;
+use structured;
+
+// File format written by Save As
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Format {
+    VAULT,
+    AGE,
+    OPENPGP,
+    OPENSSL,
+    ANSIBLE,
+    FERNET,
+    // plain text with encrypted sections in it
+    INLINE,
+    // YAML, JSON, TOML or .env with the values encrypted
+    STRUCTURED,
+}
+
+impl fmt::Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_static_str())
+    }
+}
+
+impl StaticStr for Format {
+    fn as_static_str(&self) -> &'static str {
+        match self {
+            Format::VAULT => "VAULT",
+            Format::AGE => "AGE",
+            Format::OPENPGP => "OPENPGP",
+            Format::OPENSSL => "OPENSSL",
+            Format::ANSIBLE => "ANSIBLE",
+            Format::FERNET => "FERNET",
+            Format::INLINE => "INLINE",
+            Format::STRUCTURED => "STRUCTURED",
+        }
+    }
+}
+
+impl Updater for Format {
+    fn update(&self) -> UpdateMsg {
+        UpdateMsg::Format(*self)
+    }
+}
+
+impl Default for Format {
+    fn default() -> Self {
+        Format::VAULT
+    }
+}
+
+impl Format {
+    pub const ALL: [Format; 8] = [
+        Format::VAULT,
+        Format::AGE,
+        Format::OPENPGP,
+        Format::OPENSSL,
+        Format::ANSIBLE,
+        Format::FERNET,
+        Format::INLINE,
+        Format::STRUCTURED,
+    ];
+
+    // Anything that isn't recognized is taken for a vault file, which
+    // includes the headerless ones.
+    pub fn detect(data: &[u8]) -> Format {
+        if age::is_age(data) {
+            Format::AGE
+        } else if openpgp::is_openpgp(data) {
+            Format::OPENPGP
+        } else if openssl::is_openssl(data) {
+            Format::OPENSSL
+        } else if ansible::is_ansible(data) {
+            Format::ANSIBLE
+        } else if fernet::is_fernet(data) {
+            Format::FERNET
+        } else if structured::is_structured(data) {
+            Format::STRUCTURED
+        } else if section::is_inline(data) {
+            Format::INLINE
+        } else {
+            Format::VAULT
+        }
+    }
+}
+
+impl FromStr for Format {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Format::ALL
+            .iter()
+            .find(|v| v.as_static_str().eq_ignore_ascii_case(s))
+            .cloned()
+            .ok_or(())
+    }
+}
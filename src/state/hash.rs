@@ -1,9 +1,10 @@
 use state::UpdateMsg;
 use state::Updater;
 use std::fmt;
+use std::str::FromStr;
 use string::StaticStr;
 
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, PartialEq, Debug)]
 pub enum Hash {
     RIPEMD160,
     BLAKE2B,
@@ -49,3 +50,58 @@ impl Default for Hash {
         Hash::SHA2_256
     }
 }
+
+impl Hash {
+    pub const ALL: [Hash; 9] = [
+        Hash::RIPEMD160,
+        Hash::BLAKE2B,
+        Hash::BLAKE2S,
+        Hash::SHA2_256,
+        Hash::SHA2_384,
+        Hash::SHA2_512,
+        Hash::SHA3_256,
+        Hash::SHA3_384,
+        Hash::SHA3_512,
+    ];
+
+    pub fn id(&self) -> u8 {
+        match self {
+            Hash::RIPEMD160 => 0,
+            Hash::BLAKE2B => 1,
+            Hash::BLAKE2S => 2,
+            Hash::SHA2_256 => 3,
+            Hash::SHA2_384 => 4,
+            Hash::SHA2_512 => 5,
+            Hash::SHA3_256 => 6,
+            Hash::SHA3_384 => 7,
+            Hash::SHA3_512 => 8,
+        }
+    }
+
+    pub fn from_id(id: u8) -> Option<Hash> {
+        match id {
+            0 => Some(Hash::RIPEMD160),
+            1 => Some(Hash::BLAKE2B),
+            2 => Some(Hash::BLAKE2S),
+            3 => Some(Hash::SHA2_256),
+            4 => Some(Hash::SHA2_384),
+            5 => Some(Hash::SHA2_512),
+            6 => Some(Hash::SHA3_256),
+            7 => Some(Hash::SHA3_384),
+            8 => Some(Hash::SHA3_512),
+            _ => None,
+        }
+    }
+}
+
+impl FromStr for Hash {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Hash::ALL
+            .iter()
+            .find(|v| v.as_static_str().eq_ignore_ascii_case(s))
+            .cloned()
+            .ok_or(())
+    }
+}
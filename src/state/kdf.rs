@@ -2,9 +2,10 @@ use state::cost::Cost;
 use state::UpdateMsg;
 use state::Updater;
 use std::fmt;
+use std::str::FromStr;
 use string::StaticStr;
 
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, PartialEq, Debug)]
 pub enum KDF {
     PBKDF2,
     ARGON2,
@@ -45,6 +46,23 @@ impl Default for KDF {
 }
 
 impl KDF {
+    pub const ALL: [KDF; 2] = [KDF::PBKDF2, KDF::ARGON2];
+
+    pub fn id(&self) -> u8 {
+        match self {
+            KDF::PBKDF2 => 0,
+            KDF::ARGON2 => 1,
+        }
+    }
+
+    pub fn from_id(id: u8) -> Option<KDF> {
+        match id {
+            0 => Some(KDF::PBKDF2),
+            1 => Some(KDF::ARGON2),
+            _ => None,
+        }
+    }
+
     pub fn cost(&self, cost: Cost) -> KDFCost {
         match self {
             KDF::PBKDF2 => match cost {
@@ -60,3 +78,15 @@ impl KDF {
         }
     }
 }
+
+impl FromStr for KDF {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        KDF::ALL
+            .iter()
+            .find(|v| v.as_static_str().eq_ignore_ascii_case(s))
+            .cloned()
+            .ok_or(())
+    }
+}
@@ -1,16 +1,40 @@
-use crypto;
+use age;
+use ansible;
+use atomic;
+use config;
+use container;
 use editor;
+use fernet;
+use history;
+use history::Revision;
+use lock;
+use memory;
+use openpgp;
+use openssl;
+use recipient::{Identity, Recipient};
+use recovery;
+use sandbox;
+use section;
+use signature::{SigningKey, Status, TrustList};
 use std::fmt;
-use std::fs::File;
-use std::io::prelude::*;
+use std::fs;
+use std::io;
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::str;
+use std::time::{Duration, Instant};
+use structured;
+use zeroize::Zeroizing;
 
 use self::cipher::Cipher;
 use self::cost::Cost;
+use self::format::Format;
 use self::hash::Hash;
 use self::kdf::KDF;
 
 pub mod cipher;
 pub mod cost;
+pub mod format;
 pub mod hash;
 pub mod kdf;
 
@@ -20,13 +44,77 @@ pub struct State {
     cipher: Cipher,
     cost: Cost,
     kdf: KDF,
+    format: Format,
+    // -md follows the hash
+    openssl: openssl::Params,
+    recipients: Vec<Recipient>,
+    signing_key: Option<SigningKey>,
+    // signature of the open document
+    signature: Option<Status>,
+    // header and wrapping of the open ansible file
+    ansible: ansible::Envelope,
+    // file the document was read from or last written to
+    path: Option<PathBuf>,
+    // when the open Fernet token was made
+    issued: Option<u64>,
+    // from the password of the open document, taken when the editor locks
+    lock_key: Option<lock::Key>,
+    // vault password key of the open document, reused by Save
+    session_key: Option<container::PasswordKey>,
+    // what the session key is derived again from on unlock
+    session_salt: Option<(container::Params, [u8; 16])>,
+    // file key and key slots of the open vault file, so Save keeps every
+    // recipient, and what the session key opens them again from on unlock
+    file_key: Option<container::FileKey>,
+    file_key_locked: Option<container::KeySlots>,
+    // data key of the open structured file, reused by Save
+    structured: Option<structured::Key>,
+    // what the data key is opened again from on unlock
+    structured_locked: Option<structured::Forgotten>,
+    // structured values sealed with AES-SIV
+    deterministic: bool,
+    // sections opened this session, by their armored text
+    unlocked: Vec<(String, Zeroizing<String>)>,
+    // sections of an inline file shown as their text
+    opened: Vec<section::Opened>,
+    // armored text of the sections that were open when the editor locked
+    locked_sections: Vec<String>,
+    // autosaved changes found when the document was opened
+    recovered: Option<Zeroizing<String>>,
+    // the document as last read or written, and the revisions before it
+    revision: Option<Revision>,
+    history: Vec<Revision>,
+    // how many revisions a vault file keeps
+    keep_revisions: usize,
+    locked: Option<lock::Locked>,
+    // idle time before the editor locks
+    lock_after: Option<Duration>,
+    hide_unfocused: bool,
+    // unsaved changes in the editor
+    modified: bool,
+    // why the last autosave failed
+    autosave_error: Option<String>,
+    // text copied out of the document and when to take it off the clipboard
+    copied: Option<(Zeroizing<String>, Instant)>,
+    clear_clipboard_after: Option<Duration>,
 }
 
+const LOCK_AFTER: Duration = Duration::from_secs(5 * 60);
+const CLEAR_CLIPBOARD_AFTER: Duration = Duration::from_secs(30);
+
 pub enum Action {
     // key, plaintext, path
-    Encrypt(String, String, std::path::PathBuf),
-    // key, ciphertext
-    Decrypt(String, Vec<u8>),
+    Encrypt(Zeroizing<String>, Zeroizing<String>, PathBuf),
+    // plaintext, written back with the session key
+    Save(Zeroizing<String>),
+    // key, ciphertext, path
+    Decrypt(Zeroizing<String>, Vec<u8>, PathBuf),
+    // identity, ciphertext, path
+    DecryptWithIdentity(Box<Identity>, Vec<u8>, PathBuf),
+    // age identities, ciphertext, path
+    DecryptWithAgeIdentities(Vec<age::Identity>, Vec<u8>, PathBuf),
+    // text of an inline file, path
+    Load(Vec<u8>, PathBuf),
 }
 
 #[derive(Copy, Clone)]
@@ -35,15 +123,23 @@ pub enum UpdateMsg {
     Cipher(Cipher),
     Cost(Cost),
     KDF(KDF),
+    Format(Format),
 }
 
 impl State {
     pub fn new() -> State {
-        Default::default()
+        State {
+            lock_after: Some(LOCK_AFTER),
+            hide_unfocused: true,
+            clear_clipboard_after: Some(CLEAR_CLIPBOARD_AFTER),
+            keep_revisions: history::KEEP,
+            ..Default::default()
+        }
     }
 
     fn set_hash(&mut self, hash: Hash) {
         self.hash = hash;
+        self.openssl.md = hash;
     }
 
     fn set_cipher(&mut self, cipher: Cipher) {
@@ -58,58 +154,632 @@ impl State {
         self.kdf = kdf;
     }
 
+    fn set_format(&mut self, format: Format) {
+        self.format = format;
+    }
+
+    pub fn set_openssl_iter(&mut self, iter: Option<u32>) {
+        self.openssl.iter = iter;
+    }
+
+    pub fn recipients(&self) -> &[Recipient] {
+        &self.recipients
+    }
+
+    pub fn add_recipients(&mut self, recipients: Vec<Recipient>) {
+        self.recipients.extend(recipients);
+    }
+
+    pub fn clear_recipients(&mut self) {
+        self.recipients.clear();
+    }
+
+    pub fn set_signing_key(&mut self, signing_key: Option<SigningKey>) {
+        self.signing_key = signing_key;
+    }
+
+    pub fn lock_after(&self) -> Option<Duration> {
+        self.lock_after
+    }
+
+    pub fn set_lock_after(&mut self, lock_after: Option<Duration>) {
+        self.lock_after = lock_after;
+    }
+
+    pub fn deterministic(&self) -> bool {
+        self.deterministic
+    }
+
+    pub fn set_deterministic(&mut self, deterministic: bool) {
+        self.deterministic = deterministic;
+    }
+
+    pub fn keep_revisions(&self) -> usize {
+        self.keep_revisions
+    }
+
+    pub fn set_keep_revisions(&mut self, keep_revisions: usize) {
+        self.keep_revisions = keep_revisions;
+    }
+
+    pub fn history(&self) -> &[Revision] {
+        &self.history
+    }
+
+    pub fn set_modified(&mut self, modified: bool) {
+        self.modified = modified;
+    }
+
+    // Window title, the file name with a * for unsaved changes.
+    pub fn title(&self) -> String {
+        let title = match self.path.as_ref().and_then(|path| path.file_name()) {
+            Some(name) => format!("{} - {}", name.to_string_lossy(), config::TITLE),
+            None => config::TITLE.to_string(),
+        };
+        if self.modified {
+            format!("*{}", title)
+        } else {
+            title
+        }
+    }
+
+    pub fn hide_unfocused(&self) -> bool {
+        self.hide_unfocused
+    }
+
+    pub fn set_hide_unfocused(&mut self, hide_unfocused: bool) {
+        self.hide_unfocused = hide_unfocused;
+    }
+
+    pub fn clear_clipboard_after(&self) -> Option<Duration> {
+        self.clear_clipboard_after
+    }
+
+    pub fn set_clear_clipboard_after(&mut self, clear_clipboard_after: Option<Duration>) {
+        self.clear_clipboard_after = clear_clipboard_after;
+    }
+
+    pub fn copied(&mut self, text: Zeroizing<String>) {
+        memory::lock(text.as_bytes());
+        self.copied = self
+            .clear_clipboard_after
+            .map(|after| (text, Instant::now() + after));
+    }
+
+    pub fn is_copied(&self) -> bool {
+        self.copied.is_some()
+    }
+
+    // The copied text once it is time to clear the clipboard.
+    pub fn clipboard_due(&mut self) -> Option<Zeroizing<String>> {
+        match self.copied {
+            Some((_, due)) if due <= Instant::now() => self.copied.take().map(|(text, _)| text),
+            _ => None,
+        }
+    }
+
+    // Documents opened without a password can't be locked, except inline
+    // files with sections open, which lock by sealing the sections.
+    pub fn can_lock(&self) -> bool {
+        self.lock_key.is_some() || !self.opened.is_empty()
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.locked.is_some() || !self.locked_sections.is_empty()
+    }
+
+    pub fn has_locked_sections(&self) -> bool {
+        !self.locked_sections.is_empty()
+    }
+
+    // Locks an inline file once its open sections are back in their armor
+    // in the text. Only their passwords open them again.
+    pub fn lock_sections(&mut self) -> bool {
+        if self.opened.is_empty() {
+            return false;
+        }
+        self.locked_sections = self
+            .opened
+            .drain(..)
+            .map(|opened| opened.armored().to_string())
+            .collect();
+        self.unlocked.clear();
+        true
+    }
+
+    // The locked sections that open with `password`, unless none does.
+    // Sections with another password stay sealed.
+    pub fn unlock_sections(&mut self, password: &str) -> Option<Vec<section::Opened>> {
+        let opened = self
+            .locked_sections
+            .iter()
+            .filter_map(|armored| section::Opened::open(armored, password).ok())
+            .collect::<Vec<_>>();
+        if opened.is_empty() {
+            return None;
+        }
+        self.locked_sections.clear();
+        Some(opened)
+    }
+
+    pub fn lock(&mut self, plaintext: &str) -> bool {
+        match self.lock_key.take() {
+            Some(key) => {
+                self.locked = Some(key.seal(plaintext));
+                self.unlocked.clear();
+                self.session_salt = self
+                    .session_key
+                    .take()
+                    .map(|key| (key.params(), key.salt()));
+                self.structured_locked = self.structured.take().map(structured::Key::forget);
+                self.file_key_locked = self.file_key.take().map(container::FileKey::forget);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn unlock(&mut self, password: &str) -> Option<Zeroizing<String>> {
+        let (plaintext, key) = self.locked.as_ref()?.open(password)?;
+        self.locked = None;
+        self.lock_key = Some(key);
+        // autosave goes on with the same key
+        if let Some((params, salt)) = self.session_salt.take() {
+            self.session_key = Some(container::PasswordKey::derive(params, password, &salt));
+        }
+        if let (Some(slots), Some(key)) = (self.file_key_locked.take(), self.session_key.as_ref()) {
+            self.file_key = slots.open(key);
+        }
+        if let Some(forgotten) = self.structured_locked.take() {
+            self.structured = forgotten.open(password);
+        }
+        Some(plaintext)
+    }
+
+    pub fn new_document(&mut self) {
+        self.signature = None;
+        self.ansible = Default::default();
+        self.path = None;
+        self.issued = None;
+        self.revision = None;
+        self.history.clear();
+        self.close();
+    }
+
+    // Wipes the keys of the open document.
+    pub fn close(&mut self) {
+        self.lock_key = None;
+        self.session_key = None;
+        self.session_salt = None;
+        self.file_key = None;
+        self.file_key_locked = None;
+        self.structured = None;
+        self.structured_locked = None;
+        self.recovered = None;
+        self.unlocked.clear();
+        self.opened.clear();
+        self.locked_sections.clear();
+        self.autosave_error = None;
+    }
+
+    pub fn unlocked_section(&mut self, armored: &str, plaintext: Zeroizing<String>) {
+        memory::lock(plaintext.as_bytes());
+        self.unlocked.retain(|(known, _)| known != armored);
+        self.unlocked.push((armored.to_string(), plaintext));
+    }
+
+    // Takes a section shown as its text in the document. Returns its index
+    // among the open ones.
+    pub fn open_section(&mut self, opened: section::Opened) -> usize {
+        self.unlocked_section(
+            opened.armored(),
+            Zeroizing::new(opened.plaintext().to_string()),
+        );
+        self.opened.push(opened);
+        self.opened.len() - 1
+    }
+
+    pub fn opened_sections(&mut self) -> &mut [section::Opened] {
+        &mut self.opened
+    }
+
+    // Writes a copy of the text in which every section that wasn't opened
+    // this session is replaced by a marker. The copy is plain text when the
+    // password is empty, and a vault file with the current parameters
+    // otherwise.
+    pub fn export_redacted(&self, text: &str, password: &str, path: &Path) -> io::Result<()> {
+        let redacted = section::redact(text, |armored| {
+            self.unlocked
+                .iter()
+                .find(|(known, _)| known == armored)
+                .map(|(_, plaintext)| plaintext.clone())
+        });
+        let data = if password.is_empty() {
+            Zeroizing::new(redacted.as_bytes().to_vec())
+        } else {
+            container::seal(
+                self.params(),
+                Some(password),
+                &[],
+                None,
+                redacted.as_bytes(),
+            )
+            .map(Zeroizing::new)
+            .map_err(|err| io::Error::new(io::ErrorKind::Other, err.to_string()))?
+        };
+        atomic::write(path, &data)
+    }
+
+    // Seals the text into the recovery file of the document. Does nothing
+    // without a session key, as the text would have to be stored in the clear.
+    pub fn autosave(&mut self, plaintext: &str) {
+        if let (Some(key), Some(path)) = (self.session_key.as_ref(), self.path.as_ref()) {
+            self.autosave_error = recovery::save(&config::recovery_dir(), key, path, plaintext)
+                .err()
+                .map(|err| err.to_string());
+        }
+    }
+
+    pub fn can_autosave(&self) -> bool {
+        self.session_key.is_some() && self.path.is_some()
+    }
+
+    pub fn take_recovered(&mut self) -> Option<Zeroizing<String>> {
+        self.recovered.take()
+    }
+
+    pub fn discard_recovery(&self) {
+        if let Some(ref path) = self.path {
+            recovery::discard(&config::recovery_dir(), path);
+        }
+    }
+
+    // Whether Save can write the document without asking for the password.
+    pub fn has_session_key(&self) -> bool {
+        match self.format {
+            Format::VAULT => {
+                self.file_key
+                    .as_ref()
+                    .map_or(false, |key| key.params() == self.params())
+                    || self
+                        .session_key
+                        .as_ref()
+                        .map_or(false, |key| key.params() == self.params())
+            }
+            Format::STRUCTURED => self.structured.as_ref().map_or(false, |key| {
+                key.params() == self.params() && key.deterministic() == self.deterministic
+            }),
+            _ => false,
+        }
+    }
+
+    // Whether the open file is sealed for recipients too, which a new key
+    // leaves out.
+    pub fn is_shared(&self) -> bool {
+        self.file_key
+            .as_ref()
+            .map_or(false, container::FileKey::has_recipient_slot)
+    }
+
+    // What a structured file at `path` is written as: its extension tells,
+    // or else it stays what the open document was.
+    pub fn structured_kind(&self, path: &Path) -> Option<structured::Kind> {
+        structured::Kind::from_path(path).or_else(|| self.structured.as_ref().map(|key| key.kind()))
+    }
+
+    pub fn format(&self) -> Format {
+        self.format
+    }
+
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    // Takes over the parameters of an opened file so Save writes it back the
+    // same way.
+    fn set_params(&mut self, params: container::Params) {
+        self.set_cipher(params.cipher);
+        self.set_hash(params.hash);
+        self.set_cost(params.cost);
+        self.set_kdf(params.kdf);
+    }
+
+    pub fn params(&self) -> container::Params {
+        container::Params {
+            cipher: self.cipher,
+            hash: self.hash,
+            cost: self.cost,
+            kdf: self.kdf,
+        }
+    }
+
     pub fn update(&mut self, updatemsg: UpdateMsg) {
         match updatemsg {
             UpdateMsg::Hash(hash) => self.set_hash(hash),
             UpdateMsg::Cipher(cipher) => self.set_cipher(cipher),
             UpdateMsg::Cost(cost) => self.set_cost(cost),
             UpdateMsg::KDF(kdf) => self.set_kdf(kdf),
+            UpdateMsg::Format(format) => self.set_format(format),
         }
     }
 
-    pub fn action(&self, action: Action) -> Option<editor::Action> {
+    pub fn action(&mut self, action: Action) -> Option<editor::Action> {
         match action {
             Action::Encrypt(key, plaintext, path) => {
-                match crypto::encrypt(
-                    self.cipher,
-                    self.hash,
-                    self.cost,
-                    self.kdf,
-                    key,
-                    &plaintext.into_bytes(),
-                ) {
-                    Ok((salt, ciphertext)) => {
-                        let mut file = match File::create(&path) {
-                            Err(err) => panic!("Error creating file {}: {}", path.display(), err),
-                            Ok(file) => file,
-                        };
-
-                        match file.write(&salt) {
-                            Err(err) => panic!("Error writing to file {}: {}", path.display(), err),
-                            Ok(file) => file,
-                        };
-
-                        match file.write(&ciphertext) {
-                            Err(err) => panic!("Error writing to file {}: {}", path.display(), err),
-                            Ok(file) => file,
-                        };
+                // with recipients configured an empty key means no password slot
+                let session_key = match self.format {
+                    Format::VAULT if key.is_empty() && !self.recipients.is_empty() => None,
+                    Format::VAULT => Some(container::PasswordKey::new(self.params(), &key)),
+                    _ => None,
+                };
+                let structured = match self.format {
+                    Format::STRUCTURED => self.structured_kind(&path).and_then(|kind| {
+                        structured::Key::new(kind, self.params(), self.deterministic, &key).ok()
+                    }),
+                    _ => None,
+                };
+                let session_key = mem::replace(&mut self.session_key, session_key);
+                let structured = mem::replace(&mut self.structured, structured);
+                // a new key makes a new file, with only the slots asked for
+                let file_key = self.file_key.take();
+                let action = self.write(&key, &plaintext, path);
+                // a file that wasn't written keeps the keys it had
+                if let Some(editor::Action::Failed(_)) = action {
+                    self.session_key = session_key;
+                    self.structured = structured;
+                    self.file_key = file_key;
+                } else if !key.is_empty() {
+                    self.lock_key = Some(lock::Key::new(&key));
+                }
+                action
+            }
+            Action::Save(plaintext) => {
+                if !self.has_session_key() {
+                    return None;
+                }
+                let path = self.path.clone()?;
+                self.write("", &plaintext, path)
+            }
+            Action::Decrypt(key, content, path) => {
+                let format = Format::detect(&content);
+                let mut envelope = None;
+                let mut issued = None;
+                let mut params = None;
+                let mut session_key = None;
+                let mut file_key = None;
+                let mut structured = None;
+                let mut history = Vec::new();
+                let (plain_utf8, signature) = if format == Format::AGE {
+                    let plain_utf8 = age::Encrypted::parse(&content)
+                        .and_then(|file| file.decrypt_with_passphrase(&key));
+                    (plain_utf8.ok(), Status::Unsigned)
+                } else if format == Format::OPENPGP {
+                    (openpgp::decrypt(&content, &key).ok(), Status::Unsigned)
+                } else if format == Format::OPENSSL {
+                    let plain_utf8 = openssl::decrypt(self.openssl, &key, &content);
+                    (plain_utf8.ok(), Status::Unsigned)
+                } else if format == Format::ANSIBLE {
+                    match ansible::decrypt(&content, &key) {
+                        Ok((plain_utf8, opened)) => {
+                            envelope = Some(opened);
+                            (Some(plain_utf8), Status::Unsigned)
+                        }
+                        Err(_) => (None, Status::Unsigned),
+                    }
+                } else if format == Format::FERNET {
+                    let token =
+                        fernet::Key::parse(&key).map(|key| fernet::decrypt(&key, &content, None));
+                    match token {
+                        Some(Ok((plain_utf8, timestamp))) => {
+                            issued = Some(timestamp);
+                            (Some(plain_utf8), Status::Unsigned)
+                        }
+                        _ => (None, Status::Unsigned),
                     }
-                    Err(_) => panic!("Error encrypting file {}", path.display()),
+                } else if format == Format::STRUCTURED {
+                    let opened = str::from_utf8(&content)
+                        .ok()
+                        .and_then(|text| structured::open(text, &key).ok());
+                    match opened {
+                        Some((plaintext, opened)) => {
+                            params = Some(opened.params());
+                            structured = Some(opened);
+                            (Some(plaintext.as_bytes().to_vec()), Status::Unsigned)
+                        }
+                        None => (None, Status::Unsigned),
+                    }
+                } else if container::is_container(&content) {
+                    match container::Container::parse(&content) {
+                        Ok(container) => {
+                            params = Some(container.params());
+                            let opened = container.open_with_password_key(&key).ok().map(
+                                |(plain_utf8, key)| {
+                                    history = container.history_with_key(&key).unwrap_or_default();
+                                    file_key = container.keep_with_key(&key).ok();
+                                    session_key = Some(key);
+                                    plain_utf8
+                                },
+                            );
+                            (
+                                opened,
+                                container
+                                    .signature_status(&TrustList::load(&config::trusted_signers())),
+                            )
+                        }
+                        Err(_) => (None, Status::Invalid),
+                    }
+                } else {
+                    // headerless files are parsed where a bug can't reach the file system
+                    let plain_utf8 =
+                        sandbox::decrypt(self.params(), &key, sandbox::Input::File(&path));
+                    (plain_utf8.ok(), Status::Unsigned)
+                };
+                let action = self.open(plain_utf8, signature, path)?;
+                self.format = format;
+                if let Some(params) = params {
+                    self.set_params(params);
+                }
+                if let Some(ref key) = structured {
+                    self.deterministic = key.deterministic();
+                }
+                self.session_key = session_key;
+                self.file_key = file_key;
+                self.structured = structured;
+                self.history = history;
+                self.recovered = match (self.session_key.as_ref(), self.path.as_ref()) {
+                    (Some(key), Some(path)) => recovery::load(&config::recovery_dir(), key, path),
+                    _ => None,
                 };
-                None
+                // ansible files are saved back as they were read
+                if let Some(envelope) = envelope {
+                    self.ansible = envelope;
+                }
+                self.issued = issued;
+                self.lock_key = Some(lock::Key::new(&key));
+                Some(action)
             }
-            Action::Decrypt(key, content) => {
-                if let Ok(plain_utf8) =
-                    crypto::decrypt(self.cipher, self.hash, self.cost, self.kdf, key, content)
-                {
-                    if let Ok(plaintext) = String::from_utf8(plain_utf8) {
-                        return Some(editor::Action::UpdateTextView(plaintext));
+            Action::DecryptWithIdentity(identity, content, path) => {
+                match container::Container::parse(&content) {
+                    Ok(container) => {
+                        let plain_utf8 = container.open_with_identity(&identity).ok();
+                        let signature = container
+                            .signature_status(&TrustList::load(&config::trusted_signers()));
+                        let action = self.open(plain_utf8, signature, path)?;
+                        self.format = Format::VAULT;
+                        self.set_params(container.params());
+                        self.history = container
+                            .history_with_identity(&identity)
+                            .unwrap_or_default();
+                        self.file_key = container.keep_with_identity(&identity).ok();
+                        Some(action)
                     }
+                    Err(_) => None,
                 }
-                None
             }
+            Action::DecryptWithAgeIdentities(identities, content, path) => {
+                let plain_utf8 = age::Encrypted::parse(&content)
+                    .and_then(|file| file.decrypt_with_identities(&identities));
+                let action = self.open(plain_utf8.ok(), Status::Unsigned, path)?;
+                self.format = Format::AGE;
+                Some(action)
+            }
+            Action::Load(content, path) => {
+                let action = self.open(Some(content), Status::Unsigned, path)?;
+                self.format = Format::INLINE;
+                Some(action)
+            }
+        }
+    }
+
+    fn write(&mut self, key: &str, plaintext: &str, path: PathBuf) -> Option<editor::Action> {
+        // the version being replaced moves into the history once the file
+        // is written
+        let mut history = self.history.clone();
+        if let Some(ref revision) = self.revision {
+            if revision.text.as_str() != plaintext {
+                history.insert(0, revision.clone());
+            }
+        }
+        history.truncate(self.keep_revisions);
+        let written = self
+            .encrypt(key, plaintext.as_bytes(), &history)
+            .map_err(|err| format!("Error encrypting {}: {}", path.display(), err))
+            .and_then(|data| {
+                atomic::write(&path, &data)
+                    .map_err(|err| format!("Error writing {}: {}", path.display(), err))
+            });
+        if let Err(message) = written {
+            return Some(editor::Action::Failed(message));
+        }
+        self.history = history;
+        // the changes are in a file now, wherever they were autosaved
+        self.discard_recovery();
+        self.autosave_error = None;
+        recovery::discard(&config::recovery_dir(), &path);
+        self.path = Some(path);
+        self.revision = Some(Revision::now(plaintext));
+        self.signature = Some(match self.signing_key {
+            Some(ref key) if self.format == Format::VAULT => {
+                TrustList::load(&config::trusted_signers()).status(key.public_key())
+            }
+            _ => Status::Unsigned,
+        });
+        Some(editor::Action::Saved)
+    }
+
+    // A vault file is sealed with the session key rather than `key`.
+    fn encrypt(
+        &self,
+        key: &str,
+        plaintext: &[u8],
+        history: &[Revision],
+    ) -> Result<Vec<u8>, container::Error> {
+        match self.format {
+            Format::VAULT => match self.file_key {
+                Some(ref file_key) if file_key.params() == self.params() => container::reseal(
+                    file_key,
+                    &self.recipients,
+                    self.signing_key.as_ref(),
+                    plaintext,
+                    history,
+                ),
+                _ => container::seal_with_key(
+                    self.params(),
+                    self.session_key.as_ref(),
+                    &self.recipients,
+                    self.signing_key.as_ref(),
+                    plaintext,
+                    history,
+                ),
+            },
+            Format::AGE => age::encrypt(Some(key), &[], plaintext),
+            Format::OPENPGP => Ok(openpgp::encrypt(key, plaintext)),
+            Format::OPENSSL => Ok(openssl::encrypt(self.openssl, key, plaintext)),
+            Format::ANSIBLE => Ok(ansible::encrypt(&self.ansible, key, plaintext)),
+            Format::FERNET => fernet::Key::parse(key)
+                .map(|key| fernet::encrypt(&key, plaintext).into_bytes())
+                .ok_or(container::Error::Format),
+            // the sections in it are sealed already
+            Format::INLINE => Ok(plaintext.to_vec()),
+            Format::STRUCTURED => match self.structured {
+                Some(ref key) => str::from_utf8(plaintext)
+                    .map_err(|_| container::Error::Format)
+                    .and_then(|text| structured::seal(key, text))
+                    .map(String::into_bytes),
+                None => Err(container::Error::Format),
+            },
         }
     }
+
+    fn open(
+        &mut self,
+        plain_utf8: Option<Vec<u8>>,
+        signature: Status,
+        path: PathBuf,
+    ) -> Option<editor::Action> {
+        // the buffer moves into the string, or is wiped if it isn't UTF-8
+        let plaintext = match String::from_utf8(plain_utf8?) {
+            Ok(plaintext) => Zeroizing::new(plaintext),
+            Err(err) => {
+                drop(Zeroizing::new(err.into_bytes()));
+                return None;
+            }
+        };
+        // saved when the file was last written
+        let time = fs::metadata(&path)
+            .and_then(|metadata| metadata.modified())
+            .map(history::seconds);
+        self.revision = Some(match time {
+            Ok(time) => Revision::new(time, &plaintext),
+            Err(_) => Revision::now(&plaintext),
+        });
+        self.history.clear();
+        self.signature = Some(signature);
+        self.path = Some(path);
+        self.ansible = Default::default();
+        self.close();
+        Some(editor::Action::UpdateTextView(plaintext))
+    }
 }
 
 pub trait Updater {
@@ -118,13 +788,68 @@ pub trait Updater {
 
 impl fmt::Display for State {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match self.kdf {
-            KDF::ARGON2 => write!(f, "{} | {} | {}", self.cipher, self.cost, self.kdf),
-            _ => write!(
+        match (self.format, self.kdf) {
+            (Format::VAULT, KDF::ARGON2) => {
+                write!(f, "{} | {} | {}", self.cipher, self.cost, self.kdf)?
+            }
+            (Format::VAULT, _) => write!(
                 f,
                 "{} | {} | {} | {}",
                 self.cipher, self.hash, self.cost, self.kdf
-            ),
+            )?,
+            (Format::OPENSSL, _) => match self.openssl.iter {
+                Some(iter) => write!(f, "OPENSSL | {} | {} iterations", self.hash, iter)?,
+                None => write!(f, "OPENSSL | {} | EVP_BytesToKey", self.hash)?,
+            },
+            (Format::ANSIBLE, _) => match self.ansible.vault_id() {
+                Some(vault_id) => write!(f, "ANSIBLE | {}", vault_id)?,
+                None => write!(f, "ANSIBLE")?,
+            },
+            (Format::STRUCTURED, _) if self.deterministic => write!(f, "STRUCTURED | AES-SIV")?,
+            (format, _) => write!(f, "{}", format)?,
+        }
+        match self.recipients.len() {
+            0 => {}
+            1 => write!(f, " | 1 recipient")?,
+            n => write!(f, " | {} recipients", n)?,
+        }
+        if self.signing_key.is_some() {
+            write!(f, " | signing")?;
+        }
+        if let Some(ref signature) = self.signature {
+            write!(f, " | {}", signature)?;
+        }
+        if let Some(issued) = self.issued {
+            write!(f, " | issued {}", fernet::format_timestamp(issued))?;
+        }
+        if self.modified {
+            write!(f, " | modified")?;
+            if !self.can_autosave() && !self.is_locked() {
+                write!(f, " | not autosaved")?;
+            }
+        }
+        if let Some(ref err) = self.autosave_error {
+            write!(f, " | autosave failed: {}", err)?;
+        }
+        if self.is_locked() {
+            write!(f, " | locked")?;
+        } else if self.lock_after.is_some()
+            && self.path.is_some()
+            && self.format != Format::INLINE
+            && !self.can_lock()
+        {
+            // the idle lock isn't armed; inline files show no secret text
+            // until a section is opened
+            write!(f, " | not lockable")?;
+        }
+        if let Some((_, due)) = self.copied {
+            let left = due.saturating_duration_since(Instant::now());
+            write!(f, " | clipboard clears in {}s", left.as_secs() + 1)?;
+        }
+        // secrets may still reach swap
+        match memory::warning() {
+            Some(warning) => write!(f, " | {}", warning),
+            None => Ok(()),
         }
     }
 }
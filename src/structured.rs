@@ -0,0 +1,886 @@
+use cryptolib::util::fixed_time_eq;
+use rustc_serialize::base64::{FromBase64, ToBase64, STANDARD};
+use std::collections::HashMap;
+use std::ops::Range;
+use std::path::Path;
+use std::str;
+use zeroize::Zeroizing;
+
+use container;
+use container::{Container, Error, Params};
+use crypto;
+use memory;
+
+// YAML, JSON, TOML and .env files in which the keys stay readable and only
+// the values are encrypted, one by one, so a diff shows which keys changed:
+//
+//   password: ENC[base64 of nonce | ciphertext | tag]
+//
+// A value is the text it had in the file, quotes and block indicators
+// included, sealed with ChaCha20-Poly1305 under a random data key and with
+// its path as associated data so it can't be moved to another key. The data
+// key is sealed in a vault container that goes first in the file, next to a
+// MAC over the whole decrypted text:
+//
+//   vault: VAULT[base64 container,base64 mac]
+//
+// With a random nonce every save changes every value. Deterministic files
+// seal values with AES-SIV instead, so a value that didn't change seals to
+// the same text again and diffs only show the edited ones. The price is
+// that anyone who sees two versions learns which values stayed the same, or
+// went back to an earlier one. Their metadata ends in ",siv".
+
+const ENC: &str = "ENC[";
+const META: &str = "VAULT[";
+const DATA_KEY_LEN: usize = 32;
+const NONCE_LEN: usize = 12;
+const SIV: &str = "siv";
+// keeps a deeply nested JSON file from overflowing the stack
+const MAX_DEPTH: usize = 128;
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Kind {
+    YAML,
+    JSON,
+    TOML,
+    ENV,
+}
+
+impl Kind {
+    pub fn from_path(path: &Path) -> Option<Kind> {
+        let name = path.file_name()?.to_str()?;
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("yaml") | Some("yml") => Some(Kind::YAML),
+            Some("json") => Some(Kind::JSON),
+            Some("toml") => Some(Kind::TOML),
+            Some("env") => Some(Kind::ENV),
+            // .env, .env.local and the like
+            _ if name.starts_with(".env") => Some(Kind::ENV),
+            _ => None,
+        }
+    }
+
+    // Where a bare ENC[...] wouldn't be a string it is quoted.
+    fn quoted(self) -> bool {
+        self == Kind::JSON || self == Kind::TOML
+    }
+
+    fn meta_prefix(self) -> &'static str {
+        match self {
+            Kind::YAML => "vault: ",
+            Kind::JSON => "\n  \"vault\": \"",
+            Kind::TOML => "vault = \"",
+            Kind::ENV => "vault=",
+        }
+    }
+}
+
+// The data key of a structured file. It is kept for the open document, so
+// Save neither asks for the password nor changes the metadata entry.
+pub struct Key {
+    kind: Kind,
+    params: Params,
+    deterministic: bool,
+    // base64 of the container the data key is sealed in
+    sealed: String,
+    data: Zeroizing<Vec<u8>>,
+}
+
+// A key whose data key was dropped when the editor locked.
+pub struct Forgotten {
+    kind: Kind,
+    deterministic: bool,
+    sealed: String,
+}
+
+impl Forgotten {
+    pub fn open(&self, password: &str) -> Option<Key> {
+        Key::open(self.kind, self.deterministic, &self.sealed, password).ok()
+    }
+}
+
+struct Keys {
+    value: Zeroizing<[u8; 32]>,
+    siv: Zeroizing<[u8; 64]>,
+    mac: Zeroizing<[u8; 32]>,
+}
+
+impl Key {
+    pub fn new(
+        kind: Kind,
+        params: Params,
+        deterministic: bool,
+        password: &str,
+    ) -> Result<Key, Error> {
+        let mut data = memory::key(DATA_KEY_LEN);
+        crypto::random_bytes(&mut data);
+        let sealed = container::seal(params, Some(password), &[], None, &data)?;
+        Ok(Key {
+            kind,
+            params,
+            deterministic,
+            sealed: sealed.to_base64(STANDARD),
+            data,
+        })
+    }
+
+    fn open(kind: Kind, deterministic: bool, sealed: &str, password: &str) -> Result<Key, Error> {
+        let container = Container::parse(&sealed.from_base64().map_err(|_| Error::Format)?)?;
+        let data_key = Zeroizing::new(container.open_with_password(password)?);
+        if data_key.len() != DATA_KEY_LEN {
+            return Err(Error::Format);
+        }
+        let mut data = memory::key(DATA_KEY_LEN);
+        data.copy_from_slice(&data_key);
+        Ok(Key {
+            kind,
+            params: container.params(),
+            deterministic,
+            sealed: sealed.to_string(),
+            data,
+        })
+    }
+
+    // Drops the data key, keeping what opens it again with the password.
+    pub fn forget(self) -> Forgotten {
+        Forgotten {
+            kind: self.kind,
+            deterministic: self.deterministic,
+            sealed: self.sealed.clone(),
+        }
+    }
+
+    pub fn kind(&self) -> Kind {
+        self.kind
+    }
+
+    pub fn params(&self) -> Params {
+        self.params
+    }
+
+    pub fn deterministic(&self) -> bool {
+        self.deterministic
+    }
+
+    fn keys(&self) -> Keys {
+        let mut okm = Zeroizing::new(vec![0; 128]);
+        crypto::hkdf_sha256(&[], &self.data, b"vault structured", &mut okm);
+        let mut keys = Keys {
+            value: Zeroizing::new([0; 32]),
+            siv: Zeroizing::new([0; 64]),
+            mac: Zeroizing::new([0; 32]),
+        };
+        keys.value.copy_from_slice(&okm[..32]);
+        keys.siv.copy_from_slice(&okm[32..96]);
+        keys.mac.copy_from_slice(&okm[96..]);
+        keys
+    }
+}
+
+pub fn is_structured(data: &[u8]) -> bool {
+    str::from_utf8(data).map_or(false, |text| split_meta(text).is_some())
+}
+
+// Fails only for text that isn't what the kind says, which for the line
+// based kinds is never.
+pub fn check(kind: Kind, text: &str) -> Result<(), Error> {
+    entries(kind, text).map(|_| ())
+}
+
+pub fn seal(key: &Key, text: &str) -> Result<String, Error> {
+    let keys = key.keys();
+    let mac = crypto::hmac_sha256(&*keys.mac, &[text.as_bytes()]);
+    let mode = if key.deterministic {
+        format!(",{}", SIV)
+    } else {
+        String::new()
+    };
+    let meta = format!(
+        "{}{},{}{}]",
+        META,
+        key.sealed,
+        mac.to_base64(STANDARD),
+        mode
+    );
+    let sealed = replace(key.kind, text, |path, raw| {
+        let data = if key.deterministic {
+            crypto::siv_seal(&*keys.siv, &[path.as_bytes()], raw.as_bytes())
+        } else {
+            let mut nonce = [0; NONCE_LEN];
+            crypto::random_bytes(&mut nonce);
+            let mut data = nonce.to_vec();
+            data.extend(crypto::seal(
+                &*keys.value,
+                &nonce,
+                path.as_bytes(),
+                raw.as_bytes(),
+            ));
+            data
+        };
+        let value = format!("{}{}]", ENC, data.to_base64(STANDARD));
+        Ok(Zeroizing::new(if key.kind.quoted() {
+            format!("\"{}\"", value)
+        } else {
+            value
+        }))
+    })?;
+
+    let prefix = key.kind.meta_prefix();
+    Ok(match key.kind {
+        Kind::JSON => {
+            let brace = sealed.find('{').ok_or(Error::Format)?;
+            let (head, rest) = sealed.split_at(brace + 1);
+            let empty = rest.trim_start().starts_with('}');
+            let end = if empty { "\n" } else { "," };
+            format!("{}{}{}\"{}{}", head, prefix, meta, end, rest)
+        }
+        Kind::TOML => format!("{}{}\"\n{}", prefix, meta, sealed.as_str()),
+        Kind::YAML | Kind::ENV => format!("{}{}\n{}", prefix, meta, sealed.as_str()),
+    })
+}
+
+pub fn open(text: &str, password: &str) -> Result<(Zeroizing<String>, Key), Error> {
+    let (kind, meta, rest) = split_meta(text).ok_or(Error::Format)?;
+    let mut fields = meta[META.len()..meta.len() - 1].split(',');
+    let (sealed, mac, deterministic) = match (fields.next(), fields.next(), fields.next()) {
+        (Some(sealed), Some(mac), None) => (sealed, mac, false),
+        (Some(sealed), Some(mac), Some(SIV)) => (sealed, mac, true),
+        _ => return Err(Error::Format),
+    };
+    if fields.next().is_some() {
+        return Err(Error::Format);
+    }
+    let key = Key::open(kind, deterministic, sealed, password)?;
+    let keys = key.keys();
+    let plaintext = replace(kind, &rest, |path, raw| {
+        let value = if kind.quoted() {
+            raw.strip_prefix('"').and_then(|raw| raw.strip_suffix('"'))
+        } else {
+            Some(raw)
+        };
+        let encoded = match value.and_then(|value| value.strip_prefix(ENC)) {
+            Some(encoded) => encoded.strip_suffix(']').ok_or(Error::Format)?,
+            // left in the clear
+            None => return Ok(Zeroizing::new(raw.to_string())),
+        };
+        let data = encoded.from_base64().map_err(|_| Error::Format)?;
+        let raw = if deterministic {
+            crypto::siv_open(&*keys.siv, &[path.as_bytes()], &data)
+        } else if data.len() >= NONCE_LEN {
+            let (nonce, sealed) = data.split_at(NONCE_LEN);
+            crypto::open(&*keys.value, nonce, path.as_bytes(), sealed)
+        } else {
+            return Err(Error::Format);
+        };
+        let raw = raw.ok_or(Error::Integrity)?;
+        String::from_utf8(raw.to_vec())
+            .map(Zeroizing::new)
+            .map_err(|_| Error::Format)
+    })?;
+    let expected = crypto::hmac_sha256(&*keys.mac, &[plaintext.as_bytes()]);
+    let mac = mac.from_base64().map_err(|_| Error::Format)?;
+    if !fixed_time_eq(&expected, &mac) {
+        return Err(Error::Integrity);
+    }
+    Ok((plaintext, key))
+}
+
+// The kind, the VAULT[...] metadata and the text without its entry.
+fn split_meta(text: &str) -> Option<(Kind, &str, String)> {
+    for &kind in &[Kind::YAML, Kind::TOML, Kind::ENV] {
+        let line = match text.strip_prefix(kind.meta_prefix()) {
+            Some(line) => line,
+            None => continue,
+        };
+        let end = line.find('\n')?;
+        let meta = if kind == Kind::TOML {
+            line[..end].strip_suffix('"')?
+        } else {
+            &line[..end]
+        };
+        if meta.starts_with(META) && meta.ends_with(']') {
+            return Some((kind, meta, line[end + 1..].to_string()));
+        }
+    }
+
+    let brace = text.find('{')?;
+    if !text[..brace].trim().is_empty() {
+        return None;
+    }
+    let line = text[brace + 1..].strip_prefix(Kind::JSON.meta_prefix())?;
+    let end = line.find('"')?;
+    let meta = &line[..end];
+    if !(meta.starts_with(META) && meta.ends_with(']')) {
+        return None;
+    }
+    let after = &line[end + 1..];
+    let rest = after
+        .strip_prefix(',')
+        .or_else(|| after.strip_prefix('\n'))
+        .unwrap_or(after);
+    Some((Kind::JSON, meta, format!("{}{}", &text[..=brace], rest)))
+}
+
+// Replaces every value in `text` with what `with` makes of its path and its
+// text.
+fn replace<F>(kind: Kind, text: &str, mut with: F) -> Result<Zeroizing<String>, Error>
+where
+    F: FnMut(&str, &str) -> Result<Zeroizing<String>, Error>,
+{
+    let mut out = Zeroizing::new(String::with_capacity(text.len()));
+    let mut pos = 0;
+    for entry in entries(kind, text)? {
+        out.push_str(&text[pos..entry.value.start]);
+        out.push_str(&with(&entry.path, &text[entry.value.clone()])?);
+        pos = entry.value.end;
+    }
+    out.push_str(&text[pos..]);
+    Ok(out)
+}
+
+struct Entry {
+    // keys and list indexes down to the value, joined by ':'
+    path: String,
+    value: Range<usize>,
+}
+
+fn entries(kind: Kind, text: &str) -> Result<Vec<Entry>, Error> {
+    match kind {
+        Kind::YAML => Ok(yaml(text)),
+        Kind::JSON => json(text),
+        Kind::TOML => Ok(toml(text)),
+        Kind::ENV => Ok(env(text)),
+    }
+}
+
+fn join(parent: &str, key: &str) -> String {
+    if parent.is_empty() {
+        key.to_string()
+    } else {
+        format!("{}:{}", parent, key)
+    }
+}
+
+fn line_end(text: &str, pos: usize) -> usize {
+    text[pos..].find('\n').map_or(text.len(), |i| pos + i)
+}
+
+// Length of the value at the start of `line`, without a trailing comment.
+// A quote only opens a string at the start of a word, so it's not
+// taken for one in an unquoted value.
+fn value_len(line: &str) -> usize {
+    let mut quote = None;
+    let mut escaped = false;
+    let mut prev = ' ';
+    let mut end = line.len();
+    for (i, c) in line.char_indices() {
+        match quote {
+            Some('"') if escaped => escaped = false,
+            Some('"') if c == '\\' => escaped = true,
+            Some(q) if c == q => quote = None,
+            Some(_) => {}
+            None if (c == '"' || c == '\'') && " \t[{,=".contains(prev) => quote = Some(c),
+            None if c == '#' && prev.is_whitespace() => {
+                end = i;
+                break;
+            }
+            None if c == '\n' => {
+                end = i;
+                break;
+            }
+            None => {}
+        }
+        prev = c;
+    }
+    line[..end].trim_end().len()
+}
+
+// The key of a `key: value` line and where its value starts.
+fn yaml_key(content: &str) -> Option<(String, usize)> {
+    let (key, rest) = match content.chars().next()? {
+        q @ '"' | q @ '\'' => {
+            let close = content[1..].find(q)? + 1;
+            (&content[1..close], close + 1)
+        }
+        '[' | '{' | '#' => return None,
+        _ => {
+            let colon = content.match_indices(':').map(|(i, _)| i).find(|&i| {
+                content[i + 1..]
+                    .chars()
+                    .next()
+                    .map_or(true, char::is_whitespace)
+            })?;
+            (content[..colon].trim_end(), colon)
+        }
+    };
+    let after = content[rest..].strip_prefix(':')?;
+    if !after.is_empty() && !after.starts_with(char::is_whitespace) {
+        return None;
+    }
+    let value = after.trim_start_matches(|c| c == ' ' || c == '\t');
+    Some((key.to_string(), content.len() - value.len()))
+}
+
+// Block mappings, block sequences and scalars, with | and > block scalars.
+// A quoted or flow value spanning lines is only encrypted up to the end of
+// its first line.
+fn yaml(text: &str) -> Vec<Entry> {
+    let mut entries = Vec::new();
+    // keys and list items the line is nested in: indent, key, list item
+    let mut parents: Vec<(usize, String, bool)> = Vec::new();
+    let mut items = HashMap::new();
+    let mut pos = 0;
+    while pos < text.len() {
+        let end = line_end(text, pos);
+        let line = &text[pos..end];
+        let mut content = line.trim_start_matches(' ');
+        let mut indent = line.len() - content.len();
+        let skip = content.trim().is_empty()
+            || content.starts_with('#')
+            || content.starts_with("---")
+            || content.starts_with("...");
+        if skip {
+            pos = end + 1;
+            continue;
+        }
+        let is_item = content == "-" || content.starts_with("- ");
+        // a sequence may sit at the indent of the key it belongs to
+        while parents.last().map_or(false, |&(i, _, item)| {
+            i > indent || (i == indent && (item || !is_item))
+        }) {
+            parents.pop();
+        }
+        let parent = |parents: &[(usize, String, bool)]| {
+            parents
+                .iter()
+                .map(|(_, key, _)| key.as_str())
+                .collect::<Vec<_>>()
+                .join(":")
+        };
+        let mut item = false;
+        while content == "-" || content.starts_with("- ") {
+            let count = items.entry(parent(&parents)).or_insert(0);
+            parents.push((indent, count.to_string(), true));
+            *count += 1;
+            let rest = content[1..].trim_start_matches(' ');
+            indent += content.len() - rest.len();
+            content = rest;
+            item = true;
+        }
+        let start = pos + line.len() - content.len();
+        let (path, value_start) = match yaml_key(content) {
+            Some((key, offset)) => (join(&parent(&parents), &key), start + offset),
+            None if item && !content.is_empty() => (parent(&parents), start),
+            None => {
+                pos = end + 1;
+                continue;
+            }
+        };
+        let len = value_len(&text[value_start..end]);
+        if len == 0 {
+            if let Some((key, _)) = yaml_key(content) {
+                parents.push((indent, key, false));
+            }
+            pos = end + 1;
+            continue;
+        }
+        let mut value_end = value_start + len;
+        if text[value_start..].starts_with(|c| c == '|' || c == '>') {
+            // the block goes on while lines are blank or indented deeper
+            let mut next = end + 1;
+            while next < text.len() {
+                let next_end = line_end(text, next);
+                let line = &text[next..next_end];
+                let deeper = line.len() - line.trim_start_matches(' ').len() > indent;
+                if line.trim().is_empty() {
+                    next = next_end + 1;
+                } else if deeper {
+                    value_end = next_end;
+                    next = next_end + 1;
+                } else {
+                    break;
+                }
+            }
+        }
+        entries.push(Entry {
+            path,
+            value: value_start..value_end,
+        });
+        pos = line_end(text, value_end) + 1;
+    }
+    entries
+}
+
+// Length of a TOML value, which runs across lines inside multi-line strings,
+// arrays and inline tables.
+fn toml_value_len(text: &str) -> usize {
+    let bytes = text.as_bytes();
+    let mut depth = 0usize;
+    let mut i = 0;
+    let mut end = text.len();
+    while i < bytes.len() {
+        let rest = &text[i..];
+        if rest.starts_with("\"\"\"") || rest.starts_with("'''") {
+            let close = rest[3..].find(&rest[..3]).map_or(rest.len(), |j| j + 6);
+            i += close;
+            continue;
+        }
+        match bytes[i] {
+            b'"' => {
+                let mut j = i + 1;
+                while j < bytes.len() && bytes[j] != b'"' && bytes[j] != b'\n' {
+                    j += if bytes[j] == b'\\' { 2 } else { 1 };
+                }
+                i = j;
+            }
+            b'\'' => {
+                i += rest[1..]
+                    .find(|c| c == '\'' || c == '\n')
+                    .map_or(rest.len() - 1, |j| j + 1)
+            }
+            b'[' | b'{' => depth += 1,
+            b']' | b'}' => depth = depth.saturating_sub(1),
+            b'#' if depth > 0 => i += rest.find('\n').map_or(rest.len(), |j| j) - 1,
+            b'#' | b'\n' if depth == 0 => {
+                end = i;
+                break;
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+    text[..end.min(text.len())].trim_end().len()
+}
+
+fn toml(text: &str) -> Vec<Entry> {
+    let mut entries = Vec::new();
+    let mut table = String::new();
+    let mut arrays = HashMap::new();
+    let mut pos = 0;
+    while pos < text.len() {
+        let end = line_end(text, pos);
+        let line = &text[pos..end];
+        let content = line.trim_start();
+        let start = pos + line.len() - content.len();
+        if content.starts_with('[') {
+            // [table] or [[array of tables]]
+            let array = content.starts_with("[[");
+            let name = content
+                .trim_start_matches('[')
+                .split(']')
+                .next()
+                .unwrap_or_default()
+                .trim()
+                .to_string();
+            table = if array {
+                let count = arrays.entry(name.clone()).or_insert(0);
+                *count += 1;
+                format!("{}:{}", name, *count - 1)
+            } else {
+                name
+            };
+            pos = end + 1;
+            continue;
+        }
+        let eq = match content.find('=') {
+            Some(eq) if !content.starts_with('#') => eq,
+            _ => {
+                pos = end + 1;
+                continue;
+            }
+        };
+        let key = content[..eq].trim();
+        let after = &content[eq + 1..];
+        let value_start = start + eq + 1 + after.len()
+            - after.trim_start_matches(|c| c == ' ' || c == '\t').len();
+        let len = toml_value_len(&text[value_start..]);
+        if len > 0 {
+            entries.push(Entry {
+                path: join(&table, key),
+                value: value_start..value_start + len,
+            });
+        }
+        pos = line_end(text, value_start + len) + 1;
+    }
+    entries
+}
+
+fn env(text: &str) -> Vec<Entry> {
+    let mut entries = Vec::new();
+    let mut pos = 0;
+    while pos < text.len() {
+        let end = line_end(text, pos);
+        let line = &text[pos..end];
+        let content = line.trim_start();
+        let content = content.strip_prefix("export ").unwrap_or(content);
+        if let Some(eq) = content.find('=').filter(|_| !content.starts_with('#')) {
+            let value_start = pos + line.len() - content.len() + eq + 1;
+            let len = value_len(&text[value_start..end]);
+            if len > 0 {
+                entries.push(Entry {
+                    path: content[..eq].trim().to_string(),
+                    value: value_start..value_start + len,
+                });
+            }
+        }
+        pos = end + 1;
+    }
+    entries
+}
+
+// Walks a JSON document, which has to be an object, for its scalar values.
+struct Json<'a> {
+    text: &'a str,
+    pos: usize,
+    entries: Vec<Entry>,
+}
+
+fn json(text: &str) -> Result<Vec<Entry>, Error> {
+    let mut json = Json {
+        text,
+        pos: 0,
+        entries: Vec::new(),
+    };
+    json.space();
+    if !json.text[json.pos..].starts_with('{') {
+        return Err(Error::Format);
+    }
+    json.value("", 0)?;
+    json.space();
+    if json.pos != text.len() {
+        return Err(Error::Format);
+    }
+    Ok(json.entries)
+}
+
+impl<'a> Json<'a> {
+    fn space(&mut self) {
+        let rest = &self.text[self.pos..];
+        self.pos += rest.len() - rest.trim_start().len();
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), Error> {
+        self.space();
+        match self.text[self.pos..].strip_prefix(c) {
+            Some(_) => {
+                self.pos += 1;
+                Ok(())
+            }
+            None => Err(Error::Format),
+        }
+    }
+
+    // Range of the string at pos, quotes included.
+    fn string(&mut self) -> Result<Range<usize>, Error> {
+        let start = self.pos;
+        let bytes = self.text.as_bytes();
+        let mut i = start + 1;
+        while i < bytes.len() && bytes[i] != b'"' {
+            i += if bytes[i] == b'\\' { 2 } else { 1 };
+        }
+        if i >= bytes.len() {
+            return Err(Error::Format);
+        }
+        self.pos = i + 1;
+        Ok(start..self.pos)
+    }
+
+    fn value(&mut self, path: &str, depth: usize) -> Result<(), Error> {
+        if depth > MAX_DEPTH {
+            return Err(Error::Format);
+        }
+        self.space();
+        let close = match self.text[self.pos..].chars().next() {
+            Some('{') => '}',
+            Some('[') => ']',
+            Some('"') => {
+                let value = self.string()?;
+                self.entries.push(Entry {
+                    path: path.to_string(),
+                    value,
+                });
+                return Ok(());
+            }
+            Some(_) => {
+                let start = self.pos;
+                let rest = &self.text[start..];
+                let len = rest
+                    .find(|c: char| c == ',' || c == ']' || c == '}' || c.is_whitespace())
+                    .unwrap_or(rest.len());
+                if len == 0 {
+                    return Err(Error::Format);
+                }
+                self.pos += len;
+                self.entries.push(Entry {
+                    path: path.to_string(),
+                    value: start..self.pos,
+                });
+                return Ok(());
+            }
+            None => return Err(Error::Format),
+        };
+        self.pos += 1;
+        self.space();
+        if self.text[self.pos..].starts_with(close) {
+            self.pos += 1;
+            return Ok(());
+        }
+        let mut index = 0;
+        loop {
+            let child = if close == '}' {
+                self.space();
+                if !self.text[self.pos..].starts_with('"') {
+                    return Err(Error::Format);
+                }
+                let key = self.string()?;
+                self.expect(':')?;
+                join(path, &self.text[key.start + 1..key.end - 1])
+            } else {
+                join(path, &index.to_string())
+            };
+            self.value(&child, depth + 1)?;
+            index += 1;
+            self.space();
+            match self.text[self.pos..].chars().next() {
+                Some(',') => self.pos += 1,
+                Some(c) if c == close => {
+                    self.pos += 1;
+                    return Ok(());
+                }
+                _ => return Err(Error::Format),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use state::cipher::Cipher;
+    use state::cost::Cost;
+    use state::hash::Hash;
+    use state::kdf::KDF;
+
+    fn key(kind: Kind, deterministic: bool) -> Key {
+        let params = Params {
+            cipher: Cipher::CHACHA20,
+            hash: Hash::SHA2_256,
+            cost: Cost::LOW,
+            kdf: KDF::PBKDF2,
+        };
+        Key::new(kind, params, deterministic, "password").unwrap()
+    }
+
+    fn paths(kind: Kind, text: &str) -> Vec<(String, String)> {
+        entries(kind, text)
+            .unwrap()
+            .into_iter()
+            .map(|entry| (entry.path, text[entry.value].to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_entries() {
+        let yaml = "db:\n  user: admin # who\n  hosts:\n  - a\n  - name: b\n    port: 5432\nnote: |\n  two\n  lines\nempty:\n";
+        let pairs = |pairs: &[(&str, &str)]| {
+            pairs
+                .iter()
+                .map(|&(path, value)| (path.to_string(), value.to_string()))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            paths(Kind::YAML, yaml),
+            pairs(&[
+                ("db:user", "admin"),
+                ("db:hosts:0", "a"),
+                ("db:hosts:1:name", "b"),
+                ("db:hosts:1:port", "5432"),
+                ("note", "|\n  two\n  lines"),
+            ])
+        );
+
+        let toml =
+            "title = \"x\" # t\n[server]\nports = [\n  1, # one\n  2,\n]\n[[user]]\nname = 'a'\n";
+        assert_eq!(
+            paths(Kind::TOML, toml),
+            pairs(&[
+                ("title", "\"x\""),
+                ("server:ports", "[\n  1, # one\n  2,\n]"),
+                ("user:0:name", "'a'"),
+            ])
+        );
+
+        let json = "{\"a\": {\"b\": [1, \"two\"]}, \"c\": null}";
+        assert_eq!(
+            paths(Kind::JSON, json),
+            pairs(&[("a:b:0", "1"), ("a:b:1", "\"two\""), ("c", "null")])
+        );
+        assert!(check(Kind::JSON, "[1]").is_err());
+        assert!(check(Kind::JSON, "{\"a\": }").is_err());
+
+        let env = "# comment\nexport TOKEN=abc\nURL=\"http://x#y\" # z\n";
+        assert_eq!(
+            paths(Kind::ENV, env),
+            pairs(&[("TOKEN", "abc"), ("URL", "\"http://x#y\"")])
+        );
+    }
+
+    #[test]
+    fn test_seal_open() {
+        let documents = [
+            (Kind::YAML, "db:\n  user: admin\n  password: \"hunter2\"\n"),
+            (
+                Kind::JSON,
+                "{\n  \"user\": \"admin\",\n  \"port\": 5432\n}\n",
+            ),
+            (Kind::JSON, "{ }"),
+            (
+                Kind::TOML,
+                "[db]\nuser = \"admin\"\npassword = \"hunter2\"\n",
+            ),
+            (Kind::ENV, "USER=admin\nPASSWORD=hunter2\n"),
+        ];
+        for &(kind, text) in &documents {
+            for &deterministic in &[false, true] {
+                let key = key(kind, deterministic);
+                let sealed = seal(&key, text).unwrap();
+                assert!(is_structured(sealed.as_bytes()));
+                assert!(!sealed.contains("hunter2"));
+                let (opened, reopened) = open(&sealed, "password").unwrap();
+                assert_eq!(opened.as_str(), text);
+                assert_eq!(reopened.kind(), kind);
+                assert_eq!(reopened.deterministic(), deterministic);
+                assert_eq!(open(&sealed, "wrong").err(), Some(Error::NoMatchingKey));
+
+                // only deterministic values seal the same way again, or a
+                // document without any
+                let forgotten = reopened.forget();
+                assert!(forgotten.open("wrong").is_none());
+                let unlocked = forgotten.open("password").unwrap();
+                let resealed = seal(&unlocked, text).unwrap();
+                assert_eq!(resealed == sealed, deterministic || text == "{ }");
+                assert_eq!(open(&resealed, "password").unwrap().0.as_str(), text);
+            }
+        }
+        assert!(!is_structured(b"user: admin\n"));
+    }
+
+    #[test]
+    fn test_tamper() {
+        let key = key(Kind::YAML, true);
+        let sealed = seal(&key, "user: admin\npassword: hunter2\n").unwrap();
+        let lines = sealed.lines().collect::<Vec<_>>();
+        let user = lines[1].trim_start_matches("user: ");
+        let password = lines[2].trim_start_matches("password: ");
+
+        // values can't trade places
+        let swapped = sealed
+            .replace(user, "USER")
+            .replace(password, user)
+            .replace("USER", password);
+        assert_eq!(open(&swapped, "password").err(), Some(Error::Integrity));
+        // nor can keys be added in the clear
+        let added = format!("{}admin: true\n", sealed);
+        assert_eq!(open(&added, "password").err(), Some(Error::Integrity));
+    }
+}